@@ -0,0 +1,47 @@
+#import "shaders/common_types.wgsl"::VoxelUniforms;
+#import "shaders/closest_point_bvh.wgsl"::{bvh_nodes, closest_point_bvh};
+
+@group(0) @binding(0)
+var<uniform> voxel_uniforms: VoxelUniforms;
+
+// Bindings 1 and 2 hold the mesh, see closest_point_bvh.wgsl
+
+// Closest-feature volumes of the slab, starting at `voxel_uniforms.slab_start`
+@group(0) @binding(3)
+var<storage, read_write> closest_points: array<vec4<f32>>;
+
+@group(0) @binding(4)
+var<storage, read_write> closest_normals: array<vec4<f32>>;
+
+@group(0) @binding(5)
+var<storage, read_write> closest_triangles: array<u32>;
+
+@group(0) @binding(6)
+var<storage, read_write> closest_barycentrics: array<vec4<f32>>;
+
+@compute @workgroup_size(8, 8, 8)
+fn main(@builtin(global_invocation_id) invocation: vec3<u32>) {
+    let size = voxel_uniforms.size;
+    // Same slab as the voxelizer dispatch
+    let id = invocation + vec3<u32>(0u, 0u, voxel_uniforms.slab_start);
+    if (any(id >= vec3<u32>(size, size, voxel_uniforms.slab_end))) {
+        return;
+    }
+    let root_aabb = bvh_nodes[0].aabb;
+    let mesh_extent = root_aabb.max - root_aabb.min;
+
+    let slab_index = id.x + id.y * size + (id.z - voxel_uniforms.slab_start) * size * size;
+
+    // Voxel center in the padded grid, as in the voxelizer
+    let padding_vec = mesh_extent * voxel_uniforms.padding_ratio;
+    let padded_min = root_aabb.min - padding_vec;
+    let padded_max = root_aabb.max + padding_vec;
+    let padded_extent = padded_max - padded_min;
+    let p_local = (vec3<f32>(id) + 0.5) / vec3<f32>(size) * padded_extent + padded_min;
+
+    let result = closest_point_bvh(p_local);
+    closest_points[slab_index] = vec4<f32>(result.point - p_local, 0.0);
+    closest_normals[slab_index] = vec4<f32>(result.normal, 0.0);
+    closest_triangles[slab_index] = result.triangle;
+    closest_barycentrics[slab_index] = vec4<f32>(result.barycentric, 0.0);
+}
@@ -0,0 +1,101 @@
+#import "shaders/distance_fns.wgsl"::{distance_to_aabb, closest_point_on_triangle};
+#import "shaders/common_types.wgsl"::{BvhNode, Triangle};
+
+// The mesh, at the same bindings in every shader that imports this module
+@group(0) @binding(1)
+var<storage> triangles: array<Triangle>;
+
+@group(0) @binding(2)
+var<storage> bvh_nodes: array<BvhNode>;
+
+const STACK_SIZE: u32 = 128;
+
+struct ClosestResult {
+    dist: f32,       // shortest distance found so far
+    point: vec3<f32>, // closest point on the surface
+    normal: vec3<f32>, // interpolated normal at closest point
+    triangle: u32,    // index of the closest triangle
+    barycentric: vec3<f32>, // barycentric coordinates of the closest point
+};
+
+/// Traverse a BVH to find the closest point on the mesh to `p_local`.
+/// Returns a `ClosestResult` with the shortest distance, the closest point,
+/// the normal interpolated from the triangle vertices and the triangle it lies on.
+///
+/// Performs early AABB culling to skip branches that cannot yield a closer point.
+fn closest_point_bvh(p_local: vec3<f32>) -> ClosestResult {
+    // Initialize best result with a very large distance
+    var best = ClosestResult(1e30, vec3<f32>(0.0), vec3<f32>(0.0), 0u, vec3<f32>(0.0));
+
+    // Stack for iterative traversal
+    var stack: array<u32, STACK_SIZE>;
+    var stack_ptr = 1u;
+    stack[0] = 0u; // start with root node
+
+    loop {
+        if (stack_ptr == 0u) {
+            break; // finished traversal
+        }
+
+        // Pop node from stack
+        stack_ptr -= 1u;
+        let node_index = stack[stack_ptr];
+        let node = bvh_nodes[node_index];
+
+        // Early AABB culling: skip this node if its closest point is farther than the current best
+        let dmin = distance_to_aabb(p_local, node.aabb.min, node.aabb.max);
+        if (dmin > best.dist) {
+            continue;
+        }
+
+        if (node.triangle_count > 0u) {
+            // Leaf node: test all triangles
+            for (var i = 0u; i < node.triangle_count; i++) {
+                let tri_idx = node.left_index + i;
+                let tri = triangles[tri_idx];
+
+                // Compute closest point on triangle
+                let result = closest_point_on_triangle(p_local, tri.a, tri.b, tri.c);
+                let dist = length(result.point - p_local);
+
+                // Update best result if closer
+                if (dist < best.dist) {
+                    best.dist = dist;
+                    best.point = result.point;
+                    best.triangle = tri_idx;
+                    best.barycentric = result.barycentric;
+                    // Interpolate normal from vertex normals using barycentric coordinates
+                    best.normal = normalize(
+                        tri.na * result.barycentric.x +
+                        tri.nb * result.barycentric.y +
+                        tri.nc * result.barycentric.z
+                    );
+                }
+            }
+        } else {
+            // Internal node: push children onto the stack
+            if (stack_ptr + 2u > STACK_SIZE) {
+                continue; // avoid stack overflow
+            }
+
+            let left = node.left_index;
+            let right = node.right_index;
+
+            // Compute minimum distances to children
+            let dleft = distance_to_aabb(p_local, bvh_nodes[left].aabb.min, bvh_nodes[left].aabb.max);
+            let dright = distance_to_aabb(p_local, bvh_nodes[right].aabb.min, bvh_nodes[right].aabb.max);
+
+            // Push the farther child first so the nearer child is processed next
+            if (dleft < dright) {
+                stack[stack_ptr] = right;
+                stack[stack_ptr + 1u] = left;
+            } else {
+                stack[stack_ptr] = left;
+                stack[stack_ptr + 1u] = right;
+            }
+            stack_ptr += 2u;
+        }
+    }
+
+    return best;
+}
@@ -33,3 +33,18 @@ struct BvhNode {
     right_index: u32,
     triangle_count: u32,
 }
+
+/// Bake parameters shared by the voxelization passes
+struct VoxelUniforms {
+    size: u32,
+    padding_ratio: f32,
+    mip_count: u32,
+    slab_start: u32, // z range of the voxels written by this dispatch
+    slab_end: u32,
+    sign_mode: u32, // one of the SIGN_MODE_* constants
+    shell_thickness: f32, // mesh-local thickness in SIGN_MODE_THIN_SHELL
+}
+
+const SIGN_MODE_PARITY: u32 = 0u;
+const SIGN_MODE_UNSIGNED: u32 = 1u;
+const SIGN_MODE_THIN_SHELL: u32 = 2u;
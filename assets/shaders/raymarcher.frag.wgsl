@@ -28,11 +28,13 @@ var voxel_sampler: sampler;
 @group(#{MATERIAL_BIND_GROUP}) @binding(102)
 var<uniform> camera: Camera;
 
+// Largest voxel edge of the grid
 @group(#{MATERIAL_BIND_GROUP}) @binding(103)
-var<uniform> grid_size: u32;
+var<uniform> voxel_size: f32;
 
+// Padded grid bounds the texture spans, in local space
 @group(#{MATERIAL_BIND_GROUP}) @binding(104)
-var<uniform> mesh_bounds: Box3;
+var<uniform> volume_bounds: Box3;
 
 @group(#{MATERIAL_BIND_GROUP}) @binding(105)
 var<uniform> local_from_world: mat4x4<f32>;
@@ -40,16 +42,29 @@ var<uniform> local_from_world: mat4x4<f32>;
 @group(#{MATERIAL_BIND_GROUP}) @binding(106)
 var<uniform> world_from_local: mat4x4<f32>;
 
+@group(#{MATERIAL_BIND_GROUP}) @binding(107)
+var<uniform> mip_count: u32;
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(108)
+var albedo_texture: texture_3d<f32>;
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(109)
+var albedo_sampler: sampler;
+
+// Maps sampled values to distances for normalized storage formats
+@group(#{MATERIAL_BIND_GROUP}) @binding(110)
+var<uniform> distance_scale: f32;
+
 const OUT_OF_BOUNDS_DIST: f32 = 1e30;
 const EPSILON: f32 = 0.5;
 
 // Sample 3D SDF using hardware interpolation and mipmaps
 fn voxel_lookup(p: vec3<f32>, mip: f32) -> f32 {
-    let extent = mesh_bounds.max - mesh_bounds.min;
-    let rel = (p - mesh_bounds.min) / extent;
+    let extent = volume_bounds.max - volume_bounds.min;
+    let rel = (p - volume_bounds.min) / extent;
 
     return select(
-        textureSampleLevel(voxel_texture, voxel_sampler, rel, mip).r,
+        textureSampleLevel(voxel_texture, voxel_sampler, rel, mip).r * distance_scale,
         OUT_OF_BOUNDS_DIST,
         any(rel < vec3<f32>(0.0)) || any(rel > vec3<f32>(1.0))
     );
@@ -62,13 +77,22 @@ fn compute_max_steps(dir: vec3<f32>, voxel_size: f32, max_dist: f32) -> u32 {
     return clamp(u32(ideal_steps), 32u, 256u);
 }
 
+// Coarsest mip level whose texels are small compared to the free space `d` around a sample.
+// Coarse levels are conservative (they only ever underestimate the distance), but they get
+// looser with every level, so a level is only used once its texels are at most half of `d`.
+fn select_mip(d: f32, voxel_size: f32) -> f32 {
+    let max_mip = f32(max(mip_count, 1u) - 1u);
+    return clamp(floor(log2(d / (2.0 * voxel_size))), 0.0, max_mip);
+}
+
 // Raymarch loop with adaptive stepping and mipmap LOD heuristic
 fn raymarch(origin: vec3<f32>, dir: vec3<f32>, voxel_size: f32, max_dist: f32) -> f32 {
     var t = 0.0;
 
     // Sample at start point
     var p = origin;
-    var d = voxel_lookup(p, 0.0);
+    var mip = 0.0;
+    var d = voxel_lookup(p, mip);
 
     let max_steps = compute_max_steps(dir, voxel_size, max_dist);
 
@@ -81,11 +105,19 @@ fn raymarch(origin: vec3<f32>, dir: vec3<f32>, voxel_size: f32, max_dist: f32) -
             // skip large empty space faster
             t += voxel_size * 2.0;
             p = origin + dir * t;
-            d = voxel_lookup(p, 0.0);
+            mip = 0.0;
+            d = voxel_lookup(p, mip);
             continue;
         }
 
         if (d < voxel_size * EPSILON) {
+            if (mip > 0.0) {
+                // Coarse levels only bound the distance from below, confirm on the finest level
+                mip = 0.0;
+                d = voxel_lookup(p, mip);
+                continue;
+            }
+
             // Hit detected – refine using binary search for precision
             var t_back = t - voxel_size;
             for (var j = 0u; j < 3u; j++) {
@@ -102,7 +134,7 @@ fn raymarch(origin: vec3<f32>, dir: vec3<f32>, voxel_size: f32, max_dist: f32) -
         }
 
         // Adaptive mip-level: coarser far away, finer near surface
-        let mip = clamp(log2(d / voxel_size), 0.0, 4.0);
+        mip = select_mip(d, voxel_size);
 
         // Adaptive step size: larger far away, smaller near surface
         let step = max(d, voxel_size * 0.5);
@@ -133,7 +165,7 @@ fn fragment(
     let dir_local = normalize((local_from_world * vec4<f32>(dir_world, 0.0)).xyz);
 
     // Intersect with the cube bounding the SDF
-    let result = ray_aabb_intersect(origin_local, dir_local, mesh_bounds);
+    let result = ray_aabb_intersect(origin_local, dir_local, volume_bounds);
     if (!result.hit) {
         // Return background if the ray misses the volume
 #ifdef PREPASS_PIPELINE
@@ -152,10 +184,6 @@ fn fragment(
 
     let start_local = origin_local + dir_local * start_t;
 
-    let extent = mesh_bounds.max - mesh_bounds.min;
-    let max_extent = max(extent.x, max(extent.y, extent.z));
-    let voxel_size = max_extent / f32(grid_size);
-
     let t = raymarch(start_local, dir_local, voxel_size, max_dist);
     if (t < 0.0) {
         // Miss inside bounds
@@ -182,6 +210,11 @@ fn fragment(
     let p_world = (world_from_local * vec4<f32>(p_local, 1.0)).xyz;
     let n_world = normalize((world_from_local * vec4<f32>(n_local, 0.0)).xyz);
 
+    // Surface colour transferred from the source material
+    let albedo_uv = (p_local - volume_bounds.min) / (volume_bounds.max - volume_bounds.min);
+    let albedo = textureSampleLevel(albedo_texture, albedo_sampler, albedo_uv, 0.0);
+    pbr_input.material.base_color *= albedo;
+
     // Update PBR input with raymarched data
     pbr_input.world_position = vec4<f32>(p_world, 1.0);
     pbr_input.world_normal = n_world;
@@ -0,0 +1,109 @@
+/// Operand volume, sampled through its inverse world transform
+struct CsgOperand {
+    local_from_world: mat4x4<f32>,
+    bounds_min: vec4<f32>, // w: world distance of one local unit
+    bounds_max: vec4<f32>,
+    size: u32,
+    offset: u32, // first voxel in `operand_voxels`
+}
+
+struct CsgUniforms {
+    bounds_min: vec4<f32>, // w: smooth union radius
+    bounds_max: vec4<f32>,
+    size: u32,
+    operation: u32,
+    operand_count: u32,
+}
+
+const OP_UNION: u32 = 0u;
+const OP_SUBTRACTION: u32 = 1u;
+const OP_INTERSECTION: u32 = 2u;
+const OP_SMOOTH_UNION: u32 = 3u;
+
+@group(0) @binding(0)
+var<storage, read_write> csg_output: array<f32>;
+
+/// Decoded distances of every operand, back to back in x-major order
+@group(0) @binding(1)
+var<storage, read_write> operand_voxels: array<f32>;
+
+@group(0) @binding(2)
+var<storage, read_write> operands: array<CsgOperand>;
+
+@group(0) @binding(3)
+var<uniform> csg_uniforms: CsgUniforms;
+
+fn operand_voxel(operand: CsgOperand, voxel: vec3<u32>) -> f32 {
+    let size = operand.size;
+    return operand_voxels[operand.offset + voxel.x + voxel.y * size + voxel.z * size * size];
+}
+
+/// World-space distance to an operand's surface. Points outside its grid are
+/// clamped onto the outermost voxel centers and the distance to the clamped
+/// point is added on top.
+fn sample_operand(operand: CsgOperand, p_world: vec3<f32>) -> f32 {
+    let p = (operand.local_from_world * vec4<f32>(p_world, 1.0)).xyz;
+    let bounds_min = operand.bounds_min.xyz;
+    let voxel_size = (operand.bounds_max.xyz - bounds_min) / f32(operand.size);
+
+    let q = clamp(p, bounds_min + 0.5 * voxel_size, operand.bounds_max.xyz - 0.5 * voxel_size);
+    let outside = length(p - q);
+
+    // Trilinear interpolation between the 8 surrounding voxel centers
+    let g = (q - bounds_min) / voxel_size - 0.5;
+    let max_voxel = vec3<u32>(operand.size - 1u);
+    let lo = min(vec3<u32>(max(floor(g), vec3<f32>(0.0))), max_voxel);
+    let hi = min(lo + 1u, max_voxel);
+    let t = clamp(g - vec3<f32>(lo), vec3<f32>(0.0), vec3<f32>(1.0));
+
+    let x00 = mix(operand_voxel(operand, vec3<u32>(lo.x, lo.y, lo.z)), operand_voxel(operand, vec3<u32>(hi.x, lo.y, lo.z)), t.x);
+    let x10 = mix(operand_voxel(operand, vec3<u32>(lo.x, hi.y, lo.z)), operand_voxel(operand, vec3<u32>(hi.x, hi.y, lo.z)), t.x);
+    let x01 = mix(operand_voxel(operand, vec3<u32>(lo.x, lo.y, hi.z)), operand_voxel(operand, vec3<u32>(hi.x, lo.y, hi.z)), t.x);
+    let x11 = mix(operand_voxel(operand, vec3<u32>(lo.x, hi.y, hi.z)), operand_voxel(operand, vec3<u32>(hi.x, hi.y, hi.z)), t.x);
+    let value = mix(mix(x00, x10, t.y), mix(x01, x11, t.y), t.z);
+
+    return (value + outside) * operand.bounds_min.w;
+}
+
+/// Polynomial smooth minimum, blending over distances up to `k` apart
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if (k <= 0.0) {
+        return min(a, b);
+    }
+    let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = csg_uniforms.size;
+    if (any(id >= vec3<u32>(size)) || csg_uniforms.operand_count == 0u) {
+        return;
+    }
+
+    let bounds_min = csg_uniforms.bounds_min.xyz;
+    let voxel_size = (csg_uniforms.bounds_max.xyz - bounds_min) / f32(size);
+    let p = bounds_min + (vec3<f32>(id) + 0.5) * voxel_size;
+
+    // Folded left to right, the first operand being the one subtracted from
+    var d = sample_operand(operands[0], p);
+    for (var i = 1u; i < csg_uniforms.operand_count; i++) {
+        let b = sample_operand(operands[i], p);
+        switch csg_uniforms.operation {
+            case OP_SUBTRACTION: {
+                d = max(d, -b);
+            }
+            case OP_INTERSECTION: {
+                d = max(d, b);
+            }
+            case OP_SMOOTH_UNION: {
+                d = smooth_min(d, b, csg_uniforms.bounds_min.w);
+            }
+            default: {
+                d = min(d, b);
+            }
+        }
+    }
+
+    csg_output[id.x + id.y * size + id.z * size * size] = d;
+}
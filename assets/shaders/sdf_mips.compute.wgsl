@@ -0,0 +1,107 @@
+#import "shaders/common_types.wgsl"::{BvhNode, VoxelUniforms};
+
+@group(0) @binding(0)
+var<storage, read_write> voxel_texture: array<f32>;
+
+/// Mip levels 1.. stored back to back, each in x-major order like `voxel_texture`.
+@group(0) @binding(1)
+var<storage, read_write> voxel_mips: array<f32>;
+
+@group(0) @binding(2)
+var<storage> bvh_nodes: array<BvhNode>;
+
+@group(0) @binding(3)
+var<uniform> voxel_uniforms: VoxelUniforms;
+
+/// Voxels per axis of mip `level`, matching the wgpu mip size convention.
+fn level_size(level: u32) -> u32 {
+    return max(voxel_uniforms.size >> level, 1u);
+}
+
+/// Offset of mip `level` (>= 1) inside `voxel_mips`.
+fn level_offset(level: u32) -> u32 {
+    var offset = 0u;
+    for (var l = 1u; l < level; l++) {
+        let s = level_size(l);
+        offset += s * s * s;
+    }
+    return offset;
+}
+
+/// Conservative reduction of the base level voxels covered by `texel` of `level`.
+///
+/// Keeps the smallest magnitude in the block and shrinks it by the largest distance
+/// between a point the coarse texel can influence under trilinear filtering and the
+/// nearest base voxel center of the block. Since the field is 1-Lipschitz, any value
+/// interpolated from such texels never exceeds the true distance. Blocks that
+/// straddle the surface collapse to zero.
+fn reduce_block(level: u32, texel: vec3<u32>, voxel_size: vec3<f32>) -> f32 {
+    let size = voxel_uniforms.size;
+    let s = level_size(level);
+
+    let block_min = (texel * size) / s;
+    let block_max = min(((texel + 1u) * size + s - 1u) / s, vec3<u32>(size));
+
+    var min_abs = 1e30;
+    var has_inside = false;
+    var has_outside = false;
+
+    for (var z = block_min.z; z < block_max.z; z++) {
+        for (var y = block_min.y; y < block_max.y; y++) {
+            for (var x = block_min.x; x < block_max.x; x++) {
+                let value = voxel_texture[x + y * size + z * size * size];
+                min_abs = min(min_abs, abs(value));
+                if (value < 0.0) {
+                    has_inside = true;
+                } else {
+                    has_outside = true;
+                }
+            }
+        }
+    }
+
+    if (has_inside && has_outside) {
+        return 0.0;
+    }
+
+    let block_voxels = f32(size) / f32(s);
+    let slack = 0.5 * (block_voxels + 1.0) * length(voxel_size);
+    let magnitude = max(min_abs - slack, 0.0);
+    return select(magnitude, -magnitude, has_inside);
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let first_size = level_size(1u);
+    if (voxel_uniforms.mip_count < 2u || any(id >= vec3<u32>(first_size))) {
+        return;
+    }
+    // The base level is complete after the last slab
+    if (voxel_uniforms.slab_end < voxel_uniforms.size) {
+        return;
+    }
+
+    // Voxel size of the base level, mirroring the padding applied by the voxelizer
+    let root_aabb = bvh_nodes[0].aabb;
+    let padded_extent = (root_aabb.max - root_aabb.min) * (1.0 + 2.0 * voxel_uniforms.padding_ratio);
+    let voxel_size = padded_extent / f32(voxel_uniforms.size);
+
+    // Every level is reduced straight from the base level, so the levels can be
+    // written independently. Invocation `id` owns texel `id / 2^(level - 1)` of each
+    // level it is aligned to.
+    for (var level = 1u; level < voxel_uniforms.mip_count; level++) {
+        let stride = 1u << (level - 1u);
+        if (any(id % vec3<u32>(stride) != vec3<u32>(0u))) {
+            break;
+        }
+
+        let texel = id / stride;
+        let s = level_size(level);
+        if (any(texel >= vec3<u32>(s))) {
+            break;
+        }
+
+        let index = level_offset(level) + texel.x + texel.y * s + texel.z * s * s;
+        voxel_mips[index] = reduce_block(level, texel, voxel_size);
+    }
+}
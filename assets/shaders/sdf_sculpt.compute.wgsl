@@ -0,0 +1,79 @@
+struct SculptUniforms {
+    region_min: vec4<f32>, // w: brush radius
+    voxel_size: vec4<f32>, // w: brush strength
+    center: vec4<f32>,
+    region_size: vec3<u32>,
+    mode: u32,
+}
+
+const MODE_ADD: u32 = 0u;
+const MODE_SUBTRACT: u32 = 1u;
+const MODE_SMOOTH: u32 = 2u;
+
+/// Decoded distances of the edited region, in x-major order
+@group(0) @binding(0)
+var<storage, read_write> region_input: array<f32>;
+
+@group(0) @binding(1)
+var<storage, read_write> region_output: array<f32>;
+
+@group(0) @binding(2)
+var<uniform> sculpt_uniforms: SculptUniforms;
+
+fn region_index(voxel: vec3<u32>) -> u32 {
+    let size = sculpt_uniforms.region_size;
+    return voxel.x + voxel.y * size.x + voxel.z * size.x * size.y;
+}
+
+/// Polynomial smooth minimum, blending over distances up to `k` apart
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if (k <= 0.0) {
+        return min(a, b);
+    }
+    let h = clamp(0.5 + 0.5 * (b - a) / k, 0.0, 1.0);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+/// Mean of the six face neighbours, clamped to the region
+fn neighbour_mean(voxel: vec3<u32>) -> f32 {
+    let max_voxel = sculpt_uniforms.region_size - 1u;
+    var sum = 0.0;
+    for (var axis = 0u; axis < 3u; axis++) {
+        var step = vec3<u32>(0u);
+        step[axis] = 1u;
+        sum += region_input[region_index(min(voxel + step, max_voxel))];
+        sum += region_input[region_index(select(voxel - step, voxel, voxel[axis] == 0u))];
+    }
+    return sum / 6.0;
+}
+
+@compute @workgroup_size(8, 8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= sculpt_uniforms.region_size)) {
+        return;
+    }
+
+    let radius = sculpt_uniforms.region_min.w;
+    let strength = sculpt_uniforms.voxel_size.w;
+    let p = sculpt_uniforms.region_min.xyz + vec3<f32>(id) * sculpt_uniforms.voxel_size.xyz;
+    let to_center = distance(p, sculpt_uniforms.center.xyz);
+    let sphere = to_center - radius;
+
+    let d = region_input[region_index(id)];
+    var result = d;
+    switch sculpt_uniforms.mode {
+        case MODE_ADD: {
+            result = smooth_min(d, sphere, strength * radius);
+        }
+        case MODE_SUBTRACT: {
+            result = -smooth_min(-d, sphere, strength * radius);
+        }
+        case MODE_SMOOTH: {
+            let falloff = 1.0 - smoothstep(0.0, radius, to_center);
+            result = mix(d, neighbour_mean(id), strength * falloff);
+        }
+        default: {}
+    }
+
+    region_output[region_index(id)] = result;
+}
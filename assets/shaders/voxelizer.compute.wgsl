@@ -1,111 +1,20 @@
-#import "shaders/distance_fns.wgsl"::{distance_to_aabb, closest_point_on_triangle};
 #import "shaders/util_fns.wgsl"::{ray_aabb_intersect, ray_triangle_intersect};
-#import "shaders/common_types.wgsl"::{Box3, BvhNode, Triangle};
-
-struct VoxelUniforms {
-    size: u32,
-}
+#import "shaders/common_types.wgsl"::{
+    Box3, VoxelUniforms, SIGN_MODE_THIN_SHELL, SIGN_MODE_UNSIGNED
+};
+#import "shaders/closest_point_bvh.wgsl"::{STACK_SIZE, bvh_nodes, closest_point_bvh, triangles};
 
 @group(0) @binding(0)
 var<storage, read_write> voxel_texture: array<f32>;
 
-@group(0) @binding(1)
-var<storage> triangles: array<Triangle>;
-
-@group(0) @binding(2)
-var<storage> bvh_nodes: array<BvhNode>;
+// Bindings 1 and 2 hold the mesh, see closest_point_bvh.wgsl
 
 @group(0) @binding(3)
 var<uniform> voxel_uniforms: VoxelUniforms;
 
-const STACK_SIZE: u32 = 128;
-const PADDING_RATIO: f32 = 0.05;
-
-struct ClosestResult {
-    dist: f32,       // shortest distance found so far
-    point: vec3<f32>, // closest point on the surface
-    normal: vec3<f32>, // interpolated normal at closest point
-};
-
-/// Traverse a BVH to find the closest point on the mesh to `p_local`.
-/// Returns a `ClosestResult` with the shortest distance, the closest point,
-/// and the normal interpolated from the triangle vertices.
-///
-/// Performs early AABB culling to skip branches that cannot yield a closer point.
-fn closest_point_bvh(p_local: vec3<f32>) -> ClosestResult {
-    // Initialize best result with a very large distance
-    var best = ClosestResult(1e30, vec3<f32>(0.0), vec3<f32>(0.0));
-
-    // Stack for iterative traversal
-    var stack: array<u32, STACK_SIZE>;
-    var stack_ptr = 1u;
-    stack[0] = 0u; // start with root node
-
-    loop {
-        if (stack_ptr == 0u) {
-            break; // finished traversal
-        }
-
-        // Pop node from stack
-        stack_ptr -= 1u;
-        let node_index = stack[stack_ptr];
-        let node = bvh_nodes[node_index];
-
-        // Early AABB culling: skip this node if its closest point is farther than the current best
-        let dmin = distance_to_aabb(p_local, node.aabb.min, node.aabb.max);
-        if (dmin > best.dist) {
-            continue;
-        }
-
-        if (node.triangle_count > 0u) {
-            // Leaf node: test all triangles
-            for (var i = 0u; i < node.triangle_count; i++) {
-                let tri_idx = node.left_index + i;
-                let tri = triangles[tri_idx];
-
-                // Compute closest point on triangle
-                let result = closest_point_on_triangle(p_local, tri.a, tri.b, tri.c);
-                let dist = length(result.point - p_local);
-
-                // Update best result if closer
-                if (dist < best.dist) {
-                    best.dist = dist;
-                    best.point = result.point;
-                    // Interpolate normal from vertex normals using barycentric coordinates
-                    best.normal = normalize(
-                        tri.na * result.barycentric.x +
-                        tri.nb * result.barycentric.y +
-                        tri.nc * result.barycentric.z
-                    );
-                }
-            }
-        } else {
-            // Internal node: push children onto the stack
-            if (stack_ptr + 2u > STACK_SIZE) {
-                continue; // avoid stack overflow
-            }
-
-            let left = node.left_index;
-            let right = node.right_index;
-
-            // Compute minimum distances to children
-            let dleft = distance_to_aabb(p_local, bvh_nodes[left].aabb.min, bvh_nodes[left].aabb.max);
-            let dright = distance_to_aabb(p_local, bvh_nodes[right].aabb.min, bvh_nodes[right].aabb.max);
-
-            // Push the farther child first so the nearer child is processed next
-            if (dleft < dright) {
-                stack[stack_ptr] = right;
-                stack[stack_ptr + 1u] = left;
-            } else {
-                stack[stack_ptr] = left;
-                stack[stack_ptr + 1u] = right;
-            }
-            stack_ptr += 2u;
-        }
-    }
-
-    return best;
-}
+// Copy of the slab in `voxel_texture`, starting at `voxel_uniforms.slab_start`
+@group(0) @binding(4)
+var<storage, read_write> slab_voxels: array<f32>;
 
 /// Raycast-based inside/outside test using the "odd-even rule":
 /// Cast a ray in the +X direction from point `p`.
@@ -158,9 +67,11 @@ fn is_inside(p: vec3<f32>) -> bool {
 }
 
 @compute @workgroup_size(8, 8, 8)
-fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+fn main(@builtin(global_invocation_id) invocation: vec3<u32>) {
     let size = voxel_uniforms.size;
-    if (any(id >= vec3<u32>(size, size, size))) {
+    // A dispatch covers one slab, the rest of the grid is written by other dispatches
+    let id = invocation + vec3<u32>(0u, 0u, voxel_uniforms.slab_start);
+    if (any(id >= vec3<u32>(size, size, voxel_uniforms.slab_end))) {
         return;
     }
     let root_aabb = bvh_nodes[0].aabb;
@@ -169,7 +80,7 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     let voxel_index = id.x + id.y * size + id.z * size * size;
 
     // Add padding around the mesh
-    let padding_vec = mesh_extent * PADDING_RATIO;
+    let padding_vec = mesh_extent * voxel_uniforms.padding_ratio;
     let padded_min = root_aabb.min - padding_vec;
     let padded_max = root_aabb.max + padding_vec;
     let padded_extent = padded_max - padded_min;
@@ -181,9 +92,19 @@ fn main(@builtin(global_invocation_id) id: vec3<u32>) {
     
     // Get closest point & normal via BVH
     let result = closest_point_bvh(p_local);
-    let inside = is_inside(p_local);
-    
-    let si = select(1.0, -1.0, inside);
-    let value = result.dist * si;
+
+    var value = result.dist;
+    switch voxel_uniforms.sign_mode {
+        case SIGN_MODE_UNSIGNED: {
+            // Open surfaces have no inside, skip the parity test
+        }
+        case SIGN_MODE_THIN_SHELL: {
+            value = result.dist - 0.5 * voxel_uniforms.shell_thickness;
+        }
+        default: {
+            value = select(result.dist, -result.dist, is_inside(p_local));
+        }
+    }
     voxel_texture[voxel_index] = value;
+    slab_voxels[voxel_index - voxel_uniforms.slab_start * size * size] = value;
 }
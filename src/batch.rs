@@ -0,0 +1,507 @@
+use crate::{
+    bvh::{BvhData, BvhTargetMarker},
+    headless::{HeadlessExit, mesh_load_error},
+    voxelization::{
+        VoxelizationCompleted, VoxelizationData, VoxelizationFailed, VoxelizationSettings,
+        VoxelizeTargetMarker,
+        quality::SdfQualityReport,
+        volume_io::{ExportBakedVolume, SignConvention, VolumeExported, VolumeFormat},
+    },
+};
+use bevy::{platform::time::Instant, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+};
+
+/// Models to bake in one run, read from a JSON file. Relative paths are
+/// relative to the manifest.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BatchManifest {
+    /// Directory of outputs without an explicit path. Defaults to the
+    /// directory of the manifest.
+    pub output_dir: Option<PathBuf>,
+    /// Settings of models that do not set them themselves.
+    pub defaults: BatchModelSettings,
+    pub models: Vec<BatchModel>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchModel {
+    pub mesh: PathBuf,
+    /// Output path. Defaults to the mesh file name with the extension of the
+    /// format, in the output directory.
+    pub output: Option<PathBuf>,
+    #[serde(flatten)]
+    pub settings: BatchModelSettings,
+}
+
+/// Per-model bake settings. Unset ones fall back to the manifest defaults,
+/// then to [`VoxelizationSettings::default`] and the format defaults.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct BatchModelSettings {
+    pub resolution: Option<u32>,
+    pub padding: Option<f32>,
+    pub format: Option<VolumeFormat>,
+    pub sign: Option<SignConvention>,
+}
+
+impl BatchModelSettings {
+    fn or(self, defaults: Self) -> Self {
+        Self {
+            resolution: self.resolution.or(defaults.resolution),
+            padding: self.padding.or(defaults.padding),
+            format: self.format.or(defaults.format),
+            sign: self.sign.or(defaults.sign),
+        }
+    }
+}
+
+impl BatchManifest {
+    /// Reads a manifest file, or lists every OBJ file of a directory with
+    /// default settings.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        if path.is_dir() {
+            let mut meshes = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            meshes.retain(|mesh| mesh.extension().is_some_and(|e| e == "obj"));
+            meshes.sort();
+
+            return Ok(Self {
+                models: meshes
+                    .into_iter()
+                    .map(|mesh| BatchModel {
+                        mesh,
+                        output: None,
+                        settings: default(),
+                    })
+                    .collect(),
+                ..default()
+            });
+        }
+
+        let mut manifest: Self = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        for model in &mut manifest.models {
+            model.mesh = base.join(&model.mesh);
+            model.output = model.output.as_ref().map(|output| base.join(output));
+        }
+        manifest.output_dir = manifest.output_dir.map(|dir| base.join(dir));
+        Ok(manifest)
+    }
+
+    /// Resolves the settings and outputs of all models and hashes their
+    /// inputs, a few models per thread. Models whose mesh cannot be read
+    /// become failed jobs.
+    pub fn into_jobs(self, output_dir: &Path) -> Vec<BatchJob> {
+        let output_dir = self.output_dir.as_deref().unwrap_or(output_dir);
+        let mut jobs = self
+            .models
+            .into_iter()
+            .map(|model| {
+                let settings = model.settings.or(self.defaults);
+                let format = settings.format.unwrap_or_default();
+                let output = model.output.unwrap_or_else(|| {
+                    let stem = model.mesh.file_stem().unwrap_or_default().to_string_lossy();
+                    output_dir.join(format!("{stem}.{format}"))
+                });
+
+                let defaults = VoxelizationSettings::default();
+                let voxelization = VoxelizationSettings {
+                    resolution: settings.resolution.unwrap_or(defaults.resolution),
+                    padding_ratio: settings.padding.unwrap_or(defaults.padding_ratio),
+                    ..defaults
+                };
+
+                let (mesh, error) = match fs::canonicalize(&model.mesh) {
+                    Ok(mesh) => (mesh, None),
+                    Err(e) => (model.mesh, Some(format!("failed to resolve mesh: {e}"))),
+                };
+
+                BatchJob {
+                    mesh,
+                    output,
+                    settings: voxelization,
+                    format,
+                    sign_convention: settings.sign.unwrap_or_default(),
+                    hash: String::new(),
+                    error,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = jobs.len().div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            let handles = jobs
+                .chunks_mut(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        for job in chunk.iter_mut().filter(|job| job.error.is_none()) {
+                            match job.content_hash() {
+                                Ok(hash) => job.hash = hash,
+                                Err(e) => job.error = Some(format!("failed to read mesh: {e}")),
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for handle in handles {
+                handle.join().expect("hashing thread panicked");
+            }
+        });
+
+        jobs
+    }
+}
+
+/// A model with everything needed to bake it.
+#[derive(Debug, Clone)]
+pub struct BatchJob {
+    pub mesh: PathBuf,
+    pub output: PathBuf,
+    pub settings: VoxelizationSettings,
+    pub format: VolumeFormat,
+    pub sign_convention: SignConvention,
+    /// BLAKE3 hash of the mesh file and everything that affects the output.
+    pub hash: String,
+    /// Why the job cannot be baked, if its mesh could not be read.
+    pub error: Option<String>,
+}
+
+impl BatchJob {
+    fn content_hash(&self) -> io::Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&fs::read(&self.mesh)?);
+        hasher.update(
+            format!(
+                "distill {} {:?} {} {}",
+                env!("CARGO_PKG_VERSION"),
+                self.settings,
+                self.format,
+                self.sign_convention
+            )
+            .as_bytes(),
+        );
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    /// The hash of the inputs of the output is stored next to it.
+    fn hash_path(&self) -> PathBuf {
+        let mut path = self.output.clone().into_os_string();
+        path.push(".blake3");
+        path.into()
+    }
+
+    /// Whether the output exists and was baked from the same inputs.
+    pub fn is_up_to_date(&self) -> bool {
+        self.output.exists()
+            && fs::read_to_string(self.hash_path()).is_ok_and(|hash| hash.trim() == self.hash)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum BatchStatus {
+    Pending,
+    Baked,
+    /// Skipped because the output matches the hash of its inputs.
+    UpToDate,
+    Failed,
+}
+
+/// Outcome of one model, as written to the report.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReportEntry {
+    pub mesh: PathBuf,
+    pub output: PathBuf,
+    pub status: BatchStatus,
+    pub error: Option<String>,
+    pub hash: String,
+    pub resolution: u32,
+    pub triangles: Option<usize>,
+    pub bake_seconds: Option<f64>,
+    pub min_distance: Option<f32>,
+    pub max_distance: Option<f32>,
+    pub sign_inconsistencies: Option<usize>,
+    pub eikonal_rms: Option<f32>,
+    pub crossing_ratio: Option<f32>,
+}
+
+impl BatchReportEntry {
+    const CSV_HEADER: &str = "mesh,output,status,error,hash,resolution,triangles,bake_seconds,\
+                              min_distance,max_distance,sign_inconsistencies,eikonal_rms,\
+                              crossing_ratio";
+
+    fn csv_row(&self) -> String {
+        fn field(value: Option<impl ToString>) -> String {
+            value.map(|v| v.to_string()).unwrap_or_default()
+        }
+
+        [
+            self.mesh.display().to_string(),
+            self.output.display().to_string(),
+            self.status.to_string(),
+            field(self.error.as_ref()),
+            self.hash.clone(),
+            self.resolution.to_string(),
+            field(self.triangles),
+            field(self.bake_seconds),
+            field(self.min_distance),
+            field(self.max_distance),
+            field(self.sign_inconsistencies),
+            field(self.eikonal_rms),
+            field(self.crossing_ratio),
+        ]
+        .map(|value| match value.contains([',', '"', '\n']) {
+            true => format!("\"{}\"", value.replace('"', "\"\"")),
+            false => value,
+        })
+        .join(",")
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReport {
+    pub total_seconds: f64,
+    pub entries: Vec<BatchReportEntry>,
+}
+
+impl BatchReport {
+    /// Writes CSV for `.csv` paths and JSON otherwise.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if path.extension().is_some_and(|e| e == "csv") {
+            let rows = self.entries.iter().map(BatchReportEntry::csv_row);
+            let csv = std::iter::once(BatchReportEntry::CSV_HEADER.to_string())
+                .chain(rows)
+                .collect::<Vec<_>>()
+                .join("\n");
+            fs::write(path, csv + "\n")
+        } else {
+            let json = serde_json::to_string_pretty(self)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fs::write(path, json)
+        }
+    }
+}
+
+/// State of a batch bake in a headless app.
+#[derive(Debug, Resource)]
+pub struct BatchRun {
+    jobs: Vec<BatchJob>,
+    entries: Vec<BatchReportEntry>,
+    report: PathBuf,
+    force: bool,
+    started: Instant,
+}
+
+impl BatchRun {
+    /// Rebakes up-to-date outputs too if `force` is set.
+    pub fn new(jobs: Vec<BatchJob>, report: PathBuf, force: bool) -> Self {
+        let entries = jobs
+            .iter()
+            .map(|job| BatchReportEntry {
+                mesh: job.mesh.clone(),
+                output: job.output.clone(),
+                status: match job.error {
+                    Some(_) => BatchStatus::Failed,
+                    None => BatchStatus::Pending,
+                },
+                error: job.error.clone(),
+                hash: job.hash.clone(),
+                resolution: job.settings.resolution,
+                triangles: None,
+                bake_seconds: None,
+                min_distance: None,
+                max_distance: None,
+                sign_inconsistencies: None,
+                eikonal_rms: None,
+                crossing_ratio: None,
+            })
+            .collect();
+
+        Self {
+            jobs,
+            entries,
+            report,
+            force,
+            started: Instant::now(),
+        }
+    }
+
+    fn fail(&mut self, index: usize, error: impl ToString) {
+        let entry = &mut self.entries[index];
+        error!("Baking {:?} failed: {}", entry.mesh, error.to_string());
+        entry.status = BatchStatus::Failed;
+        entry.error = Some(error.to_string());
+    }
+}
+
+/// Index of the [`BatchJob`] an entity bakes.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct BatchJobIndex(pub usize);
+
+/// Spawns a bake target for every job whose output is out of date. All of
+/// them load and build their BVH concurrently, the voxelizer then bakes them
+/// one after another.
+pub fn spawn_batch_jobs(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut run: ResMut<BatchRun>,
+) {
+    let run = &mut *run;
+    for (index, job) in run.jobs.iter().enumerate() {
+        if let Some(error) = &job.error {
+            error!("Baking {:?} failed: {error}", job.mesh);
+            continue;
+        }
+        if !run.force && job.is_up_to_date() {
+            info!("{:?} is up to date.", job.output);
+            run.entries[index].status = BatchStatus::UpToDate;
+            continue;
+        }
+
+        info!(
+            "Baking {:?} into {:?} as {}.",
+            job.mesh, job.output, job.format
+        );
+        commands.spawn((
+            VoxelizeTargetMarker,
+            BvhTargetMarker,
+            BatchJobIndex(index),
+            job.settings,
+            Mesh3d(asset_server.load(job.mesh.clone())),
+            ExportBakedVolume {
+                path: job.output.clone(),
+                format: job.format,
+                sign_convention: job.sign_convention,
+            },
+        ));
+    }
+}
+
+/// Records the outcome of every job and writes the report once all are done.
+#[allow(clippy::too_many_arguments)]
+pub fn track_batch_jobs(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+    mut run: ResMut<BatchRun>,
+    jobs: Query<(
+        Entity,
+        &BatchJobIndex,
+        &Mesh3d,
+        Option<&BvhData>,
+        Option<&VoxelizationData>,
+        Option<&SdfQualityReport>,
+    )>,
+    mut completed: MessageReader<VoxelizationCompleted>,
+    mut failed: MessageReader<VoxelizationFailed>,
+    mut exported: MessageReader<VolumeExported>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    for (entity, &BatchJobIndex(index), mesh, ..) in jobs.iter() {
+        let waited = run.started.elapsed();
+        if let Some(error) = mesh_load_error(&asset_server, mesh, waited) {
+            run.fail(index, error);
+            commands.entity(entity).despawn();
+        }
+    }
+
+    for completed in completed.read() {
+        let Ok((_, &BatchJobIndex(index), _, bvh_data, voxel_data, _)) = jobs.get(completed.entity)
+        else {
+            continue;
+        };
+
+        let voxels = voxel_data
+            .and_then(VoxelizationData::data)
+            .and_then(|data| data.read_voxels(&images));
+        let entry = &mut run.entries[index];
+        entry.bake_seconds = Some(completed.duration.as_secs_f64());
+        entry.triangles = bvh_data.map(|bvh_data| bvh_data.triangles.len());
+        if let Some(voxels) = voxels {
+            entry.min_distance = voxels.iter().copied().reduce(f32::min);
+            entry.max_distance = voxels.iter().copied().reduce(f32::max);
+        }
+    }
+
+    for failed in failed.read() {
+        if let Ok((_, &BatchJobIndex(index), ..)) = jobs.get(failed.entity) {
+            run.fail(index, &failed.error);
+            commands.entity(failed.entity).despawn();
+        }
+    }
+
+    for exported in exported.read() {
+        let Ok((_, &BatchJobIndex(index), .., quality)) = jobs.get(exported.entity) else {
+            continue;
+        };
+        commands.entity(exported.entity).despawn();
+
+        if let Some(quality) = quality {
+            let entry = &mut run.entries[index];
+            entry.sign_inconsistencies = Some(quality.sign_inconsistencies);
+            entry.eikonal_rms = Some(quality.eikonal_rms);
+            entry.crossing_ratio = Some(quality.crossing_ratio);
+        }
+
+        if let Some(error) = &exported.error {
+            run.fail(index, error);
+            continue;
+        }
+
+        let job = &run.jobs[index];
+        match fs::write(job.hash_path(), &job.hash) {
+            Ok(()) => run.entries[index].status = BatchStatus::Baked,
+            Err(e) => run.fail(index, format!("failed to write hash: {e}")),
+        }
+    }
+
+    if run
+        .entries
+        .iter()
+        .any(|entry| entry.status == BatchStatus::Pending)
+    {
+        return;
+    }
+
+    let report = BatchReport {
+        total_seconds: run.started.elapsed().as_secs_f64(),
+        entries: run.entries.clone(),
+    };
+    let failures = report
+        .entries
+        .iter()
+        .filter(|entry| entry.status == BatchStatus::Failed)
+        .count();
+    info!(
+        models = report.entries.len(),
+        failures,
+        total_seconds = report.total_seconds,
+        "Batch finished. Writing report to {:?}.",
+        run.report
+    );
+
+    exit.write(match report.write(&run.report) {
+        Err(e) => {
+            error!("Failed to write report {:?}: {e}", run.report);
+            HeadlessExit::Write.into()
+        }
+        Ok(()) if failures > 0 => HeadlessExit::Bake.into(),
+        Ok(()) => AppExit::Success,
+    });
+}
@@ -0,0 +1,21 @@
+//! Bakes a mesh into a distance field file without opening a window, see
+//! [`HeadlessCommand::parse_bake`] for the arguments. `distill-bake batch`
+//! bakes many, see [`HeadlessCommand::parse_batch`].
+use bevy::prelude::*;
+use distill::headless::{HeadlessCommand, HeadlessExit};
+
+fn main() -> AppExit {
+    let mut args = std::env::args().skip(1).peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some("batch") => HeadlessCommand::parse_batch(args.skip(1)),
+        _ => HeadlessCommand::parse_bake(args),
+    };
+
+    match command {
+        Ok(command) => command.run(),
+        Err(usage) => {
+            eprintln!("{usage}");
+            HeadlessExit::Usage.into()
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use crate::gpu_types::{GpuBvhNode, GpuTriangle};
-use bevy::prelude::*;
+use bevy::{mesh::PrimitiveTopology, prelude::*};
+use std::fmt;
 
 mod bevy_mesh_integration;
 mod bvh_builder;
+mod closest_point;
+
+pub(crate) use closest_point::closest_point_on_triangle;
 
 pub struct BvhPlugin;
 
@@ -16,15 +20,51 @@ impl Plugin for BvhPlugin {
 pub struct BvhData {
     pub nodes: Vec<GpuBvhNode>,
     pub triangles: Vec<GpuTriangle>,
+    /// Texture coordinates of each triangle's vertices, parallel to `triangles`.
+    /// `None` if the mesh has no UVs.
+    pub uvs: Option<Vec<[Vec2; 3]>>,
 }
 
 #[derive(Debug, Clone, Component)]
 pub struct BvhTargetMarker;
 
+/// Why a BVH could not be built from a mesh.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BvhError {
+    UnsupportedTopology(PrimitiveTopology),
+    /// A required vertex attribute is missing or not `Float32x3`.
+    InvalidAttribute(&'static str),
+    NoTriangles,
+}
+
+impl fmt::Display for BvhError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedTopology(topology) => {
+                write!(f, "only triangle lists are supported, got {topology:?}")
+            }
+            Self::InvalidAttribute(name) => {
+                write!(f, "attribute {name} is missing or not Float32x3")
+            }
+            Self::NoTriangles => write!(f, "the mesh has no triangles"),
+        }
+    }
+}
+
+impl std::error::Error for BvhError {}
+
+/// Added instead of [`BvhData`] when the mesh of a target cannot be turned
+/// into a BVH. Removed by a re-bake.
+#[derive(Debug, Clone, Component)]
+pub struct BvhFailed(pub BvhError);
+
 #[allow(clippy::type_complexity)]
 fn bvh_system(
     mut commands: Commands,
-    mesh_handles: Query<(Entity, &Mesh3d), (With<BvhTargetMarker>, Without<BvhData>)>,
+    mesh_handles: Query<
+        (Entity, &Mesh3d),
+        (With<BvhTargetMarker>, Without<BvhData>, Without<BvhFailed>),
+    >,
     meshes: Res<Assets<Mesh>>,
 ) {
     for (entity, mesh_handle) in mesh_handles.iter() {
@@ -34,12 +74,27 @@ fn bvh_system(
             continue;
         };
 
-        let (nodes, triangles) = mesh.build_bvh(4);
-        commands.entity(entity).insert(BvhData { nodes, triangles });
+        let (nodes, triangles) = match mesh.build_bvh(4) {
+            Ok(bvh) => bvh,
+            Err(e) => {
+                error!("Cannot build a BVH for entity {entity:?}: {e}.");
+                commands.entity(entity).insert(BvhFailed(e));
+                continue;
+            }
+        };
+        let uvs = mesh.triangle_uvs();
+        commands.entity(entity).insert(BvhData {
+            nodes,
+            triangles,
+            uvs,
+        });
         info!("BVH computed for entity {:?}", entity);
     }
 }
 
 pub trait MeshBvh {
-    fn build_bvh(&self, leaf_size: usize) -> (Vec<GpuBvhNode>, Vec<GpuTriangle>);
+    fn build_bvh(&self, leaf_size: usize) -> Result<(Vec<GpuBvhNode>, Vec<GpuTriangle>), BvhError>;
+
+    /// Per-triangle UVs in the same order as the triangles of [`MeshBvh::build_bvh`].
+    fn triangle_uvs(&self) -> Option<Vec<[Vec2; 3]>>;
 }
@@ -1,4 +1,4 @@
-use super::{MeshBvh, bvh_builder};
+use super::{BvhError, MeshBvh, bvh_builder};
 use crate::gpu_types::{GpuBvhNode, GpuTriangle, GpuVec3};
 use bevy::{
     mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
@@ -6,29 +6,25 @@ use bevy::{
 };
 
 impl MeshBvh for Mesh {
-    fn build_bvh(&self, leaf_size: usize) -> (Vec<GpuBvhNode>, Vec<GpuTriangle>) {
-        assert_eq!(
-            self.primitive_topology(),
-            PrimitiveTopology::TriangleList,
-            "Only triangle meshes are supported for BVH building."
-        );
-
-        let positions = match self.attribute(Mesh::ATTRIBUTE_POSITION).unwrap() {
-            VertexAttributeValues::Float32x3(v) => v.clone(),
-            _ => panic!("Unexpected vertex format for positions"),
-        };
+    fn build_bvh(&self, leaf_size: usize) -> Result<(Vec<GpuBvhNode>, Vec<GpuTriangle>), BvhError> {
+        if self.primitive_topology() != PrimitiveTopology::TriangleList {
+            return Err(BvhError::UnsupportedTopology(self.primitive_topology()));
+        }
 
-        let indices: Vec<usize> = match self.indices() {
-            Some(Indices::U16(i)) => i.iter().map(|&v| v as usize).collect(),
-            Some(Indices::U32(i)) => i.iter().map(|&v| v as usize).collect(),
-            None => (0..positions.len()).collect(),
+        let positions = match self.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(v)) => v.clone(),
+            _ => return Err(BvhError::InvalidAttribute(Mesh::ATTRIBUTE_POSITION.name)),
         };
 
+        let indices = triangle_indices(self, positions.len());
+        if indices.len() < 3 {
+            return Err(BvhError::NoTriangles);
+        }
+
+        // Recompute normals before building the BVH if this fails
         let normals = match self.attribute(Mesh::ATTRIBUTE_NORMAL) {
             Some(VertexAttributeValues::Float32x3(v)) => v.clone(),
-            _ => {
-                panic!("Mesh is missing normals — try recomputing them before building the BVH");
-            }
+            _ => return Err(BvhError::InvalidAttribute(Mesh::ATTRIBUTE_NORMAL.name)),
         };
 
         let mut tris = Vec::with_capacity(indices.len() / 3);
@@ -49,6 +45,29 @@ impl MeshBvh for Mesh {
         }
 
         let nodes = bvh_builder::build_bvh(&tris, leaf_size);
-        (nodes, tris)
+        Ok((nodes, tris))
+    }
+
+    fn triangle_uvs(&self) -> Option<Vec<[Vec2; 3]>> {
+        let uvs = match self.attribute(Mesh::ATTRIBUTE_UV_0)? {
+            VertexAttributeValues::Float32x2(v) => v,
+            _ => return None,
+        };
+
+        let indices = triangle_indices(self, uvs.len());
+        Some(
+            indices
+                .chunks_exact(3)
+                .map(|tri| [tri[0], tri[1], tri[2]].map(|i| Vec2::from_array(uvs[i])))
+                .collect(),
+        )
+    }
+}
+
+fn triangle_indices(mesh: &Mesh, vertex_count: usize) -> Vec<usize> {
+    match mesh.indices() {
+        Some(Indices::U16(i)) => i.iter().map(|&v| v as usize).collect(),
+        Some(Indices::U32(i)) => i.iter().map(|&v| v as usize).collect(),
+        None => (0..vertex_count).collect(),
     }
 }
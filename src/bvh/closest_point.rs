@@ -0,0 +1,102 @@
+use super::BvhData;
+use crate::gpu_types::{GpuBox3, GpuTriangle};
+use bevy::prelude::*;
+
+impl BvhData {
+    /// Unsigned distance from `point` to the closest triangle, or infinity if
+    /// there are none.
+    pub fn closest_distance(&self, point: Vec3) -> f32 {
+        if self.nodes.is_empty() {
+            return f32::INFINITY;
+        }
+
+        let mut best = f32::INFINITY;
+        let mut stack = vec![0u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if box_distance_squared(node.aabb(), point) >= best {
+                continue;
+            }
+
+            if node.triangle_count() > 0 || node.right_index() == u32::MAX {
+                let start = node.left_index() as usize;
+                for triangle in &self.triangles[start..start + node.triangle_count() as usize] {
+                    best = best
+                        .min(closest_point_on_triangle(triangle, point).distance_squared(point));
+                }
+                continue;
+            }
+
+            // Visit the closer child first so the other one is more likely pruned.
+            let (left, right) = (node.left_index(), node.right_index());
+            let left_distance = box_distance_squared(self.nodes[left as usize].aabb(), point);
+            let right_distance = box_distance_squared(self.nodes[right as usize].aabb(), point);
+            if left_distance < right_distance {
+                stack.extend([right, left]);
+            } else {
+                stack.extend([left, right]);
+            }
+        }
+
+        best.sqrt()
+    }
+}
+
+fn box_distance_squared(aabb: &GpuBox3, point: Vec3) -> f32 {
+    let (min, max) = (Vec3::from(*aabb.min()), Vec3::from(*aabb.max()));
+    (min - point)
+        .max(point - max)
+        .max(Vec3::ZERO)
+        .length_squared()
+}
+
+/// Closest point by Voronoi region of the triangle, after Ericson's
+/// Real-Time Collision Detection.
+pub(crate) fn closest_point_on_triangle(triangle: &GpuTriangle, p: Vec3) -> Vec3 {
+    let (a, b, c) = (
+        Vec3::from(*triangle.a()),
+        Vec3::from(*triangle.b()),
+        Vec3::from(*triangle.c()),
+    );
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    // Inside the face. Degenerate triangles end up here with a zero
+    // denominator, fall back to a vertex then.
+    let denominator = va + vb + vc;
+    if denominator.abs() <= f32::EPSILON {
+        return a;
+    }
+    a + ab * (vb / denominator) + ac * (vc / denominator)
+}
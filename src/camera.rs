@@ -1,5 +1,5 @@
 pub(crate) mod cameras;
-pub(crate) mod configuration;
-pub(crate) mod marker;
-pub(crate) mod plugin;
+pub mod configuration;
+pub mod marker;
+pub mod plugin;
 pub(crate) mod systems;
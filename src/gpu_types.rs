@@ -7,8 +7,8 @@ mod camera_type;
 mod polygon_types;
 mod vector_types;
 
-pub(crate) use box_types::GpuBox3;
-pub(crate) use bvh_types::GpuBvhNode;
-pub(crate) use camera_type::GpuCamera;
-pub(crate) use polygon_types::GpuTriangle;
-pub(crate) use vector_types::{GpuUVec3, GpuUVec4, GpuVec2, GpuVec3, GpuVec4};
+pub use box_types::GpuBox3;
+pub use bvh_types::GpuBvhNode;
+pub use camera_type::GpuCamera;
+pub use polygon_types::GpuTriangle;
+pub use vector_types::{GpuUVec3, GpuUVec4, GpuVec2, GpuVec3, GpuVec4};
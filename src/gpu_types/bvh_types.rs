@@ -27,6 +27,22 @@ impl GpuBvhNode {
         }
     }
 
+    pub fn aabb(&self) -> &GpuBox3 {
+        &self.aabb
+    }
+
+    pub fn left_index(&self) -> u32 {
+        self.left_index
+    }
+
+    pub fn right_index(&self) -> u32 {
+        self.right_index
+    }
+
+    pub fn triangle_count(&self) -> u32 {
+        self.triangle_count
+    }
+
     pub fn with_left_index(&mut self, left_index: u32) {
         self.left_index = left_index;
     }
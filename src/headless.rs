@@ -0,0 +1,450 @@
+use crate::{
+    batch::{self, BatchJob, BatchManifest, BatchRun},
+    bvh::{BvhPlugin, BvhTargetMarker},
+    meshing::{
+        ExportExtractedMesh, MeshExported, MeshExtractionSettings, MeshingMethod, MeshingPlugin,
+        decimation::DecimationSettings, lod::LodSettings,
+    },
+    validation::{self, ValidationPlugin, ValidationRun},
+    voxelization::{
+        SignMode, VoxelizationFailed, VoxelizationPlugin, VoxelizationSettings,
+        VoxelizeTargetMarker,
+        volume_io::{ExportBakedVolume, SignConvention, VolumeExported, VolumeFormat},
+    },
+};
+use bevy::{
+    app::ScheduleRunnerPlugin,
+    asset::{LoadState, UnapprovedPathMode},
+    prelude::*,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use bevy_obj::ObjPlugin;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+const USAGE: &str = "usage:
+  distill-bake <mesh.obj> <output> [--resolution <voxels>] [--padding <ratio>]
+               [--sign negative_inside|positive_inside] [--format raw|nrrd|ktx2|vdb]
+               [--unsigned | --shell <thickness>]
+  distill bake <same arguments as distill-bake>
+  distill batch <directory|manifest.json> [--output-dir <dir>] [--report <report.json|report.csv>]
+                [--force]
+  distill-bake batch <same arguments as distill batch>
+  distill validate [--resolutions <voxels,...>] [--max-rms <voxels>] [--report <curves.csv>]
+  distill export-vdb <mesh.obj> <output.vdb> [resolution]
+  distill remesh <mesh.obj> <output.obj> [--triangles <count>] [--max-error <distance>]
+                 [--resolution <voxels>] [--method marching_cubes|dual_contouring|greedy_voxels]
+                 [--lods <levels>]";
+
+/// Process exit codes of the headless commands, besides `0` for success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadlessExit {
+    /// The command line could not be parsed.
+    Usage = 2,
+    /// The input mesh could not be loaded.
+    MeshLoad = 3,
+    /// The mesh was loaded but could not be voxelized.
+    Bake = 4,
+    /// The result could not be written.
+    Write = 5,
+    /// A validation bake is further from the analytic distance than allowed.
+    Validation = 6,
+}
+
+impl From<HeadlessExit> for AppExit {
+    fn from(exit: HeadlessExit) -> Self {
+        AppExit::from_code(exit as u8)
+    }
+}
+
+/// Work done without a window, selected by the first command line argument.
+#[derive(Debug, Clone)]
+pub enum HeadlessCommand {
+    /// Bakes a mesh and writes the distance field.
+    Bake {
+        mesh: PathBuf,
+        output: PathBuf,
+        settings: VoxelizationSettings,
+        format: VolumeFormat,
+        sign_convention: SignConvention,
+    },
+    /// Bakes every model of a directory or manifest whose output is out of
+    /// date and writes a report.
+    Batch {
+        jobs: Vec<BatchJob>,
+        report: PathBuf,
+        force: bool,
+    },
+    /// Bakes the primitives with analytic distances at several resolutions
+    /// and compares the results against them.
+    Validate {
+        resolutions: Vec<u32>,
+        max_rms_voxels: f32,
+        report: Option<PathBuf>,
+    },
+    /// Bakes a mesh, extracts and simplifies its isosurface and writes it as
+    /// OBJ, with any levels of detail next to it.
+    Remesh {
+        mesh: PathBuf,
+        output: PathBuf,
+        settings: VoxelizationSettings,
+        extraction: MeshExtractionSettings,
+    },
+}
+
+impl HeadlessCommand {
+    /// `None` if the arguments do not start with a headless command, an error
+    /// with the usage if they do but are malformed.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Option<Result<Self, String>> {
+        match args.next()?.as_str() {
+            "bake" => Some(Self::parse_bake(args)),
+            "batch" => Some(Self::parse_batch(args)),
+            "validate" => Some(Self::parse_validate(args)),
+            "export-vdb" => Some(Self::parse_export_vdb(args)),
+            "remesh" => Some(Self::parse_remesh(args)),
+            _ => None,
+        }
+    }
+
+    /// Parses the arguments of `distill-bake`, without the program name.
+    /// Unless given, the format follows the extension of the output path.
+    pub fn parse_bake(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let (Some(mesh), Some(output)) = (args.next(), args.next()) else {
+            return Err(USAGE.to_string());
+        };
+
+        let output = PathBuf::from(output);
+        let mut settings = VoxelizationSettings::default();
+        let mut format = VolumeFormat::from_path(&output);
+        let mut sign_convention = SignConvention::default();
+
+        while let Some(flag) = args.next() {
+            if flag == "--unsigned" {
+                settings.sign_mode = SignMode::Unsigned;
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {flag}\n{USAGE}"))?;
+            match flag.as_str() {
+                "--resolution" => settings.resolution = parse_value(&flag, &value)?,
+                "--shell" => {
+                    settings.sign_mode = SignMode::ThinShell {
+                        thickness: parse_value(&flag, &value)?,
+                    }
+                }
+                "--padding" => settings.padding_ratio = parse_value(&flag, &value)?,
+                "--sign" => sign_convention = parse_value(&flag, &value)?,
+                "--format" => format = parse_value(&flag, &value)?,
+                _ => return Err(format!("unknown option {flag}\n{USAGE}")),
+            }
+        }
+
+        Ok(Self::Bake {
+            mesh: mesh_path(&mesh)?,
+            output,
+            settings,
+            format,
+            sign_convention,
+        })
+    }
+
+    /// Parses the arguments of `distill batch`. Outputs go next to the
+    /// manifest, or into `baked` inside a model directory, unless set.
+    pub fn parse_batch(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let Some(input) = args.next().map(PathBuf::from) else {
+            return Err(USAGE.to_string());
+        };
+
+        let mut output_dir = None;
+        let mut report = None;
+        let mut force = false;
+        while let Some(flag) = args.next() {
+            if flag == "--force" {
+                force = true;
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {flag}\n{USAGE}"))?;
+            match flag.as_str() {
+                "--output-dir" => output_dir = Some(PathBuf::from(value)),
+                "--report" => report = Some(PathBuf::from(value)),
+                _ => return Err(format!("unknown option {flag}\n{USAGE}")),
+            }
+        }
+
+        let mut manifest = BatchManifest::read(&input).map_err(|e| format!("{input:?}: {e}"))?;
+        let output_dir = output_dir
+            .or(manifest.output_dir.take())
+            .unwrap_or_else(|| match input.is_dir() {
+                true => input.join("baked"),
+                false => input.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            });
+        Ok(Self::Batch {
+            jobs: manifest.into_jobs(&output_dir),
+            report: report.unwrap_or_else(|| output_dir.join("report.json")),
+            force,
+        })
+    }
+
+    fn parse_validate(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut resolutions = vec![16, 32, 64, 128];
+        let mut max_rms_voxels = 0.5;
+        let mut report = None;
+
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {flag}\n{USAGE}"))?;
+            match flag.as_str() {
+                "--resolutions" => {
+                    resolutions = value
+                        .split(',')
+                        .map(|resolution| parse_value(&flag, resolution))
+                        .collect::<Result<_, _>>()?
+                }
+                "--max-rms" => max_rms_voxels = parse_value(&flag, &value)?,
+                "--report" => report = Some(PathBuf::from(value)),
+                _ => return Err(format!("unknown option {flag}\n{USAGE}")),
+            }
+        }
+
+        Ok(Self::Validate {
+            resolutions,
+            max_rms_voxels,
+            report,
+        })
+    }
+
+    fn parse_export_vdb(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let (Some(mesh), Some(output)) = (args.next(), args.next()) else {
+            return Err(USAGE.to_string());
+        };
+
+        let mut settings = VoxelizationSettings::default();
+        if let Some(resolution) = args.next() {
+            settings.resolution = parse_value("resolution", &resolution)?;
+        }
+
+        Ok(Self::Bake {
+            mesh: mesh_path(&mesh)?,
+            output: output.into(),
+            settings,
+            format: VolumeFormat::Vdb,
+            sign_convention: SignConvention::NegativeInside,
+        })
+    }
+
+    fn parse_remesh(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let (Some(mesh), Some(output)) = (args.next(), args.next()) else {
+            return Err(USAGE.to_string());
+        };
+
+        let mut settings = VoxelizationSettings::default();
+        let mut extraction = MeshExtractionSettings::default();
+        let mut decimation = DecimationSettings::default();
+
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {flag}\n{USAGE}"))?;
+            match flag.as_str() {
+                "--triangles" => decimation.target_triangles = Some(parse_value(&flag, &value)?),
+                "--max-error" => decimation.max_error = Some(parse_value(&flag, &value)?),
+                "--resolution" => settings.resolution = parse_value(&flag, &value)?,
+                "--method" => extraction.method = parse_value(&flag, &value)?,
+                "--lods" => {
+                    extraction.lod = Some(LodSettings {
+                        levels: parse_value(&flag, &value)?,
+                        ..default()
+                    })
+                }
+                _ => return Err(format!("unknown option {flag}\n{USAGE}")),
+            }
+        }
+
+        extraction.decimation = Some(decimation);
+        settings.closest_features = extraction.method == MeshingMethod::DualContouring;
+
+        Ok(Self::Remesh {
+            mesh: mesh_path(&mesh)?,
+            output: output.into(),
+            settings,
+            extraction,
+        })
+    }
+
+    pub fn run(self) -> AppExit {
+        let mut app = App::new();
+        app.add_plugins((
+            DefaultPlugins
+                .set(AssetPlugin {
+                    // Meshes are given as absolute paths, see `mesh_path`
+                    unapproved_path_mode: UnapprovedPathMode::Allow,
+                    ..default()
+                })
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    ..default()
+                })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0)),
+            ObjPlugin,
+        ));
+        app.add_plugins((BvhPlugin, VoxelizationPlugin, MeshingPlugin));
+
+        if let Self::Batch {
+            jobs,
+            report,
+            force,
+        } = self
+        {
+            app.insert_resource(BatchRun::new(jobs, report, force));
+            app.add_systems(Startup, batch::spawn_batch_jobs);
+            app.add_systems(Update, batch::track_batch_jobs);
+            return app.run();
+        }
+
+        if let Self::Validate {
+            resolutions,
+            max_rms_voxels,
+            report,
+        } = self
+        {
+            app.add_plugins(ValidationPlugin);
+            app.insert_resource(ValidationRun::new(resolutions, max_rms_voxels, report));
+            app.add_systems(Startup, validation::spawn_validation_bakes);
+            app.add_systems(Update, validation::finish_validation);
+            return app.run();
+        }
+
+        app.add_systems(
+            Startup,
+            move |mut commands: Commands, asset_server: Res<AssetServer>| match &self {
+                Self::Bake {
+                    mesh,
+                    output,
+                    settings,
+                    format,
+                    sign_convention,
+                } => {
+                    info!("Baking {mesh:?} into {output:?} as {format}.");
+                    commands.spawn((
+                        VoxelizeTargetMarker,
+                        BvhTargetMarker,
+                        *settings,
+                        Mesh3d(asset_server.load(mesh.clone())),
+                        ExportBakedVolume {
+                            path: output.clone(),
+                            format: *format,
+                            sign_convention: *sign_convention,
+                        },
+                    ));
+                }
+                Self::Batch { .. } | Self::Validate { .. } => unreachable!("run above"),
+                Self::Remesh {
+                    mesh,
+                    output,
+                    settings,
+                    extraction,
+                } => {
+                    info!("Remeshing {mesh:?} into {output:?}.");
+                    commands.spawn((
+                        VoxelizeTargetMarker,
+                        BvhTargetMarker,
+                        *settings,
+                        *extraction,
+                        Transform::default(),
+                        Mesh3d(asset_server.load(mesh.clone())),
+                        ExportExtractedMesh(output.clone()),
+                    ));
+                }
+            },
+        );
+
+        app.add_systems(Update, (exit_on_failed_load, exit_on_export));
+        app.run()
+    }
+}
+
+/// Asset paths are relative to the asset folder, so command line meshes are
+/// loaded by their absolute path. The headless app allows that.
+fn mesh_path(mesh: &str) -> Result<PathBuf, String> {
+    std::fs::canonicalize(mesh).map_err(|e| format!("{mesh}: {e}"))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("invalid {name} {value:?}: {e}\n{USAGE}"))
+}
+
+/// Longest a headless command waits for a mesh, so a load that never starts
+/// or never finishes ends the run instead of hanging it.
+pub(crate) const MESH_LOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Why `mesh` will not load: it failed, or it is still not loaded `waited`
+/// after it was requested.
+pub(crate) fn mesh_load_error(
+    asset_server: &AssetServer,
+    mesh: &Mesh3d,
+    waited: Duration,
+) -> Option<String> {
+    match asset_server.get_load_state(mesh.id()) {
+        Some(LoadState::Loaded) => None,
+        Some(LoadState::Failed(e)) => Some(format!("failed to load mesh: {e}")),
+        _ if waited > MESH_LOAD_TIMEOUT => Some(format!(
+            "mesh did not load within {}s",
+            MESH_LOAD_TIMEOUT.as_secs()
+        )),
+        _ => None,
+    }
+}
+
+fn exit_on_failed_load(
+    asset_server: Res<AssetServer>,
+    time: Res<Time<Real>>,
+    targets: Query<&Mesh3d, With<VoxelizeTargetMarker>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    for mesh in targets.iter() {
+        if let Some(error) = mesh_load_error(&asset_server, mesh, time.elapsed()) {
+            error!("{error}");
+            exit.write(HeadlessExit::MeshLoad.into());
+        }
+    }
+}
+
+fn exit_on_export(
+    mut volumes: MessageReader<VolumeExported>,
+    mut meshes: MessageReader<MeshExported>,
+    mut failures: MessageReader<VoxelizationFailed>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if failures.read().next().is_some() {
+        exit.write(HeadlessExit::Bake.into());
+    }
+
+    let errors = volumes
+        .read()
+        .map(|exported| &exported.error)
+        .chain(meshes.read().map(|exported| &exported.error));
+
+    for error in errors {
+        exit.write(match error {
+            Some(_) => HeadlessExit::Write.into(),
+            None => AppExit::Success,
+        });
+    }
+}
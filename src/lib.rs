@@ -0,0 +1,9 @@
+pub mod batch;
+pub mod bvh;
+pub mod camera;
+pub mod gpu_types;
+pub mod headless;
+pub mod meshing;
+pub(crate) mod utils;
+pub mod validation;
+pub mod voxelization;
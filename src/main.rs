@@ -1,21 +1,29 @@
-use crate::{
+use bevy::{pbr::wireframe::Wireframe, prelude::*};
+use bevy_obj::ObjPlugin;
+use distill::{
     bvh::{BvhPlugin, BvhTargetMarker},
     camera::{
         configuration::CameraConfiguration, marker::CameraMarkerPrimary, plugin::CameraPlugin,
     },
+    headless::{HeadlessCommand, HeadlessExit},
+    meshing::MeshingPlugin,
+    validation::ValidationPlugin,
     voxelization::{VoxelizationPlugin, VoxelizeTargetMarker},
 };
-use bevy::{pbr::wireframe::Wireframe, prelude::*};
-use bevy_obj::ObjPlugin;
 
-pub(crate) mod bvh;
-mod camera;
-pub(crate) mod gpu_types;
-pub(crate) mod utils;
-pub(crate) mod voxelization;
 mod window;
 
-fn main() {
+fn main() -> AppExit {
+    if let Some(command) = HeadlessCommand::from_args(std::env::args().skip(1)) {
+        return match command {
+            Ok(command) => command.run(),
+            Err(usage) => {
+                eprintln!("{usage}");
+                HeadlessExit::Usage.into()
+            }
+        };
+    }
+
     let mut app = App::new();
     app.add_plugins((DefaultPlugins, ObjPlugin));
 
@@ -40,7 +48,12 @@ fn main() {
     app.add_plugins(CameraPlugin::<CameraMarkerPrimary> {
         configuration: CameraConfiguration::<CameraMarkerPrimary>::default(),
     });
-    app.add_plugins((BvhPlugin, VoxelizationPlugin));
+    app.add_plugins((
+        BvhPlugin,
+        VoxelizationPlugin,
+        MeshingPlugin,
+        ValidationPlugin,
+    ));
 
     app.add_systems(Startup, (camera_system, light_system));
 
@@ -50,7 +63,7 @@ fn main() {
 
     app.add_systems(PostStartup, spawn_target_mesh_obj);
 
-    app.run();
+    app.run()
 }
 
 fn camera_system(mut commands: Commands) {
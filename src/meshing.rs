@@ -0,0 +1,486 @@
+use crate::{
+    bvh::BvhData,
+    gpu_types::GpuBox3,
+    meshing::{
+        decimation::DecimationSettings,
+        greedy_voxels::VoxelColors,
+        lod::{LodExtraction, LodSettings, MeshLods},
+    },
+    utils::input_utils::is_modifier,
+    voxelization::{VoxelizationData, VoxelizationState, VoxelizeTargetMarker},
+};
+use bevy::{
+    asset::RenderAssetUsages,
+    mesh::{Indices, PrimitiveTopology},
+    prelude::*,
+};
+use std::path::{Path, PathBuf};
+use tracing::instrument;
+
+pub mod decimation;
+pub mod dual_contouring;
+pub mod greedy_voxels;
+pub mod lod;
+pub mod marching_cubes;
+pub mod obj_export;
+
+const TEMP_DIR: &str = "temp";
+
+pub struct MeshingPlugin;
+
+impl Plugin for MeshingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<MeshExported>();
+        app.add_systems(
+            Update,
+            (
+                despawn_stale_extracted_meshes,
+                spawn_extracted_meshes,
+                lod::finish_lod_extractions,
+                export_requested_meshes,
+            )
+                .chain(),
+        );
+        app.add_systems(Update, (lod::switch_mesh_lods, mesh_exporter));
+    }
+}
+
+/// Per-entity isosurface extraction settings. Entities without this component
+/// are extracted with [`MeshExtractionSettings::default`].
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct MeshExtractionSettings {
+    pub method: MeshingMethod,
+    /// Distance of the extracted surface from the baked one. Positive values
+    /// dilate the surface, negative ones erode it.
+    pub iso_level: f32,
+    /// Simplifies the extracted mesh if set.
+    pub decimation: Option<DecimationSettings>,
+    /// Extracts coarser levels of detail and switches between them by
+    /// distance if set.
+    pub lod: Option<LodSettings>,
+}
+
+impl Default for MeshExtractionSettings {
+    fn default() -> Self {
+        Self {
+            method: MeshingMethod::MarchingCubes,
+            iso_level: 0.0,
+            decimation: None,
+            lod: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum MeshingMethod {
+    /// Smooth surface through interpolated edge crossings. Rounds off sharp
+    /// features.
+    #[default]
+    MarchingCubes,
+    /// One QEF-placed vertex per cell, keeping corners and creases. Uses the
+    /// closest-feature normals when they were baked.
+    DualContouring,
+    /// Axis-aligned cubes for the voxels inside the surface, with coplanar
+    /// faces merged. Coloured from the albedo volume when it was baked. Never
+    /// decimated.
+    GreedyVoxels,
+}
+
+/// Writes the mesh extracted from an entity as OBJ to the given path once it
+/// exists, then removes itself.
+#[derive(Debug, Clone, Component)]
+pub struct ExportExtractedMesh(pub PathBuf);
+
+/// Sent when an [`ExportExtractedMesh`] request was processed.
+#[derive(Debug, Clone, Message)]
+pub struct MeshExported {
+    pub entity: Entity,
+    pub path: PathBuf,
+    /// The error message if the export failed.
+    pub error: Option<String>,
+}
+
+/// Marks a mesh extracted from the baked distance field of `source_entity`.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct ExtractedMesh {
+    pub source_entity: Entity,
+}
+
+/// Read-only view of a decoded distance grid in mesh-local space.
+#[derive(Debug, Clone, Copy)]
+pub struct SdfGrid<'a> {
+    voxels: &'a [f32],
+    size: u32,
+    min: Vec3,
+    voxel_size: Vec3,
+}
+
+impl<'a> SdfGrid<'a> {
+    /// `voxels` holds `size`³ distances, x fastest, whose voxel centers sit at
+    /// the centers of `size`³ equal cells of `bounds`.
+    pub fn new(voxels: &'a [f32], size: u32, bounds: &GpuBox3) -> Self {
+        assert_eq!(voxels.len(), (size as usize).pow(3));
+        Self {
+            voxels,
+            size,
+            min: Vec3::from(*bounds.min()),
+            voxel_size: Vec3::from(bounds.size()) / size as f32,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn voxel_size(&self) -> Vec3 {
+        self.voxel_size
+    }
+
+    /// Mesh-local bounds the voxels were sampled over.
+    pub fn bounds(&self) -> GpuBox3 {
+        GpuBox3::new(
+            self.min.into(),
+            (self.min + self.voxel_size * self.size as f32).into(),
+        )
+    }
+
+    pub fn index(&self, voxel: UVec3) -> usize {
+        let size = self.size as usize;
+        voxel.x as usize + voxel.y as usize * size + voxel.z as usize * size * size
+    }
+
+    pub fn value(&self, voxel: UVec3) -> f32 {
+        self.voxels[self.index(voxel)]
+    }
+
+    /// Mesh-local position of a voxel center.
+    pub fn position(&self, voxel: UVec3) -> Vec3 {
+        self.min + (voxel.as_vec3() + 0.5) * self.voxel_size
+    }
+
+    /// Distance gradient at a voxel center, by central differences inside the
+    /// grid and one-sided differences on its faces.
+    pub fn gradient(&self, voxel: UVec3) -> Vec3 {
+        let max = UVec3::splat(self.size - 1);
+        let axis_derivative = |axis: UVec3| {
+            let lo = voxel.saturating_sub(axis);
+            let hi = (voxel + axis).min(max);
+            let steps = (hi - lo).element_sum().max(1) as f32;
+            (self.value(hi) - self.value(lo)) / steps
+        };
+
+        Vec3::new(
+            axis_derivative(UVec3::X),
+            axis_derivative(UVec3::Y),
+            axis_derivative(UVec3::Z),
+        ) / self.voxel_size
+    }
+}
+
+/// Indexed triangle mesh in mesh-local space, wound counter-clockwise when
+/// seen from outside the surface.
+#[derive(Debug, Default, Clone)]
+pub struct IsoMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    /// Linear vertex colours, empty if the mesh has none.
+    pub colors: Vec<Vec4>,
+    pub indices: Vec<u32>,
+}
+
+impl IsoMesh {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn into_mesh(self) -> Mesh {
+        let mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+        )
+        .with_inserted_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            self.positions
+                .iter()
+                .map(|p| p.to_array())
+                .collect::<Vec<_>>(),
+        )
+        .with_inserted_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            self.normals
+                .iter()
+                .map(|n| n.to_array())
+                .collect::<Vec<_>>(),
+        )
+        .with_inserted_indices(Indices::U32(self.indices));
+
+        if self.colors.is_empty() {
+            mesh
+        } else {
+            mesh.with_inserted_attribute(
+                Mesh::ATTRIBUTE_COLOR,
+                self.colors.iter().map(|c| c.to_array()).collect::<Vec<_>>(),
+            )
+        }
+    }
+}
+
+/// Extracts the isosurface of a grid with the given settings, decimating it
+/// to `target_scale` times the target triangle count.
+fn extract_isosurface(
+    grid: &SdfGrid,
+    normals: Option<&[[f32; 4]]>,
+    colors: Option<VoxelColors>,
+    settings: &MeshExtractionSettings,
+    target_scale: f32,
+) -> IsoMesh {
+    let iso_mesh = match settings.method {
+        MeshingMethod::MarchingCubes => marching_cubes::marching_cubes(grid, settings.iso_level),
+        MeshingMethod::DualContouring => {
+            dual_contouring::dual_contouring(grid, normals, settings.iso_level)
+        }
+        MeshingMethod::GreedyVoxels => {
+            return greedy_voxels::greedy_voxels(grid, colors, settings.iso_level);
+        }
+    };
+
+    let Some(decimation) = &settings.decimation else {
+        return iso_mesh;
+    };
+
+    let decimated = decimation::decimate(
+        &iso_mesh,
+        &DecimationSettings {
+            target_triangles: decimation
+                .target_triangles
+                .map(|target| (target as f32 * target_scale) as usize),
+            ..*decimation
+        },
+    );
+    debug!(
+        before = iso_mesh.triangle_count(),
+        after = decimated.triangle_count(),
+        "Decimated isosurface."
+    );
+    decimated
+}
+
+/// Spawns the isosurface of every computed target next to it, for comparison
+/// with the source mesh. Levels of detail follow from a background task, see
+/// [`LodExtraction`].
+#[allow(clippy::type_complexity)]
+#[instrument(skip_all)]
+fn spawn_extracted_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    images: Res<Assets<Image>>,
+    sources: Query<
+        (
+            Entity,
+            &VoxelizationData,
+            &Transform,
+            Option<&MeshExtractionSettings>,
+            Option<&BvhData>,
+        ),
+        With<VoxelizeTargetMarker>,
+    >,
+    extracted: Query<&ExtractedMesh>,
+) {
+    for (entity, voxel_data, transform, settings, bvh_data) in sources.iter() {
+        if *voxel_data.state() != VoxelizationState::Computed
+            || extracted.iter().any(|mesh| mesh.source_entity == entity)
+        {
+            continue;
+        }
+
+        let Some(sdf_data) = voxel_data.data() else {
+            continue;
+        };
+
+        let Some(voxels) = sdf_data.read_voxels(&images) else {
+            error!("Distance field of entity {entity:?} has no CPU-accessible data.");
+            continue;
+        };
+
+        let settings = settings.copied().unwrap_or_default();
+        let grid = SdfGrid::new(&voxels, sdf_data.volume.grid_size, &sdf_data.volume.bounds);
+        let normals = sdf_data
+            .closest_features
+            .as_ref()
+            .and_then(|features| images.get(&features.closest_normal))
+            .and_then(|image| image.data.as_deref())
+            .map(bytemuck::cast_slice::<u8, [f32; 4]>);
+        // Voxel colours are wanted as soon as the albedo volume is baked.
+        let colors = match (settings.method, &sdf_data.albedo) {
+            (MeshingMethod::GreedyVoxels, None) if voxel_data.settings().albedo => continue,
+            (MeshingMethod::GreedyVoxels, Some(albedo)) => images
+                .get(albedo)
+                .and_then(|image| image.data.as_deref())
+                .map(|texels| VoxelColors {
+                    texels: bytemuck::cast_slice(texels),
+                    size: sdf_data.volume.grid_size,
+                }),
+            _ => None,
+        };
+        if settings.method == MeshingMethod::DualContouring && normals.is_none() {
+            warn!(
+                "Entity {entity:?} has no closest-feature normals. Dual contouring falls back to the distance gradient."
+            );
+        }
+
+        let iso_mesh = extract_isosurface(&grid, normals, colors, &settings, 1.0);
+        info!(
+            method = %settings.method,
+            vertices = iso_mesh.positions.len(),
+            triangles = iso_mesh.triangle_count(),
+            "Extracted isosurface of entity {entity:?}."
+        );
+
+        let lod_extraction = settings.lod.map(|lod_settings| {
+            if bvh_data.is_none() {
+                warn!(
+                    "Entity {entity:?} has no BVH. LOD errors are measured against the full resolution extraction."
+                );
+            }
+            lod::spawn_lod_extraction(
+                lod_settings,
+                &settings,
+                &grid,
+                colors,
+                iso_mesh.clone(),
+                bvh_data,
+            )
+        });
+        let mesh = meshes.add(iso_mesh.into_mesh());
+
+        let extent = Vec3::from(sdf_data.volume.bounds.size()) * transform.scale;
+        let mut extracted_mesh = commands.spawn((
+            ExtractedMesh {
+                source_entity: entity,
+            },
+            Mesh3d(mesh),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                // Vertex colours are multiplied with the base colour.
+                base_color: if colors.is_some() {
+                    Color::WHITE
+                } else {
+                    Color::linear_rgba(0.8, 0.8, 0.8, 1.0)
+                },
+                ..default()
+            })),
+            transform.with_translation(
+                transform.translation + transform.rotation * (Vec3::X * extent.x),
+            ),
+        ));
+        if let Some(lod_extraction) = lod_extraction {
+            extracted_mesh.insert(lod_extraction);
+        }
+    }
+}
+
+/// Despawns extracted meshes whose source was re-baked, removed or had its
+/// extraction settings changed, so they are extracted again.
+fn despawn_stale_extracted_meshes(
+    mut commands: Commands,
+    extracted: Query<(Entity, &ExtractedMesh)>,
+    sources: Query<&VoxelizationData>,
+    changed_settings: Query<(), Changed<MeshExtractionSettings>>,
+) {
+    for (entity, mesh) in extracted.iter() {
+        let computed = sources
+            .get(mesh.source_entity)
+            .is_ok_and(|voxel_data| *voxel_data.state() == VoxelizationState::Computed);
+
+        if !computed || changed_settings.contains(mesh.source_entity) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Writes an extracted mesh as OBJ. Coarser levels of detail go next to it,
+/// with `_lod<i>` appended to the file stem.
+fn write_extracted_mesh(
+    path: &Path,
+    meshes: &Assets<Mesh>,
+    mesh_handle: &Mesh3d,
+    lods: Option<&MeshLods>,
+) -> Result<(), String> {
+    let handles = match lods {
+        Some(lods) => lods.levels.iter().map(|level| &level.mesh).collect(),
+        None => vec![&mesh_handle.0],
+    };
+
+    for (i, handle) in handles.into_iter().enumerate() {
+        let level_path = if i == 0 {
+            path.to_path_buf()
+        } else {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!("{stem}_lod{i}.obj"))
+        };
+
+        let mesh = meshes
+            .get(handle)
+            .ok_or_else(|| "extracted mesh is not loaded".to_string())?;
+        obj_export::write_obj(&level_path, mesh).map_err(|e| format!("{level_path:?}: {e}"))?;
+    }
+
+    Ok(())
+}
+
+/// Exports once the levels of detail, if any, are extracted.
+fn export_requested_meshes(
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    extracted: Query<(&ExtractedMesh, &Mesh3d, Option<&MeshLods>), Without<LodExtraction>>,
+    requests: Query<&ExportExtractedMesh>,
+    mut exported: MessageWriter<MeshExported>,
+) {
+    for (extracted_mesh, mesh_handle, lods) in extracted.iter() {
+        let entity = extracted_mesh.source_entity;
+        let Ok(ExportExtractedMesh(path)) = requests.get(entity) else {
+            continue;
+        };
+
+        let result = write_extracted_mesh(path, &meshes, mesh_handle, lods);
+
+        match &result {
+            Ok(()) => info!("Exported mesh of entity {entity:?} to {path:?}."),
+            Err(e) => error!("Failed to export mesh of entity {entity:?} to {path:?}: {e}"),
+        }
+
+        exported.write(MeshExported {
+            entity,
+            path: path.clone(),
+            error: result.err(),
+        });
+        commands.entity(entity).remove::<ExportExtractedMesh>();
+    }
+}
+
+/// Shift+E writes every extracted mesh whose levels of detail are done as OBJ.
+fn mesh_exporter(
+    input: Res<ButtonInput<KeyCode>>,
+    meshes: Res<Assets<Mesh>>,
+    extracted: Query<(&ExtractedMesh, &Mesh3d, Option<&MeshLods>), Without<LodExtraction>>,
+) {
+    let shift_held = input.pressed(KeyCode::ShiftLeft) || input.pressed(KeyCode::ShiftRight);
+    let other_modifier_held = input
+        .get_pressed()
+        .any(|key| is_modifier(*key) && !matches!(key, KeyCode::ShiftLeft | KeyCode::ShiftRight));
+
+    if !(input.just_pressed(KeyCode::KeyE) && shift_held) || other_modifier_held {
+        return;
+    }
+
+    let temp_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(TEMP_DIR);
+    for (extracted_mesh, mesh_handle, lods) in extracted.iter() {
+        let entity = extracted_mesh.source_entity;
+        let path = temp_path.join(format!("{entity}.obj"));
+        match write_extracted_mesh(&path, &meshes, mesh_handle, lods) {
+            Ok(()) => info!("Exported mesh of entity {entity:?} to {path:?}."),
+            Err(e) => error!("Failed to export mesh of entity {entity:?} to {path:?}: {e}"),
+        }
+    }
+}
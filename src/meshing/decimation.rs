@@ -0,0 +1,323 @@
+use super::IsoMesh;
+use bevy::{
+    math::{DMat3, DVec3},
+    prelude::*,
+};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashSet},
+};
+
+/// Bounds for [`decimate`]. Collapsing stops at whichever is reached first.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DecimationSettings {
+    pub target_triangles: Option<usize>,
+    /// Largest collapse error accepted, as the root of the quadric error. This
+    /// is roughly how far a collapsed vertex may move off the original surface.
+    pub max_error: Option<f32>,
+}
+
+/// Sum of squared distances to a set of planes, `vᵀAv + 2bᵀv + c`.
+#[derive(Debug, Default, Clone, Copy)]
+struct Quadric {
+    a: DMat3,
+    b: DVec3,
+    c: f64,
+}
+
+impl Quadric {
+    fn from_plane(normal: DVec3, point: DVec3) -> Self {
+        let d = -normal.dot(point);
+        Self {
+            a: DMat3::from_cols(normal * normal.x, normal * normal.y, normal * normal.z),
+            b: normal * d,
+            c: d * d,
+        }
+    }
+
+    fn error(&self, v: DVec3) -> f64 {
+        (v.dot(self.a * v) + 2.0 * self.b.dot(v) + self.c).max(0.0)
+    }
+}
+
+impl std::ops::Add for Quadric {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            a: self.a + rhs.a,
+            b: self.b + rhs.b,
+            c: self.c + rhs.c,
+        }
+    }
+}
+
+impl std::ops::AddAssign for Quadric {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+/// A candidate collapse of `from` into `into`, valid while both vertices keep
+/// the versions it was computed with.
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    cost: f64,
+    into: u32,
+    from: u32,
+    versions: [u32; 2],
+    position: DVec3,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cost.total_cmp(&other.cost)
+    }
+}
+
+struct Decimator {
+    positions: Vec<DVec3>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    removed: Vec<bool>,
+    faces: Vec<[u32; 3]>,
+    face_alive: Vec<bool>,
+    /// Faces around each vertex. May hold dead faces, which are skipped.
+    vertex_faces: Vec<Vec<usize>>,
+    queue: BinaryHeap<Reverse<Collapse>>,
+}
+
+impl Decimator {
+    fn new(mesh: &IsoMesh) -> Self {
+        let positions = mesh
+            .positions
+            .iter()
+            .map(|p| p.as_dvec3())
+            .collect::<Vec<_>>();
+        let faces = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|f| [f[0], f[1], f[2]])
+            .collect::<Vec<_>>();
+
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut vertex_faces = vec![Vec::new(); positions.len()];
+        for (i, face) in faces.iter().enumerate() {
+            let [a, b, c] = face.map(|v| positions[v as usize]);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            let plane = Quadric::from_plane(normal, a);
+            for &v in face {
+                quadrics[v as usize] += plane;
+                vertex_faces[v as usize].push(i);
+            }
+        }
+
+        let mut decimator = Self {
+            versions: vec![0; positions.len()],
+            removed: vec![false; positions.len()],
+            face_alive: vec![true; faces.len()],
+            positions,
+            quadrics,
+            faces,
+            vertex_faces,
+            queue: BinaryHeap::new(),
+        };
+
+        let edges = decimator
+            .faces
+            .iter()
+            .flat_map(|&[a, b, c]| [(a, b), (b, c), (c, a)])
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect::<HashSet<_>>();
+        for (a, b) in edges {
+            decimator.push_collapse(a, b);
+        }
+
+        decimator
+    }
+
+    fn push_collapse(&mut self, into: u32, from: u32) {
+        let quadric = self.quadrics[into as usize] + self.quadrics[from as usize];
+        let (p, q) = (self.positions[into as usize], self.positions[from as usize]);
+
+        let optimal = (quadric.a.determinant().abs() > 1e-12)
+            .then(|| quadric.a.inverse() * -quadric.b)
+            .filter(|v| v.is_finite());
+        let (cost, position) = optimal
+            .into_iter()
+            .chain([p, q, (p + q) * 0.5])
+            .map(|v| (quadric.error(v), v))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .unwrap();
+
+        self.queue.push(Reverse(Collapse {
+            cost,
+            into,
+            from,
+            versions: [self.versions[into as usize], self.versions[from as usize]],
+            position,
+        }));
+    }
+
+    fn is_current(&self, collapse: &Collapse) -> bool {
+        let (into, from) = (collapse.into as usize, collapse.from as usize);
+        !self.removed[into]
+            && !self.removed[from]
+            && collapse.versions == [self.versions[into], self.versions[from]]
+    }
+
+    fn alive_faces(&self, vertex: u32) -> impl Iterator<Item = usize> + '_ {
+        self.vertex_faces[vertex as usize]
+            .iter()
+            .copied()
+            .filter(|&f| self.face_alive[f])
+    }
+
+    /// Whether moving both vertices to `position` would turn any surviving
+    /// face around them over.
+    fn flips_faces(&self, collapse: &Collapse) -> bool {
+        let moved = [collapse.into, collapse.from];
+        self.alive_faces(collapse.into)
+            .chain(self.alive_faces(collapse.from))
+            .map(|f| self.faces[f])
+            .filter(|face| !moved.iter().all(|v| face.contains(v)))
+            .any(|face| {
+                let before = face.map(|v| self.positions[v as usize]);
+                let after = face.map(|v| {
+                    if moved.contains(&v) {
+                        collapse.position
+                    } else {
+                        self.positions[v as usize]
+                    }
+                });
+                let normal = |[a, b, c]: [DVec3; 3]| (b - a).cross(c - a);
+                normal(before).dot(normal(after)) <= 0.0
+            })
+    }
+
+    /// Collapses `from` into `into` and returns the number of faces removed.
+    fn collapse(&mut self, collapse: &Collapse) -> usize {
+        let (into, from) = (collapse.into, collapse.from);
+        self.positions[into as usize] = collapse.position;
+        let from_quadric = self.quadrics[from as usize];
+        self.quadrics[into as usize] += from_quadric;
+        self.removed[from as usize] = true;
+        self.versions[into as usize] += 1;
+
+        let mut removed_faces = 0;
+        for f in std::mem::take(&mut self.vertex_faces[from as usize]) {
+            if !self.face_alive[f] {
+                continue;
+            }
+
+            if self.faces[f].contains(&into) {
+                self.face_alive[f] = false;
+                removed_faces += 1;
+            } else {
+                for v in &mut self.faces[f] {
+                    if *v == from {
+                        *v = into;
+                    }
+                }
+                self.vertex_faces[into as usize].push(f);
+            }
+        }
+
+        let neighbors = self
+            .alive_faces(into)
+            .flat_map(|f| self.faces[f])
+            .filter(|&v| v != into)
+            .collect::<HashSet<_>>();
+        for neighbor in neighbors {
+            self.push_collapse(into, neighbor);
+        }
+
+        removed_faces
+    }
+
+    /// Compacts the surviving vertices and faces, with area-weighted normals.
+    fn into_mesh(self) -> IsoMesh {
+        let mut remap = vec![u32::MAX; self.positions.len()];
+        let mut mesh = IsoMesh::default();
+
+        for (face, _) in self
+            .faces
+            .iter()
+            .zip(&self.face_alive)
+            .filter(|(_, alive)| **alive)
+        {
+            for &v in face {
+                if remap[v as usize] == u32::MAX {
+                    remap[v as usize] = mesh.positions.len() as u32;
+                    mesh.positions.push(self.positions[v as usize].as_vec3());
+                }
+                mesh.indices.push(remap[v as usize]);
+            }
+        }
+
+        mesh.normals = vec![Vec3::ZERO; mesh.positions.len()];
+        for face in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]].map(|v| mesh.positions[v as usize]);
+            let normal = (b - a).cross(c - a);
+            for &v in face {
+                mesh.normals[v as usize] += normal;
+            }
+        }
+        mesh.normals
+            .iter_mut()
+            .for_each(|n| *n = n.normalize_or_zero());
+
+        mesh
+    }
+}
+
+/// Simplifies a mesh by quadric error metric edge collapses, cheapest first.
+/// Collapses that would flip a face are skipped.
+pub fn decimate(mesh: &IsoMesh, settings: &DecimationSettings) -> IsoMesh {
+    if settings.target_triangles.is_none() && settings.max_error.is_none() {
+        return mesh.clone();
+    }
+
+    let mut decimator = Decimator::new(mesh);
+    let mut triangle_count = mesh.triangle_count();
+    let max_cost = settings.max_error.map(|e| (e as f64).powi(2));
+
+    while let Some(Reverse(collapse)) = decimator.queue.pop() {
+        if settings
+            .target_triangles
+            .is_some_and(|target| triangle_count <= target)
+        {
+            break;
+        }
+
+        if !decimator.is_current(&collapse) {
+            continue;
+        }
+
+        if max_cost.is_some_and(|max| collapse.cost > max) {
+            break;
+        }
+
+        if decimator.flips_faces(&collapse) {
+            continue;
+        }
+
+        triangle_count -= decimator.collapse(&collapse);
+    }
+
+    decimator.into_mesh()
+}
@@ -0,0 +1,225 @@
+use super::{
+    IsoMesh, SdfGrid,
+    marching_cubes::{EDGES, corner},
+};
+use bevy::prelude::*;
+
+/// Eigenvalues below this fraction of the largest one are dropped from the
+/// QEF solve, leaving those directions to the mass point.
+const QEF_EIGENVALUE_CUTOFF: f32 = 0.1;
+const JACOBI_SWEEPS: usize = 8;
+
+/// Extracts the `iso_level` isosurface with one vertex per cell, placed by
+/// minimizing the distance to the tangent planes at the cell's edge crossings.
+/// Flat faces stay flat and corners and creases, where the planes meet, are
+/// reproduced instead of being cut off.
+///
+/// `normals` are the closest-surface normals of each voxel, as baked in the
+/// `closest_normal` volume. Without them the distance gradient is used, which
+/// is blurred across features and rounds them off again.
+pub fn dual_contouring(grid: &SdfGrid, normals: Option<&[[f32; 4]]>, iso_level: f32) -> IsoMesh {
+    let size = grid.size();
+    let cells = size.saturating_sub(1);
+    let cell_index = |cell: UVec3| {
+        let cells = cells as usize;
+        cell.x as usize + cell.y as usize * cells + cell.z as usize * cells * cells
+    };
+
+    let mut mesh = IsoMesh::default();
+    let mut cell_vertices = vec![u32::MAX; (cells as usize).pow(3)];
+
+    for z in 0..cells {
+        for y in 0..cells {
+            for x in 0..cells {
+                let cell = UVec3::new(x, y, z);
+                if let Some((position, normal)) = cell_vertex(grid, normals, iso_level, cell) {
+                    cell_vertices[cell_index(cell)] = mesh.positions.len() as u32;
+                    mesh.positions.push(position);
+                    mesh.normals.push(normal);
+                }
+            }
+        }
+    }
+
+    // Every grid edge crossing the surface is surrounded by four cells, whose
+    // vertices form a quad.
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                let voxel = UVec3::new(x, y, z);
+                for axis in 0..3 {
+                    let u = UVec3::AXES[axis];
+                    let (v, w) = (UVec3::AXES[(axis + 1) % 3], UVec3::AXES[(axis + 2) % 3]);
+                    let end = voxel + u;
+                    if end.cmpge(UVec3::splat(size)).any()
+                        || voxel.dot(v) == 0
+                        || voxel.dot(w) == 0
+                        || voxel.dot(v) >= cells
+                        || voxel.dot(w) >= cells
+                    {
+                        continue;
+                    }
+
+                    let inside = grid.value(voxel) < iso_level;
+                    if inside == (grid.value(end) < iso_level) {
+                        continue;
+                    }
+
+                    // Counter-clockwise around +u
+                    let quad = [voxel - v - w, voxel - w, voxel, voxel - v]
+                        .map(|cell| cell_vertices[cell_index(cell)]);
+                    if quad.contains(&u32::MAX) {
+                        continue;
+                    }
+
+                    let [a, b, c, d] = if inside {
+                        quad
+                    } else {
+                        [quad[3], quad[2], quad[1], quad[0]]
+                    };
+                    mesh.indices.extend([a, b, c, a, c, d]);
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+/// The QEF-minimizing vertex of a cell and its mean Hermite normal, or `None`
+/// if the surface does not pass through the cell.
+fn cell_vertex(
+    grid: &SdfGrid,
+    normals: Option<&[[f32; 4]]>,
+    iso_level: f32,
+    cell: UVec3,
+) -> Option<(Vec3, Vec3)> {
+    let mut qef = Qef::default();
+    let mut normal_sum = Vec3::ZERO;
+
+    for (a, b) in EDGES {
+        let (voxel_a, voxel_b) = (cell + corner(a), cell + corner(b));
+        let (value_a, value_b) = (
+            grid.value(voxel_a) - iso_level,
+            grid.value(voxel_b) - iso_level,
+        );
+        if (value_a < 0.0) == (value_b < 0.0) {
+            continue;
+        }
+
+        let t = value_a / (value_a - value_b);
+        let point = grid.position(voxel_a).lerp(grid.position(voxel_b), t);
+        let closer = if t < 0.5 { voxel_a } else { voxel_b };
+        let normal = normals
+            .map(|normals| Vec3::from_slice(&normals[grid.index(closer)][..3]))
+            .filter(|normal| normal.length_squared() > 0.25)
+            .unwrap_or_else(|| grid.gradient(voxel_a).lerp(grid.gradient(voxel_b), t))
+            .normalize_or_zero();
+
+        qef.add(point, normal);
+        normal_sum += normal;
+    }
+
+    if qef.count == 0 {
+        return None;
+    }
+
+    // Keep the vertex within reach of its cell, falling back to the mass
+    // point when the planes are nearly parallel but not quite.
+    let slack = grid.voxel_size() * 0.5;
+    let min = grid.position(cell) - slack;
+    let max = grid.position(cell + UVec3::ONE) + slack;
+    let solution = qef.solve();
+    let position = if solution.cmpge(min).all() && solution.cmple(max).all() {
+        solution
+    } else {
+        qef.mass_point()
+    };
+
+    Some((position, normal_sum.normalize_or_zero()))
+}
+
+/// Quadratic error function `Σ (nᵢ · (x - pᵢ))²` over a cell's tangent planes.
+#[derive(Debug, Default, Clone, Copy)]
+struct Qef {
+    ata: Mat3,
+    atb: Vec3,
+    point_sum: Vec3,
+    count: u32,
+}
+
+impl Qef {
+    fn add(&mut self, point: Vec3, normal: Vec3) {
+        self.ata += Mat3::from_cols(normal * normal.x, normal * normal.y, normal * normal.z);
+        self.atb += normal * normal.dot(point);
+        self.point_sum += point;
+        self.count += 1;
+    }
+
+    fn mass_point(&self) -> Vec3 {
+        self.point_sum / self.count as f32
+    }
+
+    /// Minimizer closest to the mass point, via the truncated pseudo-inverse
+    /// of `AᵀA`. Directions the planes do not constrain stay at the mass point.
+    fn solve(&self) -> Vec3 {
+        let mass_point = self.mass_point();
+        let rhs = self.atb - self.ata * mass_point;
+
+        let (eigenvalues, eigenvectors) = symmetric_eigen(self.ata);
+        let largest = eigenvalues.abs().max_element();
+        if largest <= f32::EPSILON {
+            return mass_point;
+        }
+
+        (0..3)
+            .filter(|&i| eigenvalues[i].abs() > largest * QEF_EIGENVALUE_CUTOFF)
+            .map(|i| {
+                let v = eigenvectors.col(i);
+                v * (v.dot(rhs) / eigenvalues[i])
+            })
+            .fold(mass_point, |x, step| x + step)
+    }
+}
+
+/// Eigenvalues and eigenvectors (as columns) of a symmetric matrix, by cyclic
+/// Jacobi rotations.
+fn symmetric_eigen(matrix: Mat3) -> (Vec3, Mat3) {
+    let mut a = matrix.to_cols_array_2d();
+    let mut v = Mat3::IDENTITY.to_cols_array_2d();
+
+    for _ in 0..JACOBI_SWEEPS {
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q].abs() < 1e-12 {
+                continue;
+            }
+
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+
+            for row in &mut a {
+                let (akp, akq) = (row[p], row[q]);
+                row[p] = c * akp - s * akq;
+                row[q] = s * akp + c * akq;
+            }
+            for k in 0..3 {
+                let (apk, aqk) = (a[p][k], a[q][k]);
+                a[p][k] = c * apk - s * aqk;
+                a[q][k] = s * apk + c * aqk;
+            }
+            for row in &mut v {
+                let (vp, vq) = (row[p], row[q]);
+                row[p] = c * vp - s * vq;
+                row[q] = s * vp + c * vq;
+            }
+        }
+    }
+
+    // `v` holds the eigenvectors row-major.
+    (
+        Vec3::new(a[0][0], a[1][1], a[2][2]),
+        Mat3::from_cols_array_2d(&v).transpose(),
+    )
+}
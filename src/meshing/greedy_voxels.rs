@@ -0,0 +1,137 @@
+use super::{IsoMesh, SdfGrid};
+use bevy::prelude::*;
+
+/// sRGB voxel colours on a grid of their own, e.g. the baked albedo volume.
+/// Voxels of coarser grids over the same bounds take the colour of the voxel
+/// under their center.
+#[derive(Debug, Clone, Copy)]
+pub struct VoxelColors<'a> {
+    pub texels: &'a [[u8; 4]],
+    pub size: u32,
+}
+
+impl VoxelColors<'_> {
+    fn at(&self, voxel: UVec3, grid_size: u32) -> [u8; 4] {
+        let scaled = (voxel.as_vec3() + 0.5) * self.size as f32 / grid_size as f32;
+        let v = scaled.as_uvec3().min(UVec3::splat(self.size - 1));
+        let size = self.size as usize;
+        self.texels[v.x as usize + v.y as usize * size + v.z as usize * size * size]
+    }
+}
+
+/// Meshes the voxels below `iso_level` as axis-aligned cubes. Only faces
+/// between a solid and an empty voxel are kept, and coplanar neighbouring
+/// faces of the same colour are merged into rectangles, row by row first and
+/// then across rows.
+///
+/// Every quad has its own four vertices so faces stay flat shaded. Merged
+/// quads meet in T-junctions, which rasterize without cracks for axis-aligned
+/// geometry.
+pub fn greedy_voxels(grid: &SdfGrid, colors: Option<VoxelColors>, iso_level: f32) -> IsoMesh {
+    let size = grid.size();
+    let solid = |voxel: IVec3| {
+        voxel.cmpge(IVec3::ZERO).all()
+            && voxel.cmplt(IVec3::splat(size as i32)).all()
+            && grid.value(voxel.as_uvec3()) < iso_level
+    };
+
+    let mut mesh = IsoMesh::default();
+    let mut mask = vec![None; (size as usize).pow(2)];
+
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for direction in [-1, 1] {
+            let mut normal = IVec3::ZERO;
+            normal[axis] = direction;
+
+            for slice in 0..size {
+                // The face colour of every solid voxel of the slice whose
+                // neighbour along `normal` is empty.
+                for j in 0..size {
+                    for i in 0..size {
+                        let mut voxel = UVec3::ZERO;
+                        voxel[axis] = slice;
+                        voxel[u] = i;
+                        voxel[v] = j;
+
+                        let exposed = solid(voxel.as_ivec3()) && !solid(voxel.as_ivec3() + normal);
+                        mask[(i + j * size) as usize] = exposed
+                            .then(|| colors.map_or([255; 4], |colors| colors.at(voxel, size)));
+                    }
+                }
+
+                for j in 0..size {
+                    let mut i = 0;
+                    while i < size {
+                        let Some(color) = mask[(i + j * size) as usize] else {
+                            i += 1;
+                            continue;
+                        };
+
+                        let same = |i: u32, j: u32| mask[(i + j * size) as usize] == Some(color);
+                        let width = (i..size).take_while(|&i| same(i, j)).count() as u32;
+                        let height = (j..size)
+                            .take_while(|&j| (i..i + width).all(|i| same(i, j)))
+                            .count() as u32;
+
+                        for jj in j..j + height {
+                            for ii in i..i + width {
+                                mask[(ii + jj * size) as usize] = None;
+                            }
+                        }
+
+                        let mut origin = UVec3::ZERO;
+                        origin[axis] = slice + u32::from(direction > 0);
+                        origin[u] = i;
+                        origin[v] = j;
+                        let (mut du, mut dv) = (UVec3::ZERO, UVec3::ZERO);
+                        du[u] = width;
+                        dv[v] = height;
+
+                        push_quad(
+                            &mut mesh,
+                            grid,
+                            [origin, origin + du, origin + du + dv, origin + dv],
+                            normal.as_vec3(),
+                            colors.map(|_| color),
+                        );
+                        i += width;
+                    }
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+/// Adds a quad given by its corners on the voxel lattice, counter-clockwise
+/// seen from the positive side of its axis. The winding is flipped for
+/// quads facing the negative side.
+fn push_quad(
+    mesh: &mut IsoMesh,
+    grid: &SdfGrid,
+    corners: [UVec3; 4],
+    normal: Vec3,
+    color: Option<[u8; 4]>,
+) {
+    // Lattice points sit on voxel corners, half a voxel below the centers.
+    let half_voxel = grid.voxel_size() * 0.5;
+    let base = mesh.positions.len() as u32;
+    for corner in corners {
+        mesh.positions.push(grid.position(corner) - half_voxel);
+        mesh.normals.push(normal);
+        if let Some([r, g, b, a]) = color {
+            mesh.colors
+                .push(Color::srgba_u8(r, g, b, a).to_linear().to_vec4());
+        }
+    }
+
+    if normal.element_sum() > 0.0 {
+        mesh.indices
+            .extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    } else {
+        mesh.indices
+            .extend([base, base + 2, base + 1, base, base + 3, base + 2]);
+    }
+}
@@ -0,0 +1,291 @@
+use super::{
+    ExtractedMesh, IsoMesh, MeshExtractionSettings, SdfGrid, extract_isosurface,
+    greedy_voxels::VoxelColors,
+};
+use crate::{
+    bvh::{BvhData, MeshBvh},
+    camera::marker::CameraMarkerPrimary,
+};
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, block_on, futures_lite::future},
+};
+
+/// Smallest grid a level is extracted from. Coarser grids cannot hold more
+/// than a couple of cells of surface.
+const MIN_LOD_GRID_SIZE: u32 = 4;
+
+/// Steps per triangle edge of the barycentric lattice the Hausdorff distance
+/// is sampled on, so edges and interiors count and not just vertices.
+const HAUSDORFF_SUBDIVISIONS: u32 = 3;
+
+/// Extra levels of detail extracted from successively halved grids.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodSettings {
+    /// Levels besides the full resolution one. Fewer are generated if the grid
+    /// gets too coarse.
+    pub levels: u32,
+    /// Largest on-screen Hausdorff error, in logical pixels, a level may show
+    /// before a finer one is switched in.
+    pub max_screen_error: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            levels: 3,
+            max_screen_error: 1.0,
+        }
+    }
+}
+
+/// The levels of detail of an extracted mesh, finest first. The level shown
+/// in [`Mesh3d`] is switched by distance to the primary camera.
+#[derive(Debug, Clone, Component)]
+pub struct MeshLods {
+    pub levels: Vec<MeshLod>,
+    pub max_screen_error: f32,
+    /// Index of the level currently in [`Mesh3d`].
+    pub active: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct MeshLod {
+    pub mesh: Handle<Mesh>,
+    pub triangles: usize,
+    /// Symmetric Hausdorff distance to the source mesh in mesh-local units.
+    pub hausdorff_error: f32,
+}
+
+/// Levels of detail of an extracted mesh with their Hausdorff errors, finest
+/// first, being computed off the main thread. Replaced by [`MeshLods`] once
+/// done.
+#[derive(Component)]
+pub struct LodExtraction {
+    task: Task<Vec<(IsoMesh, f32)>>,
+    max_screen_error: f32,
+}
+
+/// Starts extracting the coarser levels of `grid` and measuring every level
+/// against `reference`, or against `level_zero` without one.
+pub(super) fn spawn_lod_extraction(
+    lod_settings: LodSettings,
+    settings: &MeshExtractionSettings,
+    grid: &SdfGrid,
+    colors: Option<VoxelColors>,
+    level_zero: IsoMesh,
+    reference: Option<&BvhData>,
+) -> LodExtraction {
+    let (voxels, size, bounds) = (grid.voxels.to_vec(), grid.size(), grid.bounds());
+    let colors = colors.map(|colors| (colors.texels.to_vec(), colors.size));
+    let reference = reference.map(|bvh_data| BvhData {
+        nodes: bvh_data.nodes.clone(),
+        triangles: bvh_data.triangles.clone(),
+        uvs: None,
+    });
+    let settings = *settings;
+
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let grid = SdfGrid::new(&voxels, size, &bounds);
+        let colors = colors.as_ref().map(|(texels, size)| VoxelColors {
+            texels,
+            size: *size,
+        });
+        let reference = reference.unwrap_or_else(|| mesh_bvh(&level_zero));
+
+        let mut levels = vec![level_zero];
+        let mut downsampled = downsample(&grid);
+        while levels.len() <= lod_settings.levels as usize {
+            let Some((voxels, size)) = downsampled else {
+                break;
+            };
+            let grid = SdfGrid::new(&voxels, size, &bounds);
+            // Halving the grid quarters the surface triangles. The baked
+            // normals only match the full resolution grid.
+            let target_scale = 0.25f32.powi(levels.len() as i32);
+            let level = extract_isosurface(&grid, None, colors, &settings, target_scale);
+            if level.indices.is_empty() {
+                break;
+            }
+            levels.push(level);
+            downsampled = downsample(&grid);
+        }
+
+        levels
+            .into_iter()
+            .map(|level| {
+                let hausdorff_error = hausdorff_distance(&level, &reference);
+                (level, hausdorff_error)
+            })
+            .collect()
+    });
+
+    LodExtraction {
+        task,
+        max_screen_error: lod_settings.max_screen_error,
+    }
+}
+
+/// Turns finished [`LodExtraction`]s into [`MeshLods`]. Level zero keeps the
+/// mesh the entity was spawned with.
+pub(super) fn finish_lod_extractions(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut extractions: Query<(Entity, &ExtractedMesh, &Mesh3d, &mut LodExtraction)>,
+) {
+    for (entity, extracted, mesh, mut extraction) in extractions.iter_mut() {
+        let Some(levels) = block_on(future::poll_once(&mut extraction.task)) else {
+            continue;
+        };
+
+        let source = extracted.source_entity;
+        let levels = levels
+            .into_iter()
+            .enumerate()
+            .map(|(i, (level, hausdorff_error))| {
+                info!(
+                    triangles = level.triangle_count(),
+                    hausdorff_error, "Extracted LOD {i} of entity {source:?}."
+                );
+                MeshLod {
+                    triangles: level.triangle_count(),
+                    mesh: if i == 0 {
+                        mesh.0.clone()
+                    } else {
+                        meshes.add(level.into_mesh())
+                    },
+                    hausdorff_error,
+                }
+            })
+            .collect();
+
+        commands
+            .entity(entity)
+            .remove::<LodExtraction>()
+            .insert(MeshLods {
+                levels,
+                max_screen_error: extraction.max_screen_error,
+                active: 0,
+            });
+    }
+}
+
+/// Halves the resolution of a grid over the same bounds, by trilinear
+/// sampling at the coarse voxel centers. For even sizes this is the average of
+/// each 2x2x2 block.
+///
+/// The baked mips are not used on purpose: they are conservative for sphere
+/// tracing and pull the surface inwards.
+pub fn downsample(grid: &SdfGrid) -> Option<(Vec<f32>, u32)> {
+    let size = grid.size() / 2;
+    if size < MIN_LOD_GRID_SIZE {
+        return None;
+    }
+
+    let scale = grid.size() as f32 / size as f32;
+    let max = UVec3::splat(grid.size() - 1);
+    let mut voxels = Vec::with_capacity((size as usize).pow(3));
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                // Fine voxel coordinates of the coarse voxel center.
+                let p = ((UVec3::new(x, y, z).as_vec3() + 0.5) * scale - 0.5).max(Vec3::ZERO);
+                let lo = p.floor().as_uvec3().min(max);
+                let hi = (lo + UVec3::ONE).min(max);
+                let t = p - lo.as_vec3();
+
+                let value = |mask: UVec3| grid.value(UVec3::select(mask.cmpeq(UVec3::ONE), hi, lo));
+                let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+                let x00 = lerp(value(UVec3::new(0, 0, 0)), value(UVec3::new(1, 0, 0)), t.x);
+                let x10 = lerp(value(UVec3::new(0, 1, 0)), value(UVec3::new(1, 1, 0)), t.x);
+                let x01 = lerp(value(UVec3::new(0, 0, 1)), value(UVec3::new(1, 0, 1)), t.x);
+                let x11 = lerp(value(UVec3::new(0, 1, 1)), value(UVec3::new(1, 1, 1)), t.x);
+                voxels.push(lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z));
+            }
+        }
+    }
+
+    Some((voxels, size))
+}
+
+/// BVH over an extracted mesh, for distance queries against it. Empty if the
+/// mesh has no triangles.
+pub fn mesh_bvh(mesh: &IsoMesh) -> BvhData {
+    let (nodes, triangles) = mesh.clone().into_mesh().build_bvh(4).unwrap_or_default();
+    BvhData {
+        nodes,
+        triangles,
+        uvs: None,
+    }
+}
+
+/// Symmetric Hausdorff distance between a mesh and a reference surface,
+/// measured on a barycentric lattice over the triangles of both.
+pub fn hausdorff_distance(mesh: &IsoMesh, reference: &BvhData) -> f32 {
+    if mesh.indices.is_empty() {
+        return f32::INFINITY;
+    }
+
+    let to_reference = mesh
+        .indices
+        .chunks_exact(3)
+        .flat_map(|t| triangle_samples([t[0], t[1], t[2]].map(|i| mesh.positions[i as usize])))
+        .map(|p| reference.closest_distance(p))
+        .fold(0.0, f32::max);
+
+    let mesh_bvh = mesh_bvh(mesh);
+    let to_mesh = reference
+        .triangles
+        .iter()
+        .flat_map(|t| triangle_samples([(*t.a()).into(), (*t.b()).into(), (*t.c()).into()]))
+        .map(|p| mesh_bvh.closest_distance(p))
+        .fold(0.0, f32::max);
+
+    to_reference.max(to_mesh)
+}
+
+/// Points of a triangle on a lattice with [`HAUSDORFF_SUBDIVISIONS`] steps per
+/// edge, vertices included.
+fn triangle_samples([a, b, c]: [Vec3; 3]) -> impl Iterator<Item = Vec3> {
+    let n = HAUSDORFF_SUBDIVISIONS;
+    (0..=n).flat_map(move |i| {
+        (0..=n - i).map(move |j| {
+            let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+            a * (1.0 - u - v) + b * u + c * v
+        })
+    })
+}
+
+/// Shows the coarsest level whose error stays below
+/// [`MeshLods::max_screen_error`] pixels at its distance to the camera.
+pub fn switch_mesh_lods(
+    camera: Single<(&Camera, &Transform, &Projection), With<CameraMarkerPrimary>>,
+    mut lods: Query<(&mut MeshLods, &mut Mesh3d, &Transform)>,
+) {
+    let (camera, camera_transform, projection) = camera.into_inner();
+    let Some(viewport) = camera.logical_viewport_size() else {
+        return;
+    };
+
+    // Pixels covered by one world unit, at distance `d`
+    let pixels_per_unit = |d: f32| match projection {
+        Projection::Perspective(p) => viewport.y / (2.0 * (p.fov * 0.5).tan() * d.max(p.near)),
+        Projection::Orthographic(o) => viewport.y / o.area.height(),
+        _ => f32::INFINITY,
+    };
+
+    for (mut lods, mut mesh, transform) in lods.iter_mut() {
+        let distance = camera_transform.translation.distance(transform.translation);
+        let scale = pixels_per_unit(distance) * transform.scale.max_element();
+
+        let level = (0..lods.levels.len())
+            .rev()
+            .find(|&i| lods.levels[i].hausdorff_error * scale <= lods.max_screen_error)
+            .unwrap_or(0);
+
+        if level != lods.active {
+            lods.active = level;
+            mesh.0 = lods.levels[level].mesh.clone();
+        }
+    }
+}
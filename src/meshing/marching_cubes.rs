@@ -0,0 +1,151 @@
+use super::{IsoMesh, SdfGrid};
+use bevy::prelude::*;
+use std::{collections::HashMap, sync::OnceLock};
+
+/// Cell edges as pairs of corners. Corner `i` sits at offset
+/// `(i & 1, (i >> 1) & 1, (i >> 2) & 1)` from the cell origin.
+pub(super) const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (0, 2),
+    (0, 4),
+    (1, 3),
+    (1, 5),
+    (2, 3),
+    (2, 6),
+    (3, 7),
+    (4, 5),
+    (4, 6),
+    (5, 7),
+    (6, 7),
+];
+
+/// Cell faces as corners in counter-clockwise order seen from outside the cell.
+const FACES: [[usize; 4]; 6] = [
+    [4, 6, 2, 0],
+    [1, 3, 7, 5],
+    [1, 5, 4, 0],
+    [2, 6, 7, 3],
+    [2, 3, 1, 0],
+    [4, 5, 7, 6],
+];
+
+pub(super) fn corner(i: usize) -> UVec3 {
+    UVec3::new(i as u32 & 1, (i as u32 >> 1) & 1, (i as u32 >> 2) & 1)
+}
+
+fn edge_between(a: usize, b: usize) -> usize {
+    let edge = (a.min(b), a.max(b));
+    EDGES.iter().position(|&e| e == edge).unwrap()
+}
+
+/// Triangles of every inside-corner configuration, as cell edges.
+fn triangle_table() -> &'static [Vec<[usize; 3]>; 256] {
+    static TABLE: OnceLock<[Vec<[usize; 3]>; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(cell_triangles))
+}
+
+/// Triangulates one configuration, bit `i` of `config` set if corner `i` is
+/// inside. Instead of the classic hand-written table, the surface is traced
+/// from the face crossings: each face contributes segments that keep its
+/// inside corners on their left, the segments are chained into loops around
+/// the cell and each loop is fanned into triangles.
+///
+/// Faces with two diagonal inside corners are ambiguous. They always separate
+/// the inside corners, which only depends on the face itself, so the two
+/// cells sharing it agree and the surface is closed.
+fn cell_triangles(config: usize) -> Vec<[usize; 3]> {
+    let inside = |corner: usize| config & (1 << corner) != 0;
+
+    // next[edge] is the edge the surface loop continues to.
+    let mut next = [None; 12];
+    for face in FACES {
+        // (edge, whether walking along it enters the inside)
+        let mut crossings = (0..4)
+            .map(|i| (face[i], face[(i + 1) % 4]))
+            .filter(|&(a, b)| inside(a) != inside(b))
+            .map(|(a, b)| (edge_between(a, b), inside(b)))
+            .collect::<Vec<_>>();
+
+        if crossings.is_empty() {
+            continue;
+        }
+
+        // Start at a crossing into the inside, then pair each exit with the
+        // entry before it.
+        let start = crossings.iter().position(|&(_, enters)| enters).unwrap();
+        crossings.rotate_left(start);
+        for pair in crossings.chunks_exact(2) {
+            let [(entry, _), (exit, _)] = [pair[0], pair[1]];
+            next[exit] = Some(entry);
+        }
+    }
+
+    let mut triangles = Vec::new();
+    let mut visited = [false; 12];
+    for start in 0..12 {
+        if visited[start] || next[start].is_none() {
+            continue;
+        }
+
+        let mut polygon = vec![start];
+        visited[start] = true;
+        let mut edge = next[start].unwrap();
+        while edge != start {
+            polygon.push(edge);
+            visited[edge] = true;
+            edge = next[edge].unwrap();
+        }
+
+        // Loops run clockwise seen from outside the surface.
+        for i in 1..polygon.len() - 1 {
+            triangles.push([polygon[0], polygon[i + 1], polygon[i]]);
+        }
+    }
+
+    triangles
+}
+
+/// Extracts the `iso_level` isosurface of the grid. Vertices are shared
+/// between cells, placed by linear interpolation along the cell edges and
+/// given normals from the interpolated distance gradient.
+pub fn marching_cubes(grid: &SdfGrid, iso_level: f32) -> IsoMesh {
+    let table = triangle_table();
+    let cells = grid.size().saturating_sub(1);
+
+    let mut mesh = IsoMesh::default();
+    let mut edge_vertices = HashMap::<(usize, usize), u32>::new();
+
+    for z in 0..cells {
+        for y in 0..cells {
+            for x in 0..cells {
+                let cell = UVec3::new(x, y, z);
+                let values: [f32; 8] =
+                    std::array::from_fn(|i| grid.value(cell + corner(i)) - iso_level);
+                let config = (0..8)
+                    .filter(|&i| values[i] < 0.0)
+                    .fold(0, |config, i| config | (1 << i));
+
+                for triangle in &table[config] {
+                    let indices = triangle.map(|edge| {
+                        let (a, b) = EDGES[edge];
+                        let (voxel_a, voxel_b) = (cell + corner(a), cell + corner(b));
+                        let key = (grid.index(voxel_a), grid.index(voxel_b));
+
+                        *edge_vertices.entry(key).or_insert_with(|| {
+                            let t = values[a] / (values[a] - values[b]);
+                            let position = grid.position(voxel_a).lerp(grid.position(voxel_b), t);
+                            let normal = grid.gradient(voxel_a).lerp(grid.gradient(voxel_b), t);
+
+                            mesh.positions.push(position);
+                            mesh.normals.push(normal.normalize_or_zero());
+                            mesh.positions.len() as u32 - 1
+                        })
+                    });
+                    mesh.indices.extend(indices);
+                }
+            }
+        }
+    }
+
+    mesh
+}
@@ -0,0 +1,74 @@
+use bevy::{
+    mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
+    prelude::*,
+};
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Writes an indexed or non-indexed triangle mesh as Wavefront OBJ, with
+/// normals if the mesh has them. Vertex colours are appended to the vertex
+/// positions as sRGB in `0..1`, the common `v x y z r g b` extension.
+pub fn write_obj(path: &Path, mesh: &Mesh) -> io::Result<()> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return Err(unsupported("only triangle lists can be exported"));
+    }
+
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return Err(unsupported("mesh has no Float32x3 positions"));
+    };
+
+    let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+        Some(VertexAttributeValues::Float32x3(normals)) => Some(normals),
+        _ => None,
+    };
+
+    let colors = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+        Some(VertexAttributeValues::Float32x4(colors)) => Some(colors),
+        _ => None,
+    };
+
+    let indices = match mesh.indices() {
+        Some(Indices::U16(i)) => i.iter().map(|&v| v as usize).collect(),
+        Some(Indices::U32(i)) => i.iter().map(|&v| v as usize).collect(),
+        None => (0..positions.len()).collect::<Vec<_>>(),
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut out = BufWriter::new(fs::File::create(path)?);
+
+    writeln!(out, "# Exported by distill")?;
+    for (i, [x, y, z]) in positions.iter().enumerate() {
+        match colors.map(|colors| Srgba::from(LinearRgba::from_f32_array(colors[i]))) {
+            Some(Srgba {
+                red, green, blue, ..
+            }) => writeln!(out, "v {x} {y} {z} {red} {green} {blue}")?,
+            None => writeln!(out, "v {x} {y} {z}")?,
+        }
+    }
+    for [x, y, z] in normals.into_iter().flatten() {
+        writeln!(out, "vn {x} {y} {z}")?;
+    }
+
+    // OBJ indices are 1-based
+    for face in indices.chunks_exact(3) {
+        let [a, b, c] = [face[0] + 1, face[1] + 1, face[2] + 1];
+        if normals.is_some() {
+            writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        } else {
+            writeln!(out, "f {a} {b} {c}")?;
+        }
+    }
+
+    out.flush()
+}
+
+fn unsupported(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
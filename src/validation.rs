@@ -0,0 +1,541 @@
+use crate::{
+    bvh::{BvhTargetMarker, closest_point_on_triangle},
+    gpu_types::GpuTriangle,
+    headless::HeadlessExit,
+    utils::input_utils::is_modifier,
+    voxelization::{
+        SignedDistanceFieldData, VoxelizationCompleted, VoxelizationData, VoxelizationFailed,
+        VoxelizationSettings, VoxelizeTargetMarker,
+    },
+};
+use bevy::prelude::*;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use strum::IntoEnumIterator;
+
+/// Resolution of the primitives spawned by the in-app validation command.
+const IN_APP_RESOLUTION: u32 = 64;
+
+/// V bakes the primitive gallery and logs how far each bake is from the
+/// analytic distance. Results are also collected in [`ValidationResults`].
+pub struct ValidationPlugin;
+
+impl Plugin for ValidationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ValidationResults>();
+        app.add_systems(
+            Update,
+            (spawn_validation_gallery, validate_baked_primitives),
+        );
+    }
+}
+
+/// Meshed primitives with a closed-form distance function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumIter)]
+#[strum(serialize_all = "snake_case")]
+pub enum ValidationPrimitive {
+    Sphere,
+    Cuboid,
+    Torus,
+    Cone,
+    Tetrahedron,
+    Capsule,
+}
+
+impl ValidationPrimitive {
+    /// Finely tessellated mesh of the primitive, within the triangle capacity
+    /// of the voxelizer. Curved primitives differ from their analytic surface
+    /// by the tessellation error, which stays well below a voxel at the
+    /// supported resolutions.
+    pub fn mesh(&self) -> Mesh {
+        match self {
+            Self::Sphere => Sphere::new(1.0)
+                .mesh()
+                .ico(12)
+                .expect("subdivision count is supported"),
+            Self::Cuboid => Cuboid::new(2.0, 2.0, 2.0).into(),
+            Self::Torus => Torus::new(0.5, 1.0)
+                .mesh()
+                .minor_resolution(24)
+                .major_resolution(64)
+                .build(),
+            Self::Cone => Cone::new(1.0, 3.0).mesh().resolution(256).build(),
+            Self::Tetrahedron => Tetrahedron::default().into(),
+            Self::Capsule => Capsule3d::default()
+                .mesh()
+                .longitudes(64)
+                .latitudes(32)
+                .build(),
+        }
+    }
+
+    /// Exact signed distance from `p` to the primitive, negative inside.
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            Self::Sphere => p.length() - 1.0,
+            Self::Cuboid => {
+                let q = p.abs() - Vec3::ONE;
+                q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+            }
+            Self::Torus => {
+                let torus = Torus::new(0.5, 1.0);
+                let q = Vec2::new(p.xz().length() - torus.major_radius, p.y);
+                q.length() - torus.minor_radius
+            }
+            Self::Cone => capped_cone_distance(p, 1.5, 1.0, 0.0),
+            Self::Tetrahedron => tetrahedron_distance(&Tetrahedron::default(), p),
+            Self::Capsule => {
+                let capsule = Capsule3d::default();
+                let y = p.y.clamp(-capsule.half_length, capsule.half_length);
+                (p - Vec3::Y * y).length() - capsule.radius
+            }
+        }
+    }
+}
+
+/// Cone frustum along y with half height `h`, radius `r1` at the bottom and
+/// `r2` at the top, after Inigo Quilez.
+fn capped_cone_distance(p: Vec3, h: f32, r1: f32, r2: f32) -> f32 {
+    let q = Vec2::new(p.xz().length(), p.y);
+    let k1 = Vec2::new(r2, h);
+    let k2 = Vec2::new(r2 - r1, 2.0 * h);
+    let cap_radius = if q.y < 0.0 { r1 } else { r2 };
+    let ca = Vec2::new(q.x - q.x.min(cap_radius), q.y.abs() - h);
+    let cb = q - k1 + k2 * ((k1 - q).dot(k2) / k2.length_squared()).clamp(0.0, 1.0);
+    let sign = if cb.x < 0.0 && ca.y < 0.0 { -1.0 } else { 1.0 };
+    sign * ca.length_squared().min(cb.length_squared()).sqrt()
+}
+
+/// Distance to the closest face, negative if `p` is on the inner side of all
+/// four face planes.
+fn tetrahedron_distance(tetrahedron: &Tetrahedron, p: Vec3) -> f32 {
+    let v = tetrahedron.vertices;
+    let faces = [(0, 1, 2, 3), (0, 1, 3, 2), (0, 2, 3, 1), (1, 2, 3, 0)];
+
+    let mut inside = true;
+    let mut distance = f32::INFINITY;
+    for (a, b, c, opposite) in faces {
+        let normal = (v[b] - v[a]).cross(v[c] - v[a]);
+        inside &= normal.dot(p - v[a]).signum() == normal.dot(v[opposite] - v[a]).signum();
+
+        let n = normal.normalize().into();
+        let triangle = GpuTriangle::new(v[a].into(), v[b].into(), v[c].into(), n, n, n);
+        distance = distance.min(closest_point_on_triangle(&triangle, p).distance(p));
+    }
+
+    if inside { -distance } else { distance }
+}
+
+/// Marks a bake of a primitive whose result is compared against
+/// [`ValidationPrimitive::distance`].
+#[derive(Debug, Clone, Copy, Component)]
+pub struct ValidationTarget(pub ValidationPrimitive);
+
+/// Difference between a bake and the analytic distance over all voxels.
+#[derive(Debug, Clone, Copy)]
+pub struct ValidationResult {
+    pub primitive: ValidationPrimitive,
+    pub resolution: u32,
+    /// Largest voxel edge, the unit of the relative errors.
+    pub voxel_size: f32,
+    pub max_error: f32,
+    pub rms_error: f32,
+    /// Voxels more than a voxel away from the surface with the wrong sign.
+    /// Closer ones may flip with the tessellation and are not counted.
+    pub sign_mismatches: usize,
+}
+
+impl ValidationResult {
+    /// Compares the base level of a bake against the analytic distance at
+    /// every voxel center. `None` if the voxels are not on the CPU.
+    pub fn compute(
+        primitive: ValidationPrimitive,
+        data: &SignedDistanceFieldData,
+        images: &Assets<Image>,
+    ) -> Option<Self> {
+        let voxels = data.read_voxels(images)?;
+        let size = data.volume.grid_size;
+        let voxel_size = data.voxel_size();
+
+        let mut max_error = 0.0f32;
+        let mut squared_error = 0.0f64;
+        let mut sign_mismatches = 0;
+        for (index, &baked) in voxels.iter().enumerate() {
+            let index = index as u32;
+            let voxel = UVec3::new(index % size, (index / size) % size, index / (size * size));
+            let center = data.volume.voxel_center(voxel);
+            let expected = primitive.distance(center);
+
+            let error = (baked - expected).abs();
+            max_error = max_error.max(error);
+            squared_error += (error as f64).powi(2);
+            if expected.abs() > voxel_size.max_element() && baked.signum() != expected.signum() {
+                sign_mismatches += 1;
+            }
+        }
+
+        Some(Self {
+            primitive,
+            resolution: size,
+            voxel_size: voxel_size.max_element(),
+            max_error,
+            rms_error: (squared_error / voxels.len().max(1) as f64).sqrt() as f32,
+            sign_mismatches,
+        })
+    }
+
+    pub fn rms_error_voxels(&self) -> f32 {
+        self.rms_error / self.voxel_size
+    }
+
+    pub fn max_error_voxels(&self) -> f32 {
+        self.max_error / self.voxel_size
+    }
+}
+
+/// Validation results in the order the bakes finished.
+#[derive(Debug, Default, Resource)]
+pub struct ValidationResults(pub Vec<ValidationResult>);
+
+impl ValidationResults {
+    const CSV_HEADER: &str = "primitive,resolution,voxel_size,max_error,rms_error,max_error_voxels,rms_error_voxels,sign_mismatches";
+
+    /// Writes the error-versus-resolution curves as CSV, one row per bake
+    /// sorted by primitive and resolution.
+    pub fn write_csv(&self, path: &Path) -> io::Result<()> {
+        let mut results = self.0.clone();
+        results.sort_by_key(|result| (result.primitive as u8, result.resolution));
+
+        let rows = results.iter().map(|r| {
+            format!(
+                "{},{},{},{},{},{},{},{}",
+                r.primitive,
+                r.resolution,
+                r.voxel_size,
+                r.max_error,
+                r.rms_error,
+                r.max_error_voxels(),
+                r.rms_error_voxels(),
+                r.sign_mismatches
+            )
+        });
+        let csv = std::iter::once(Self::CSV_HEADER.to_string())
+            .chain(rows)
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, csv + "\n")
+    }
+}
+
+/// Spawns a bake of `primitive` at `resolution` voxels per axis.
+pub fn spawn_validation_target<'a>(
+    commands: &'a mut Commands,
+    meshes: &mut Assets<Mesh>,
+    primitive: ValidationPrimitive,
+    resolution: u32,
+) -> EntityCommands<'a> {
+    commands.spawn((
+        VoxelizeTargetMarker,
+        BvhTargetMarker,
+        ValidationTarget(primitive),
+        VoxelizationSettings {
+            resolution,
+            ..default()
+        },
+        Mesh3d(meshes.add(primitive.mesh())),
+    ))
+}
+
+fn spawn_validation_gallery(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !input.just_pressed(KeyCode::KeyV) || input.get_pressed().any(|key| is_modifier(*key)) {
+        return;
+    }
+
+    info!("Baking the validation primitives at {IN_APP_RESOLUTION} voxels.");
+    let material = materials.add(StandardMaterial {
+        base_color: Color::linear_rgba(0.0, 0.4, 1.0, 1.0),
+        ..default()
+    });
+    for (i, primitive) in ValidationPrimitive::iter().enumerate() {
+        spawn_validation_target(&mut commands, &mut meshes, primitive, IN_APP_RESOLUTION).insert((
+            MeshMaterial3d(material.clone()),
+            Transform::from_xyz(3.0 * i as f32 - 7.5, 0.0, 6.0),
+        ));
+    }
+}
+
+/// Compares finished validation bakes against the analytic distance.
+fn validate_baked_primitives(
+    mut completed: MessageReader<VoxelizationCompleted>,
+    targets: Query<(&ValidationTarget, &VoxelizationData)>,
+    images: Res<Assets<Image>>,
+    mut results: ResMut<ValidationResults>,
+) {
+    for completed in completed.read() {
+        let Ok((&ValidationTarget(primitive), voxel_data)) = targets.get(completed.entity) else {
+            continue;
+        };
+
+        let Some(result) = voxel_data
+            .data()
+            .and_then(|data| ValidationResult::compute(primitive, data, &images))
+        else {
+            warn!("Bake of {primitive} has no CPU-accessible data to validate.");
+            continue;
+        };
+
+        info!(
+            resolution = result.resolution,
+            max_error_voxels = result.max_error_voxels(),
+            rms_error_voxels = result.rms_error_voxels(),
+            sign_mismatches = result.sign_mismatches,
+            "Validated {primitive}."
+        );
+        results.0.push(result);
+    }
+}
+
+/// Headless validation of every primitive at several resolutions.
+#[derive(Debug, Resource)]
+pub struct ValidationRun {
+    pub resolutions: Vec<u32>,
+    /// Largest RMS error in voxels that passes.
+    pub max_rms_voxels: f32,
+    /// Where to write the error curves as CSV, if anywhere.
+    pub report: Option<PathBuf>,
+    failures: usize,
+}
+
+impl ValidationRun {
+    pub fn new(resolutions: Vec<u32>, max_rms_voxels: f32, report: Option<PathBuf>) -> Self {
+        Self {
+            resolutions,
+            max_rms_voxels,
+            report,
+            failures: 0,
+        }
+    }
+
+    fn bake_count(&self) -> usize {
+        self.resolutions.len() * ValidationPrimitive::iter().count()
+    }
+}
+
+pub fn spawn_validation_bakes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    run: Res<ValidationRun>,
+) {
+    for primitive in ValidationPrimitive::iter() {
+        for &resolution in &run.resolutions {
+            spawn_validation_target(&mut commands, &mut meshes, primitive, resolution);
+        }
+    }
+}
+
+/// Logs the error curves once every bake is validated and exits with
+/// [`HeadlessExit::Validation`] if any bake failed, has sign mismatches or
+/// exceeds [`ValidationRun::max_rms_voxels`].
+pub fn finish_validation(
+    mut run: ResMut<ValidationRun>,
+    results: Res<ValidationResults>,
+    mut failed: MessageReader<VoxelizationFailed>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    run.failures += failed.read().count();
+    if results.0.len() + run.failures < run.bake_count() {
+        return;
+    }
+
+    let mut passed = run.failures == 0;
+    for primitive in ValidationPrimitive::iter() {
+        let mut curve = results
+            .0
+            .iter()
+            .filter(|result| result.primitive == primitive)
+            .collect::<Vec<_>>();
+        curve.sort_by_key(|result| result.resolution);
+
+        for result in curve {
+            let ok = result.sign_mismatches == 0 && result.rms_error_voxels() <= run.max_rms_voxels;
+            passed &= ok;
+            info!(
+                "{primitive:>12} {:>4}³  rms {:.4} ({:.3} voxels)  max {:.4} ({:.3} voxels)  sign mismatches {}{}",
+                result.resolution,
+                result.rms_error,
+                result.rms_error_voxels(),
+                result.max_error,
+                result.max_error_voxels(),
+                result.sign_mismatches,
+                if ok { "" } else { "  FAILED" }
+            );
+        }
+    }
+
+    if let Some(report) = &run.report
+        && let Err(e) = results.write_csv(report)
+    {
+        error!("Failed to write validation report {report:?}: {e}");
+        exit.write(HeadlessExit::Write.into());
+        return;
+    }
+
+    if passed {
+        exit.write(AppExit::Success);
+    } else {
+        error!("Validation failed.");
+        exit.write(HeadlessExit::Validation.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gpu_types::GpuBox3,
+        voxelization::{
+            SdfVolume,
+            storage_format::{SdfEncoding, SdfStorageFormat},
+        },
+    };
+    use bevy::{
+        asset::RenderAssetUsages,
+        render::render_resource::{Extent3d, TextureDimension},
+    };
+
+    const SIZE: u32 = 8;
+
+    /// A baked field of `primitive` whose voxels hold `bake` of the analytic
+    /// distance at their centers.
+    fn baked_field(
+        primitive: ValidationPrimitive,
+        images: &mut Assets<Image>,
+        bake: impl Fn(f32) -> f32,
+    ) -> SignedDistanceFieldData {
+        let volume = SdfVolume::new(
+            SIZE,
+            GpuBox3::new(Vec3::splat(-2.0).into(), Vec3::splat(2.0).into()),
+            0.0,
+        );
+        let voxels = (0..volume.voxel_count() as u32)
+            .map(|i| UVec3::new(i % SIZE, (i / SIZE) % SIZE, i / (SIZE * SIZE)))
+            .map(|voxel| bake(primitive.distance(volume.voxel_center(voxel))))
+            .collect::<Vec<_>>();
+
+        let encoding = SdfEncoding::new(SdfStorageFormat::R32Float, 1.0);
+        let image = Image::new(
+            Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth_or_array_layers: SIZE,
+            },
+            TextureDimension::D3,
+            encoding.encode(&voxels),
+            encoding.format.texture_format(),
+            RenderAssetUsages::MAIN_WORLD,
+        );
+        SignedDistanceFieldData {
+            signed_distance_field: images.add(image),
+            volume,
+            mip_count: 1,
+            encoding,
+            closest_features: None,
+            albedo: None,
+            occupancy: None,
+        }
+    }
+
+    #[test]
+    fn analytic_distances_match_known_points() {
+        let cases = [
+            (ValidationPrimitive::Sphere, Vec3::ZERO, -1.0),
+            (ValidationPrimitive::Sphere, Vec3::new(0.0, 3.0, 0.0), 2.0),
+            (ValidationPrimitive::Cuboid, Vec3::ZERO, -1.0),
+            (ValidationPrimitive::Cuboid, Vec3::new(2.0, 0.0, 0.0), 1.0),
+            (
+                ValidationPrimitive::Cuboid,
+                Vec3::new(2.0, 2.0, 0.0),
+                2.0f32.sqrt(),
+            ),
+            (ValidationPrimitive::Torus, Vec3::new(0.75, 0.0, 0.0), -0.25),
+            (ValidationPrimitive::Torus, Vec3::ZERO, 0.5),
+            (ValidationPrimitive::Cone, Vec3::new(0.0, -2.0, 0.0), 0.5),
+            (ValidationPrimitive::Cone, Vec3::new(0.0, 2.5, 0.0), 1.0),
+            (ValidationPrimitive::Capsule, Vec3::ZERO, -0.5),
+            (ValidationPrimitive::Capsule, Vec3::new(0.0, 2.0, 0.0), 1.0),
+        ];
+        for (primitive, p, expected) in cases {
+            let distance = primitive.distance(p);
+            assert!(
+                (distance - expected).abs() < 1e-5,
+                "{primitive} at {p}: {distance}, expected {expected}"
+            );
+        }
+
+        let tetrahedron = Tetrahedron::default();
+        let distance = ValidationPrimitive::Tetrahedron.distance(tetrahedron.centroid());
+        assert!(
+            distance < 0.0,
+            "tetrahedron centroid is outside: {distance}"
+        );
+    }
+
+    #[test]
+    fn mesh_vertices_lie_on_the_analytic_surface() {
+        for primitive in ValidationPrimitive::iter() {
+            let mesh = primitive.mesh();
+            let positions = mesh
+                .attribute(Mesh::ATTRIBUTE_POSITION)
+                .and_then(|positions| positions.as_float3())
+                .expect("primitive meshes have float positions");
+            for &p in positions {
+                let distance = primitive.distance(Vec3::from(p));
+                assert!(
+                    distance.abs() < 1e-4,
+                    "{primitive} vertex {p:?} is {distance} off the surface"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn offset_bake_reports_the_offset() {
+        let mut images = Assets::<Image>::default();
+        let data = baked_field(ValidationPrimitive::Sphere, &mut images, |d| d + 0.1);
+        let result =
+            ValidationResult::compute(ValidationPrimitive::Sphere, &data, &images).unwrap();
+
+        assert_eq!(result.resolution, SIZE);
+        assert_eq!(result.voxel_size, 0.5);
+        assert!((result.max_error - 0.1).abs() < 1e-5, "{result:?}");
+        assert!((result.rms_error - 0.1).abs() < 1e-5, "{result:?}");
+        assert!((result.rms_error_voxels() - 0.2).abs() < 1e-4, "{result:?}");
+        assert_eq!(result.sign_mismatches, 0);
+    }
+
+    #[test]
+    fn flipped_bake_counts_sign_mismatches_away_from_the_surface() {
+        let primitive = ValidationPrimitive::Cuboid;
+        let mut images = Assets::<Image>::default();
+        let data = baked_field(primitive, &mut images, |d| -d);
+        let result = ValidationResult::compute(primitive, &data, &images).unwrap();
+
+        let far_voxels = (0..data.volume.voxel_count() as u32)
+            .map(|i| UVec3::new(i % SIZE, (i / SIZE) % SIZE, i / (SIZE * SIZE)))
+            .filter(|&voxel| primitive.distance(data.volume.voxel_center(voxel)).abs() > 0.5)
+            .count();
+        assert!(far_voxels > 0);
+        assert_eq!(result.sign_mismatches, far_voxels);
+    }
+}
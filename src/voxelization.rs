@@ -1,11 +1,39 @@
-use crate::voxelization::{raymarch_material::RaymarchMaterialExtension, snapshot::SnapshotType};
-use bevy::{pbr::ExtendedMaterial, prelude::*};
+use crate::{
+    bvh::BvhError,
+    gpu_types::GpuBox3,
+    voxelization::{
+        occupancy::OccupancyFormat,
+        raymarch_material::RaymarchMaterialExtension,
+        snapshot::SnapshotType,
+        storage_format::{SdfEncoding, SdfStorageFormat},
+        volume_io::SignConvention,
+        voxelization_worker::SIZE,
+    },
+};
+use bevy::{
+    pbr::ExtendedMaterial,
+    platform::time::Instant,
+    prelude::*,
+    render::{ExtractSchedule, Render, RenderApp, RenderSystems},
+};
 use bevy_app_compute::prelude as compute;
+use std::{fmt, time::Duration};
 
+mod albedo;
+pub mod csg;
+pub mod csg_worker;
+mod failure;
+mod invalidation;
+pub mod occupancy;
+pub mod quality;
 mod raymarch;
 pub mod raymarch_material;
 mod raymarch_systems;
+pub mod sculpt;
+pub mod sculpt_worker;
 mod snapshot;
+pub mod storage_format;
+pub mod volume_io;
 mod voxelization_systems;
 pub mod voxelization_worker;
 
@@ -13,16 +41,55 @@ pub struct VoxelizationPlugin;
 
 impl Plugin for VoxelizationPlugin {
     fn build(&self, app: &mut App) {
+        // The voxelization workers size their slabs by the policy.
+        app.init_resource::<VoxelizationPolicy>();
         app.add_plugins((
             compute::AppComputePlugin,
             compute::AppComputeWorkerPlugin::<voxelization_worker::VoxelizationWorker>::default(),
+            compute::AppComputeWorkerPlugin::<voxelization_worker::ClosestFeatureWorker>::default(),
+            compute::AppComputeWorkerPlugin::<csg_worker::CsgWorker>::default(),
+            compute::AppComputeWorkerPlugin::<sculpt_worker::SculptWorker>::default(),
             MaterialPlugin::<ExtendedMaterial<StandardMaterial, RaymarchMaterialExtension>>::default()
         ));
 
+        app.add_message::<RebakeRequest>();
+        app.add_message::<VoxelizationStarted>();
+        app.add_message::<VoxelizationCompleted>();
+        app.add_message::<VoxelizationFailed>();
+        app.add_message::<volume_io::VolumeExported>();
+        app.init_resource::<voxelization_systems::BakeSlabs>();
+        app.init_resource::<csg::CsgRun>();
+        app.init_resource::<failure::DeviceLoss>();
+        app.add_systems(Startup, failure::watch_device_loss);
         app.add_systems(
             Update,
             (
+                failure::fail_invalid_meshes,
+                failure::fail_stalled_jobs,
+                failure::handle_failed_jobs,
+            )
+                .chain()
+                .after(voxelization_systems::queue_voxelization)
+                .after(csg::queue_csg),
+        );
+        app.add_systems(
+            Update,
+            (
+                invalidation::request_rebake_on_change,
+                invalidation::request_rebake_on_key,
+                invalidation::process_rebake_requests,
+            )
+                .chain()
+                .before(voxelization_systems::extract_voxelization_data),
+        );
+
+        app.add_systems(
+            Update,
+            (
+                volume_io::load_baked_volumes,
                 voxelization_systems::extract_voxelization_data,
+                albedo::bake_albedo_volumes,
+                volume_io::export_requested_volumes,
                 voxelization_systems::queue_voxelization,
                 raymarch_systems::spawn_raymarch_render_targets,
                 raymarch_systems::update_raymarch_materials,
@@ -30,10 +97,53 @@ impl Plugin for VoxelizationPlugin {
                 .chain(),
         );
 
+        app.add_systems(
+            Update,
+            (
+                csg::invalidate_csg_results,
+                csg::extract_csg_results,
+                csg::queue_csg,
+            )
+                .chain()
+                .after(voxelization_systems::extract_voxelization_data),
+        );
+
+        app.init_resource::<sculpt::SculptBrush>();
+        app.init_resource::<sculpt::SculptHistory>();
+        app.init_resource::<sculpt::PendingDab>();
+        app.init_resource::<sculpt::SculptCache>();
+        app.init_resource::<sculpt::SculptUploads>();
+        app.add_systems(
+            Update,
+            (
+                sculpt::invalidate_sculpt_cache,
+                sculpt::sculpt_controls,
+                sculpt::extract_sculpt_results,
+                sculpt::apply_sculpt_brush,
+                sculpt::sync_sculpted_images,
+            )
+                .chain()
+                .after(raymarch_systems::update_raymarch_materials),
+        );
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<sculpt::SculptUploads>()
+                .add_systems(ExtractSchedule, sculpt::extract_sculpt_uploads)
+                .add_systems(
+                    Render,
+                    sculpt::upload_sculpt_regions.in_set(RenderSystems::PrepareResources),
+                );
+        }
+
         app.init_state::<SnapshotType>();
         app.add_systems(
             Update,
-            (snapshot::snapshotter, snapshot::cycle_snapshot_type),
+            (
+                snapshot::snapshotter,
+                snapshot::cycle_snapshot_type,
+                volume_io::exporter,
+                csg::csg_cycler,
+            ),
         );
     }
 }
@@ -41,20 +151,372 @@ impl Plugin for VoxelizationPlugin {
 #[derive(Debug, Clone, Component)]
 pub struct VoxelizeTargetMarker;
 
+/// Per-entity bake settings. Entities without this component are baked with
+/// [`VoxelizationSettings::default`]. Changing it re-bakes the entity.
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct VoxelizationSettings {
+    /// Grid resolution along each axis. Clamped to the worker's capacity.
+    pub resolution: u32,
+    /// Padding added around the mesh bounds, relative to the mesh extent.
+    pub padding_ratio: f32,
+    /// Also bake the closest-feature volumes, see [`ClosestFeatureData`].
+    pub closest_features: bool,
+    /// Also bake a colour volume from the source material. Implies
+    /// `closest_features`.
+    pub albedo: bool,
+    /// Texel format of the baked distance field.
+    pub storage_format: SdfStorageFormat,
+    /// Half-width of the band kept by the normalized storage formats, in
+    /// voxels. Distances beyond it saturate.
+    pub band_width: f32,
+    /// How the distances to the surface are signed.
+    pub sign_mode: SignMode,
+    /// Also store the fraction of every voxel inside the mesh, see
+    /// [`SignedDistanceFieldData::occupancy`]. Ignored for unsigned fields.
+    pub occupancy: Option<OccupancyFormat>,
+}
+
+/// How the voxelizer signs distances. Only closed meshes have an inside, so
+/// single-sided geometry like cloth or leaves needs one of the other modes.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SignMode {
+    /// Negative inside, by the parity of ray crossings.
+    #[default]
+    Parity,
+    /// Plain distance to the surface, never negative.
+    Unsigned,
+    /// Signed field of the surface thickened to a shell of `thickness`, in
+    /// mesh-local units like the field itself, so the shell scales with the
+    /// entity. Negative within `thickness / 2` of the surface.
+    ThinShell { thickness: f32 },
+}
+
+impl Default for VoxelizationSettings {
+    fn default() -> Self {
+        Self {
+            resolution: SIZE,
+            padding_ratio: 0.05,
+            closest_features: false,
+            albedo: false,
+            storage_format: SdfStorageFormat::R32Float,
+            band_width: 4.0,
+            sign_mode: SignMode::Parity,
+            occupancy: None,
+        }
+    }
+}
+
+/// Requests that the derived BVH and voxelization data of `entity` be thrown
+/// away and rebuilt from its current mesh and settings.
+#[derive(Debug, Clone, Copy, Message)]
+pub struct RebakeRequest {
+    pub entity: Entity,
+}
+
+/// Sent when a bake, CSG evaluation or volume load of `entity` starts.
+#[derive(Debug, Clone, Copy, Message)]
+pub struct VoxelizationStarted {
+    pub entity: Entity,
+    pub settings: VoxelizationSettings,
+}
+
+/// Sent when the distance field of `entity` is available, `duration` after
+/// the matching [`VoxelizationStarted`].
+#[derive(Debug, Clone, Copy, Message)]
+pub struct VoxelizationCompleted {
+    pub entity: Entity,
+    pub settings: VoxelizationSettings,
+    pub duration: Duration,
+}
+
+/// Sent when a job for `entity` ended without a distance field and will not
+/// be retried.
+#[derive(Debug, Clone, Message)]
+pub struct VoxelizationFailed {
+    pub entity: Entity,
+    pub settings: VoxelizationSettings,
+    pub duration: Duration,
+    pub error: VoxelizationError,
+}
+
+/// Time limit and retry policy of voxelization jobs.
+#[derive(Debug, Clone, Resource)]
+pub struct VoxelizationPolicy {
+    /// Jobs still in progress after this long fail with
+    /// [`VoxelizationError::Timeout`].
+    pub timeout: Duration,
+    /// How often a job that failed for a transient reason is started again
+    /// before it is reported, see [`VoxelizationError::is_transient`].
+    pub max_retries: u32,
+    /// Workgroups of 8³ voxels a bake may dispatch per frame. Larger grids
+    /// are voxelized in Z-slabs over several frames, keeping each dispatch
+    /// short enough not to stall rendering or trip the GPU watchdog. Read
+    /// once, when the voxelization worker is built.
+    pub workgroups_per_frame: u32,
+}
+
+impl Default for VoxelizationPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_retries: 2,
+            workgroups_per_frame: 1024,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum VoxelizationState {
     #[default]
     InProgress,
     Computed,
+    Failed(VoxelizationError),
 }
 
+/// Why a voxelization job ended without a distance field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoxelizationError {
+    /// The source mesh cannot be turned into a BVH.
+    InvalidMesh(BvhError),
+    /// The input needs more elements than a worker buffer holds.
+    BufferOverflow {
+        buffer: String,
+        required: usize,
+        capacity: usize,
+    },
+    /// A read-back buffer is smaller than the output of the job.
+    ReadbackSize {
+        buffer: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// The job was still in progress after [`VoxelizationPolicy::timeout`].
+    Timeout(Duration),
+    /// The render device was lost, with the reason reported by the driver.
+    DeviceLost(String),
+    /// A baked volume file could not be read.
+    Load(String),
+}
+
+impl VoxelizationError {
+    /// Whether starting the job again may succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Timeout(_))
+    }
+}
+
+impl fmt::Display for VoxelizationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMesh(e) => write!(f, "invalid mesh: {e}"),
+            Self::BufferOverflow {
+                buffer,
+                required,
+                capacity,
+            } => write!(
+                f,
+                "{buffer} needs {required} elements but the worker holds {capacity}"
+            ),
+            Self::ReadbackSize {
+                buffer,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "read back {actual} bytes of {buffer}, expected at least {expected}"
+            ),
+            Self::Timeout(duration) => write!(f, "timed out after {duration:?}"),
+            Self::DeviceLost(reason) => write!(f, "render device lost: {reason}"),
+            Self::Load(e) => write!(f, "failed to load volume: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for VoxelizationError {}
+
 #[derive(Debug, Clone)]
 pub struct SignedDistanceFieldData {
     pub signed_distance_field: Handle<Image>,
+    /// Where the voxels of `signed_distance_field` sit in mesh-local space.
+    pub volume: SdfVolume,
+    /// Number of mip levels stored in the image, including the base level.
+    /// Coarser levels never overestimate the distance.
+    pub mip_count: u32,
+    /// How the values of `signed_distance_field` map to distances.
+    pub encoding: SdfEncoding,
+    /// Closest-feature volumes, present if requested in [`VoxelizationSettings`].
+    pub closest_features: Option<ClosestFeatureData>,
+    /// `Rgba8UnormSrgb` colour volume transferred from the source material,
+    /// present once baked if requested in [`VoxelizationSettings`].
+    pub albedo: Option<Handle<Image>>,
+    /// Solid fraction of every voxel in `[0, 1]`, on the grid of the base
+    /// level without mips. Present if requested in [`VoxelizationSettings`].
+    /// Sculpting leaves it as baked.
+    pub occupancy: Option<Handle<Image>>,
+}
+
+impl SignedDistanceFieldData {
+    pub fn voxel_size(&self) -> Vec3 {
+        self.volume.voxel_size()
+    }
+
+    /// Decodes the base level of the distance field to distances, if the image
+    /// is loaded and has CPU-accessible data.
+    pub fn read_voxels(&self, images: &Assets<Image>) -> Option<Vec<f32>> {
+        let data = images.get(&self.signed_distance_field)?.data.as_ref()?;
+        let base_len = self.volume.voxel_count() * self.encoding.format.bytes_per_voxel();
+        Some(self.encoding.decode(data.get(..base_len)?))
+    }
+}
+
+/// Placement of a distance grid in mesh-local space. Everything that maps
+/// between voxels and mesh space, from the raymarcher to the exporters,
+/// reads it from here instead of recomputing it from the mesh.
+#[derive(Debug, Clone, Copy)]
+pub struct SdfVolume {
+    /// Voxels along each axis. Grids are cubic.
+    pub grid_size: u32,
+    /// Mesh-local bounds of the padded grid. Voxel centers sit at the centers
+    /// of `grid_size`³ equal cells of this box.
+    pub bounds: GpuBox3,
+    /// Padding that was added around the mesh bounds, relative to its extent.
+    pub padding_ratio: f32,
+    /// Side of the surface the stored values are negative on. Fields in
+    /// memory are always negative inside, files may differ.
+    pub sign_convention: SignConvention,
+}
+
+impl SdfVolume {
+    /// A negative-inside grid of `grid_size`³ voxels spanning `bounds`.
+    pub fn new(grid_size: u32, bounds: GpuBox3, padding_ratio: f32) -> Self {
+        Self {
+            grid_size,
+            bounds,
+            padding_ratio,
+            sign_convention: SignConvention::NegativeInside,
+        }
+    }
+
+    /// The grid the voxelizer samples around `mesh_bounds`, grown by
+    /// `padding_ratio` of their extent on every side.
+    pub fn padded(grid_size: u32, mesh_bounds: &GpuBox3, padding_ratio: f32) -> Self {
+        let padding = Vec3::from(mesh_bounds.size()) * padding_ratio;
+        let bounds = GpuBox3::new(
+            (Vec3::from(*mesh_bounds.min()) - padding).into(),
+            (Vec3::from(*mesh_bounds.max()) + padding).into(),
+        );
+        Self::new(grid_size, bounds, padding_ratio)
+    }
+
+    pub fn dimensions(&self) -> UVec3 {
+        UVec3::splat(self.grid_size)
+    }
+
+    pub fn voxel_count(&self) -> usize {
+        (self.grid_size as usize).pow(3)
+    }
+
+    pub fn min(&self) -> Vec3 {
+        Vec3::from(*self.bounds.min())
+    }
+
+    pub fn max(&self) -> Vec3 {
+        Vec3::from(*self.bounds.max())
+    }
+
+    /// Size of a voxel along each axis. Differs between axes unless the
+    /// bounds are a cube.
+    pub fn voxel_size(&self) -> Vec3 {
+        Vec3::from(self.bounds.size()) / self.grid_size as f32
+    }
+
+    /// Mesh-local position of the center of `voxel`.
+    pub fn voxel_center(&self, voxel: UVec3) -> Vec3 {
+        self.min() + (voxel.as_vec3() + 0.5) * self.voxel_size()
+    }
+
+    /// Continuous voxel coordinates of a mesh-local point, with voxel centers
+    /// at whole numbers.
+    pub fn to_voxel(&self, p: Vec3) -> Vec3 {
+        (p - self.min()) / self.voxel_size() - 0.5
+    }
+}
+
+/// Per-voxel attributes of the closest point on the mesh surface. All volumes
+/// share the grid of the signed distance field and use nearest sampling.
+#[derive(Debug, Clone)]
+pub struct ClosestFeatureData {
+    /// `Rgba32Float`, offset from the voxel center to the closest point in `xyz`.
+    pub closest_point_offset: Handle<Image>,
+    /// `Rgba32Float`, vertex normal interpolated at the closest point in `xyz`.
+    pub closest_normal: Handle<Image>,
+    /// `R32Uint`, index of the closest triangle in [`crate::bvh::BvhData::triangles`].
+    pub closest_triangle: Handle<Image>,
+    /// `Rgba32Float`, barycentric coordinates of the closest point in `xyz`.
+    pub closest_barycentric: Handle<Image>,
 }
 
 #[derive(Debug, Clone, Component)]
 pub struct VoxelizationData {
     state: VoxelizationState,
+    settings: VoxelizationSettings,
     data: Option<SignedDistanceFieldData>,
+    /// Fraction of the dispatches completed, `1.0` once computed.
+    progress: f32,
+    started: Instant,
+}
+
+impl VoxelizationData {
+    fn in_progress(settings: VoxelizationSettings, data: Option<SignedDistanceFieldData>) -> Self {
+        Self {
+            state: VoxelizationState::InProgress,
+            settings,
+            data,
+            progress: 0.0,
+            started: Instant::now(),
+        }
+    }
+
+    fn failed(settings: VoxelizationSettings, error: VoxelizationError) -> Self {
+        Self {
+            state: VoxelizationState::Failed(error),
+            ..Self::in_progress(settings, None)
+        }
+    }
+
+    /// Marks the job done and returns the time since it started.
+    fn complete(&mut self) -> Duration {
+        self.state = VoxelizationState::Computed;
+        self.progress = 1.0;
+        self.started.elapsed()
+    }
+
+    fn fail(&mut self, error: VoxelizationError) {
+        self.state = VoxelizationState::Failed(error);
+        self.data = None;
+    }
+
+    /// Fraction of the job done in `[0, 1]`. Bakes advance with every
+    /// completed Z-slab, other jobs jump from `0.0` to `1.0`.
+    pub fn progress(&self) -> f32 {
+        self.progress
+    }
+
+    /// Time since the job started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn state(&self) -> &VoxelizationState {
+        &self.state
+    }
+
+    pub fn settings(&self) -> &VoxelizationSettings {
+        &self.settings
+    }
+
+    /// The baked field, once the state is [`VoxelizationState::Computed`].
+    pub fn data(&self) -> Option<&SignedDistanceFieldData> {
+        self.data.as_ref()
+    }
 }
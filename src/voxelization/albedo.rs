@@ -0,0 +1,160 @@
+use crate::{
+    bvh::BvhData,
+    voxelization::{VoxelizationData, VoxelizationState, VoxelizeTargetMarker},
+};
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use tracing::instrument;
+
+/// Bakes an RGBA colour volume for every computed target that requested one,
+/// by sampling the source `StandardMaterial` at the closest surface point of
+/// each voxel. Waits until the material's base colour texture is loaded.
+#[allow(clippy::type_complexity)]
+#[instrument(skip_all)]
+pub(super) fn bake_albedo_volumes(
+    mut images: ResMut<Assets<Image>>,
+    materials: Res<Assets<StandardMaterial>>,
+    mut query: Query<
+        (
+            Entity,
+            &mut VoxelizationData,
+            &BvhData,
+            Option<&MeshMaterial3d<StandardMaterial>>,
+        ),
+        With<VoxelizeTargetMarker>,
+    >,
+) {
+    for (entity, mut voxel_data, bvh_data, material_handle) in query.iter_mut() {
+        if voxel_data.state != VoxelizationState::Computed || !voxel_data.settings.albedo {
+            continue;
+        }
+
+        let Some(sdf_data) = &voxel_data.data else {
+            continue;
+        };
+
+        if sdf_data.albedo.is_some() {
+            continue;
+        }
+
+        let Some(features) = &sdf_data.closest_features else {
+            error!("Entity {entity:?} requested an albedo volume but has no closest-feature data!");
+            continue;
+        };
+
+        let material = material_handle.and_then(|handle| materials.get(handle));
+        let base_color = material
+            .map(|material| material.base_color.to_linear())
+            .unwrap_or(LinearRgba::WHITE);
+
+        let texture = match material.and_then(|material| material.base_color_texture.as_ref()) {
+            Some(handle) => match images.get(handle) {
+                Some(image) => Some(image),
+                None => {
+                    trace!("Base colour texture of entity {entity:?} is not loaded yet.");
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let (Some(triangle_image), Some(barycentric_image)) = (
+            images.get(&features.closest_triangle),
+            images.get(&features.closest_barycentric),
+        ) else {
+            error!("Closest-feature images for entity {entity:?} not found in Assets<Image>!");
+            continue;
+        };
+
+        let (Some(triangle_data), Some(barycentric_data)) =
+            (&triangle_image.data, &barycentric_image.data)
+        else {
+            error!("Closest-feature images for entity {entity:?} have no CPU-accessible data.");
+            continue;
+        };
+
+        let albedo = transfer_albedo(
+            bytemuck::cast_slice(triangle_data),
+            bytemuck::cast_slice(barycentric_data),
+            bvh_data.uvs.as_deref(),
+            base_color,
+            texture,
+        );
+
+        let size = sdf_data.volume.grid_size;
+        let image = Image::new(
+            Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: size,
+            },
+            TextureDimension::D3,
+            albedo,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+        );
+
+        info!(
+            textured = texture.is_some() && bvh_data.uvs.is_some(),
+            "Baked albedo volume for entity {entity:?}."
+        );
+
+        let handle = images.add(image);
+        if let Some(sdf_data) = voxel_data.data.as_mut() {
+            sdf_data.albedo = Some(handle);
+        }
+    }
+}
+
+/// Computes the sRGB colour of every voxel from its closest triangle and the
+/// barycentric coordinates of its closest point.
+fn transfer_albedo(
+    triangles: &[u32],
+    barycentrics: &[[f32; 4]],
+    uvs: Option<&[[Vec2; 3]]>,
+    base_color: LinearRgba,
+    texture: Option<&Image>,
+) -> Vec<u8> {
+    triangles
+        .iter()
+        .zip(barycentrics)
+        .flat_map(|(&triangle, barycentric)| {
+            let texel = uvs
+                .and_then(|uvs| uvs.get(triangle as usize))
+                .zip(texture)
+                .map(|(uv, texture)| {
+                    let uv =
+                        uv[0] * barycentric[0] + uv[1] * barycentric[1] + uv[2] * barycentric[2];
+                    sample_bilinear(texture, uv)
+                })
+                .unwrap_or(LinearRgba::WHITE);
+
+            let color = LinearRgba::from_vec4(base_color.to_vec4() * texel.to_vec4());
+            Srgba::from(color).to_u8_array()
+        })
+        .collect()
+}
+
+/// Bilinear lookup with repeat wrapping. Texels that cannot be read are white.
+fn sample_bilinear(texture: &Image, uv: Vec2) -> LinearRgba {
+    let size = texture.size().as_ivec2();
+    let p = uv * size.as_vec2() - 0.5;
+    let base = p.floor();
+    let frac = p - base;
+    let base = base.as_ivec2();
+
+    let texel = |offset: IVec2| {
+        let coord = (base + offset).rem_euclid(size).as_uvec2();
+        texture
+            .get_color_at(coord.x, coord.y)
+            .map(|color| color.to_linear())
+            .unwrap_or(LinearRgba::WHITE)
+    };
+
+    let top = texel(IVec2::new(0, 0)).mix(&texel(IVec2::new(1, 0)), frac.x);
+    let bottom = texel(IVec2::new(0, 1)).mix(&texel(IVec2::new(1, 1)), frac.x);
+    top.mix(&bottom, frac.y)
+}
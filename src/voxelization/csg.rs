@@ -0,0 +1,365 @@
+use crate::{
+    gpu_types::GpuBox3,
+    utils::input_utils::is_modifier,
+    voxelization::{
+        SdfVolume, SignedDistanceFieldData, VoxelizationCompleted, VoxelizationData,
+        VoxelizationSettings, VoxelizationStarted, VoxelizationState, VoxelizeTargetMarker,
+        csg_worker::{CsgOperand, CsgUniforms, CsgVariables, CsgWorker, MAX_CSG_OPERANDS},
+        storage_format::SdfEncoding,
+        voxelization_systems::{read_back, sdf_image},
+        voxelization_worker::SIZE,
+    },
+};
+use bevy::prelude::*;
+use bevy_app_compute::prelude::AppComputeWorker;
+use tracing::instrument;
+
+/// Combines the baked distance fields of other entities into a distance field
+/// of its own, on a world-aligned grid covering the operands. The result is
+/// stored in the entity's [`VoxelizationData`] like a regular bake and is
+/// re-evaluated whenever an operand moves or is re-baked, or this component
+/// changes.
+///
+/// The result is in world space, so the entity should keep an identity
+/// transform. Operands may themselves be `SdfCsg` entities.
+#[derive(Debug, Clone, PartialEq, Component)]
+pub struct SdfCsg {
+    pub operation: CsgOperation,
+    /// Entities with a baked [`VoxelizationData`], at most
+    /// [`MAX_CSG_OPERANDS`]. For subtraction the first one is subtracted from.
+    pub operands: Vec<Entity>,
+    /// Voxels per axis of the result. Clamped to the worker's capacity.
+    pub resolution: u32,
+}
+
+impl SdfCsg {
+    pub fn new(operation: CsgOperation, operands: impl IntoIterator<Item = Entity>) -> Self {
+        Self {
+            operation,
+            operands: operands.into_iter().collect(),
+            resolution: SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum CsgOperation {
+    Union,
+    /// The first operand minus all others.
+    Subtraction,
+    Intersection,
+    /// Union with the creases rounded off over `radius` world units.
+    SmoothUnion {
+        radius: f32,
+    },
+}
+
+impl CsgOperation {
+    /// Shader operation index and blend radius.
+    fn shader_operation(&self) -> (u32, f32) {
+        match *self {
+            Self::Union => (0, 0.0),
+            Self::Subtraction => (1, 0.0),
+            Self::Intersection => (2, 0.0),
+            Self::SmoothUnion { radius } => (3, radius),
+        }
+    }
+
+    fn next(&self) -> Self {
+        match self {
+            Self::Union => Self::Subtraction,
+            Self::Subtraction => Self::Intersection,
+            Self::Intersection => Self::SmoothUnion { radius: 0.25 },
+            Self::SmoothUnion { .. } => Self::Union,
+        }
+    }
+
+    /// Bounds of the result given the world bounds of the operands.
+    fn bounds(&self, operand_bounds: &[(Vec3, Vec3)]) -> (Vec3, Vec3) {
+        let (first_min, first_max) = operand_bounds[0];
+        match self {
+            Self::Union | Self::SmoothUnion { .. } => operand_bounds
+                .iter()
+                .fold((first_min, first_max), |(min, max), &(b_min, b_max)| {
+                    (min.min(b_min), max.max(b_max))
+                }),
+            Self::Subtraction => (first_min, first_max),
+            Self::Intersection => operand_bounds
+                .iter()
+                .fold((first_min, first_max), |(min, max), &(b_min, b_max)| {
+                    (min.max(b_min), max.min(b_max))
+                }),
+        }
+    }
+}
+
+/// Marks a CSG entity whose operands changed while its result was being
+/// computed. The result is kept until it is computed again.
+#[derive(Debug, Clone, Copy, Component)]
+pub(super) struct CsgStale;
+
+/// CSG entity the worker runs for, until its output is read back. Outputs of
+/// runs whose entity was invalidated or failed meanwhile are dropped.
+#[derive(Debug, Default, Resource)]
+pub(super) struct CsgRun(Option<Entity>);
+
+/// Drops the result of every CSG entity whose component or operands changed
+/// since the last run, so it is queued again.
+#[allow(clippy::type_complexity)]
+pub(super) fn invalidate_csg_results(
+    mut commands: Commands,
+    csgs: Query<(Entity, Ref<SdfCsg>, &VoxelizationData, Has<CsgStale>)>,
+    operands: Query<(Ref<GlobalTransform>, Ref<VoxelizationData>)>,
+) {
+    for (entity, csg, voxel_data, stale) in csgs.iter() {
+        let operand_changed = csg
+            .operands
+            .iter()
+            .any(|&operand| match operands.get(operand) {
+                Ok((transform, voxel_data)) => transform.is_changed() || voxel_data.is_changed(),
+                Err(_) => true,
+            });
+
+        if !csg.is_changed() && !operand_changed && !stale {
+            continue;
+        }
+
+        if voxel_data.state == VoxelizationState::InProgress {
+            commands.entity(entity).insert(CsgStale);
+        } else {
+            info!("Operands of CSG entity {entity:?} changed. Re-evaluating.");
+            commands
+                .entity(entity)
+                .remove::<(VoxelizationData, CsgStale)>();
+        }
+    }
+}
+
+/// Uploads the operands of the next CSG entity without a result and starts the
+/// worker, once every operand is baked.
+#[instrument(skip_all)]
+pub(super) fn queue_csg(
+    mut commands: Commands,
+    images: Res<Assets<Image>>,
+    csgs: Query<(Entity, &SdfCsg), Without<VoxelizationData>>,
+    operands: Query<(&GlobalTransform, &VoxelizationData)>,
+    in_progress: Query<&VoxelizationData, With<SdfCsg>>,
+    mut worker: ResMut<AppComputeWorker<CsgWorker>>,
+    mut run: ResMut<CsgRun>,
+    mut started: MessageWriter<VoxelizationStarted>,
+) {
+    // The worker holds a single result at a time.
+    if run.0.is_some()
+        || in_progress
+            .iter()
+            .any(|voxel_data| voxel_data.state == VoxelizationState::InProgress)
+    {
+        return;
+    }
+
+    'csgs: for (entity, csg) in csgs.iter() {
+        if csg.operands.is_empty() || csg.operands.len() > MAX_CSG_OPERANDS {
+            warn_once!(
+                "CSG entity {entity:?} has {} operands, expected 1 to {MAX_CSG_OPERANDS}.",
+                csg.operands.len()
+            );
+            continue;
+        }
+
+        let baked = csg
+            .operands
+            .iter()
+            .map(|&operand| {
+                let (transform, voxel_data) = operands.get(operand).ok()?;
+                let data = voxel_data.data.as_ref()?;
+                (voxel_data.state == VoxelizationState::Computed).then_some((transform, data))
+            })
+            .collect::<Option<Vec<_>>>();
+        let Some(baked) = baked else {
+            trace!("Operands of CSG entity {entity:?} are not baked yet.");
+            continue;
+        };
+
+        let mut voxels = Vec::new();
+        let mut gpu_operands = Vec::with_capacity(baked.len());
+        let mut world_bounds = Vec::with_capacity(baked.len());
+        for (transform, data) in baked {
+            let Some(operand_voxels) = data.read_voxels(&images) else {
+                error!("Operand of CSG entity {entity:?} has no CPU-accessible data.");
+                continue 'csgs;
+            };
+
+            let (min, max) = (data.volume.min(), data.volume.max());
+            let world_corners = (0..8).map(|i| {
+                let corner = Vec3::select(BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), max, min);
+                transform.transform_point(corner)
+            });
+            world_bounds.push(
+                world_corners.fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+                    (min.min(p), max.max(p))
+                }),
+            );
+
+            // Distances only stay distances under uniform scale. For others
+            // the smallest scale keeps them from being overestimated.
+            let distance_scale = transform.scale().abs().min_element();
+            gpu_operands.push(CsgOperand::new(
+                transform.to_matrix().inverse(),
+                min,
+                max,
+                distance_scale,
+                data.volume.grid_size,
+                voxels.len() as u32,
+            ));
+            voxels.extend(operand_voxels);
+        }
+
+        let (min, max) = csg.operation.bounds(&world_bounds);
+        if min.cmpge(max).any() {
+            warn_once!("Operands of CSG entity {entity:?} do not overlap.");
+            continue;
+        }
+
+        let resolution = csg.resolution.clamp(1, SIZE);
+        let (operation, radius) = csg.operation.shader_operation();
+        worker.write_slice(CsgVariables::OperandVoxels.as_ref(), &voxels);
+        worker.write_slice(CsgVariables::Operands.as_ref(), &gpu_operands);
+        worker.write(
+            CsgVariables::CsgUniforms.as_ref(),
+            &CsgUniforms::new(
+                min,
+                max,
+                resolution,
+                operation,
+                radius,
+                gpu_operands.len() as u32,
+            ),
+        );
+
+        info!(
+            operation = %csg.operation,
+            operands = gpu_operands.len(),
+            resolution,
+            "Starting CSG evaluation for entity {entity:?}."
+        );
+
+        let settings = VoxelizationSettings {
+            resolution,
+            padding_ratio: 0.0,
+            ..default()
+        };
+        commands
+            .entity(entity)
+            .insert(VoxelizationData::in_progress(
+                settings,
+                Some(SignedDistanceFieldData {
+                    signed_distance_field: Handle::default(),
+                    volume: SdfVolume::new(resolution, GpuBox3::new(min.into(), max.into()), 0.0),
+                    mip_count: 1,
+                    encoding: SdfEncoding::default(),
+                    closest_features: None,
+                    albedo: None,
+                    occupancy: None,
+                }),
+            ));
+        started.write(VoxelizationStarted { entity, settings });
+
+        run.0 = Some(entity);
+        worker.execute();
+        return;
+    }
+}
+
+/// Stores the worker output as the result of the CSG entity it was computed
+/// for.
+#[instrument(skip_all)]
+pub(super) fn extract_csg_results(
+    mut images: ResMut<Assets<Image>>,
+    worker: ResMut<AppComputeWorker<CsgWorker>>,
+    mut run: ResMut<CsgRun>,
+    mut csgs: Query<&mut VoxelizationData, With<SdfCsg>>,
+    mut completed: MessageWriter<VoxelizationCompleted>,
+) {
+    if !worker.ready() || !worker.is_changed() {
+        return;
+    }
+    let Some(entity) = run.0.take() else {
+        return;
+    };
+
+    let Ok(mut voxel_data) = csgs.get_mut(entity) else {
+        debug!("Dropping CSG output of missing entity {entity:?}.");
+        return;
+    };
+    if voxel_data.state != VoxelizationState::InProgress {
+        debug!("Dropping CSG output of stale job for entity {entity:?}.");
+        return;
+    }
+    let Some(data) = voxel_data.data.as_mut() else {
+        return;
+    };
+
+    let voxel_count = data.volume.voxel_count();
+    let output = match read_back(
+        &worker,
+        CsgVariables::CsgOutput.as_ref(),
+        voxel_count * std::mem::size_of::<f32>(),
+    ) {
+        Ok(output) => output,
+        Err(error) => {
+            voxel_data.fail(error);
+            return;
+        }
+    };
+    let encoded = data.encoding.encode(bytemuck::cast_slice(&output));
+    data.signed_distance_field =
+        images.add(sdf_image(data.volume.grid_size, 1, &data.encoding, encoded));
+
+    let duration = voxel_data.complete();
+    info!(?duration, "CSG result for entity {entity:?} computed.");
+    completed.write(VoxelizationCompleted {
+        entity,
+        settings: voxel_data.settings,
+        duration,
+    });
+}
+
+/// B combines the baked targets into a union, or cycles the operation of the
+/// existing CSG entities.
+pub(super) fn csg_cycler(
+    mut commands: Commands,
+    input: Res<ButtonInput<KeyCode>>,
+    mut csgs: Query<&mut SdfCsg>,
+    targets: Query<(Entity, &VoxelizationData), (With<VoxelizeTargetMarker>, Without<SdfCsg>)>,
+) {
+    if !input.just_pressed(KeyCode::KeyB) || input.get_pressed().any(|key| is_modifier(*key)) {
+        return;
+    }
+
+    if !csgs.is_empty() {
+        for mut csg in csgs.iter_mut() {
+            csg.operation = csg.operation.next();
+            info!("CSG operation set to {}.", csg.operation);
+        }
+        return;
+    }
+
+    let operands = targets
+        .iter()
+        .filter(|(_, voxel_data)| voxel_data.state == VoxelizationState::Computed)
+        .map(|(entity, _)| entity)
+        .take(MAX_CSG_OPERANDS)
+        .collect::<Vec<_>>();
+    if operands.len() < 2 {
+        info!("CSG needs at least two baked targets.");
+        return;
+    }
+
+    info!("Combining {} targets.", operands.len());
+    commands.spawn((
+        VoxelizeTargetMarker,
+        SdfCsg::new(CsgOperation::Union, operands),
+        Transform::default(),
+    ));
+}
@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use bevy_app_compute::prelude::{
+    AppComputeWorker, AppComputeWorkerBuilder, ComputeShader, ComputeWorker, ShaderRef, ShaderType,
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::voxelization::voxelization_worker::SIZE;
+
+/// Most volumes a single [`super::csg::SdfCsg`] can combine.
+pub const MAX_CSG_OPERANDS: usize = 4;
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(Debug, strum::EnumString, strum::Display, strum::AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum CsgVariables {
+    CsgOutput,
+    OperandVoxels,
+    Operands,
+    CsgUniforms,
+}
+
+/// Where an operand's voxels sit in the shared voxel buffer and how to sample
+/// them from world space.
+#[derive(Clone, Copy, Zeroable, Pod, ShaderType)]
+#[repr(C)]
+pub struct CsgOperand {
+    pub local_from_world: Mat4,
+    /// Mesh-local grid bounds in `xyz`. `w` of the minimum is the world
+    /// distance of one local unit.
+    pub bounds_min: Vec4,
+    pub bounds_max: Vec4,
+    /// Voxels per axis and offset into the voxel buffer.
+    pub size: u32,
+    pub offset: u32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+impl CsgOperand {
+    pub fn new(
+        local_from_world: Mat4,
+        bounds_min: Vec3,
+        bounds_max: Vec3,
+        distance_scale: f32,
+        size: u32,
+        offset: u32,
+    ) -> Self {
+        Self {
+            local_from_world,
+            bounds_min: bounds_min.extend(distance_scale),
+            bounds_max: bounds_max.extend(0.0),
+            size,
+            offset,
+            _pad0: 0,
+            _pad1: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Zeroable, Pod, ShaderType)]
+#[repr(C)]
+pub struct CsgUniforms {
+    /// World-space bounds of the output grid in `xyz`. `w` of the minimum is
+    /// the smooth union radius.
+    pub bounds_min: Vec4,
+    pub bounds_max: Vec4,
+    pub size: u32,
+    /// Index of the [`super::csg::CsgOperation`] variant.
+    pub operation: u32,
+    pub operand_count: u32,
+    _pad0: u32,
+}
+
+impl CsgUniforms {
+    pub fn new(
+        bounds_min: Vec3,
+        bounds_max: Vec3,
+        size: u32,
+        operation: u32,
+        radius: f32,
+        operand_count: u32,
+    ) -> Self {
+        Self {
+            bounds_min: bounds_min.extend(radius),
+            bounds_max: bounds_max.extend(0.0),
+            size,
+            operation,
+            operand_count,
+            _pad0: 0,
+        }
+    }
+}
+
+#[derive(Default, TypePath)]
+pub struct CsgShader;
+
+impl ComputeShader for CsgShader {
+    fn shader() -> ShaderRef {
+        "shaders/sdf_csg.compute.wgsl".into()
+    }
+}
+
+#[derive(Resource)]
+pub struct CsgWorker;
+
+impl ComputeWorker for CsgWorker {
+    fn build(world: &mut World) -> AppComputeWorker<Self> {
+        let workgroups = [SIZE.div_ceil(WORKGROUP_SIZE); 3];
+        let voxel_count = (SIZE as u64).pow(3);
+
+        AppComputeWorkerBuilder::new(world)
+            .add_empty_staging(CsgVariables::CsgOutput.as_ref(), voxel_count * 4)
+            .add_empty_rw_storage(
+                CsgVariables::OperandVoxels.as_ref(),
+                MAX_CSG_OPERANDS as u64 * voxel_count * 4,
+            )
+            .add_empty_rw_storage(
+                CsgVariables::Operands.as_ref(),
+                (MAX_CSG_OPERANDS * std::mem::size_of::<CsgOperand>()) as u64,
+            )
+            .add_uniform(
+                CsgVariables::CsgUniforms.as_ref(),
+                &CsgUniforms::new(Vec3::ZERO, Vec3::ONE, SIZE, 0, 0.0, 0),
+            )
+            .add_pass::<CsgShader>(
+                workgroups,
+                &[
+                    CsgVariables::CsgOutput.as_ref(),
+                    CsgVariables::OperandVoxels.as_ref(),
+                    CsgVariables::Operands.as_ref(),
+                    CsgVariables::CsgUniforms.as_ref(),
+                ],
+            )
+            .one_shot()
+            .build()
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{
+    bvh::BvhFailed,
+    voxelization::{
+        VoxelizationData, VoxelizationError, VoxelizationFailed, VoxelizationPolicy,
+        VoxelizationSettings, VoxelizationState, VoxelizeTargetMarker,
+    },
+};
+use bevy::{prelude::*, render::renderer::RenderDevice};
+use std::sync::{Arc, Mutex};
+
+/// Reason the render device was lost, set from the device's callback.
+#[derive(Debug, Default, Clone, Resource)]
+pub(super) struct DeviceLoss(Arc<Mutex<Option<String>>>);
+
+/// Number of times the job of an entity was restarted after a transient
+/// failure. Removed once the job completes or the entity is re-baked.
+#[derive(Debug, Clone, Copy, Component)]
+pub(super) struct VoxelizationRetries(pub u32);
+
+pub(super) fn watch_device_loss(device: Option<Res<RenderDevice>>, loss: Res<DeviceLoss>) {
+    let Some(device) = device else {
+        return;
+    };
+
+    let loss = loss.0.clone();
+    device
+        .wgpu_device()
+        .set_device_lost_callback(move |reason, message| {
+            error!("Render device lost ({reason:?}): {message}");
+            *loss.lock().unwrap() = Some(message);
+        });
+}
+
+/// Fails the voxelization of targets whose mesh has no BVH.
+#[allow(clippy::type_complexity)]
+pub(super) fn fail_invalid_meshes(
+    mut commands: Commands,
+    targets: Query<
+        (Entity, &BvhFailed, Option<&VoxelizationSettings>),
+        (With<VoxelizeTargetMarker>, Without<VoxelizationData>),
+    >,
+) {
+    for (entity, BvhFailed(error), settings) in targets.iter() {
+        commands.entity(entity).insert(VoxelizationData::failed(
+            settings.copied().unwrap_or_default(),
+            VoxelizationError::InvalidMesh(error.clone()),
+        ));
+    }
+}
+
+/// Fails jobs that outlived [`VoxelizationPolicy::timeout`] or lost their
+/// device.
+pub(super) fn fail_stalled_jobs(
+    policy: Res<VoxelizationPolicy>,
+    loss: Res<DeviceLoss>,
+    mut jobs: Query<&mut VoxelizationData>,
+) {
+    let lost = loss.0.lock().unwrap().clone();
+    for mut voxel_data in jobs.iter_mut() {
+        if voxel_data.state != VoxelizationState::InProgress {
+            continue;
+        }
+
+        if let Some(reason) = &lost {
+            voxel_data.fail(VoxelizationError::DeviceLost(reason.clone()));
+        } else if voxel_data.elapsed() > policy.timeout {
+            let elapsed = voxel_data.elapsed();
+            voxel_data.fail(VoxelizationError::Timeout(elapsed));
+        }
+    }
+}
+
+/// Restarts jobs that failed for a transient reason while retries are left,
+/// and reports all other failures. A timed out run is still read back and
+/// dropped before its job starts again.
+#[allow(clippy::type_complexity)]
+pub(super) fn handle_failed_jobs(
+    mut commands: Commands,
+    policy: Res<VoxelizationPolicy>,
+    jobs: Query<
+        (Entity, &VoxelizationData, Option<&VoxelizationRetries>),
+        Changed<VoxelizationData>,
+    >,
+    mut failed: MessageWriter<VoxelizationFailed>,
+) {
+    for (entity, voxel_data, retries) in jobs.iter() {
+        let retries = retries.map_or(0, |retries| retries.0);
+        let error = match &voxel_data.state {
+            VoxelizationState::Failed(error) => error,
+            VoxelizationState::Computed if retries > 0 => {
+                commands.entity(entity).remove::<VoxelizationRetries>();
+                continue;
+            }
+            _ => continue,
+        };
+
+        if error.is_transient() && retries < policy.max_retries {
+            warn!(
+                "Voxelization of entity {entity:?} failed: {error}. Retrying ({}/{}).",
+                retries + 1,
+                policy.max_retries
+            );
+            commands
+                .entity(entity)
+                .remove::<VoxelizationData>()
+                .insert(VoxelizationRetries(retries + 1));
+            continue;
+        }
+
+        error!("Voxelization of entity {entity:?} failed: {error}.");
+        failed.write(VoxelizationFailed {
+            entity,
+            settings: voxel_data.settings,
+            duration: voxel_data.elapsed(),
+            error: error.clone(),
+        });
+    }
+}
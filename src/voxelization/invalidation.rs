@@ -0,0 +1,111 @@
+use crate::{
+    bvh::{BvhData, BvhFailed, BvhTargetMarker},
+    utils::input_utils::is_modifier,
+    voxelization::{
+        RebakeRequest, VoxelizationData, VoxelizationSettings, VoxelizeTargetMarker,
+        failure::VoxelizationRetries, quality::SdfQualityReport, raymarch::RaymarchRenderTarget,
+    },
+};
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// Turns mesh hot-reloads, mesh handle swaps and settings edits on already
+/// processed targets into [`RebakeRequest`]s.
+#[allow(clippy::type_complexity)]
+pub(super) fn request_rebake_on_change(
+    mut mesh_events: MessageReader<AssetEvent<Mesh>>,
+    targets: Query<(Entity, &Mesh3d), With<VoxelizeTargetMarker>>,
+    changed_meshes: Query<
+        Entity,
+        (
+            With<BvhTargetMarker>,
+            Changed<Mesh3d>,
+            Or<(With<BvhData>, With<VoxelizationData>)>,
+        ),
+    >,
+    changed_settings: Query<Entity, (Changed<VoxelizationSettings>, With<VoxelizationData>)>,
+    mut rebake_requests: MessageWriter<RebakeRequest>,
+) {
+    let modified_meshes = mesh_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    let reloaded = targets
+        .iter()
+        .filter(|(_, mesh_handle)| modified_meshes.contains(&mesh_handle.id()))
+        .map(|(entity, _)| entity);
+
+    for entity in reloaded
+        .chain(changed_meshes.iter())
+        .chain(changed_settings.iter())
+        .collect::<HashSet<_>>()
+    {
+        info!("Source of entity {entity:?} changed. Requesting re-bake.");
+        rebake_requests.write(RebakeRequest { entity });
+    }
+}
+
+/// Shift+R re-bakes every voxelization target.
+pub(super) fn request_rebake_on_key(
+    input: Res<ButtonInput<KeyCode>>,
+    targets: Query<Entity, With<VoxelizeTargetMarker>>,
+    mut rebake_requests: MessageWriter<RebakeRequest>,
+) {
+    let shift_held = input.pressed(KeyCode::ShiftLeft) || input.pressed(KeyCode::ShiftRight);
+    let other_modifier_held = input
+        .get_pressed()
+        .any(|key| is_modifier(*key) && !matches!(key, KeyCode::ShiftLeft | KeyCode::ShiftRight));
+
+    if !(input.just_pressed(KeyCode::KeyR) && shift_held) || other_modifier_held {
+        return;
+    }
+
+    info!(
+        "Re-bake requested for {} target(s).",
+        targets.iter().count()
+    );
+    rebake_requests.write_batch(targets.iter().map(|entity| RebakeRequest { entity }));
+}
+
+/// Clears the derived BVH and voxelization components of every requested
+/// entity and despawns its render targets. The regular pipeline systems then
+/// pick the entity up again as if it had just been spawned, once a bake still
+/// running for it was read back and dropped.
+pub(super) fn process_rebake_requests(
+    mut commands: Commands,
+    mut rebake_requests: MessageReader<RebakeRequest>,
+    render_targets: Query<(Entity, &RaymarchRenderTarget)>,
+) {
+    let requested = rebake_requests
+        .read()
+        .map(|request| request.entity)
+        .collect::<HashSet<_>>();
+
+    for entity in requested {
+        let Ok(mut entity_commands) = commands.get_entity(entity) else {
+            warn!("Ignoring re-bake request for missing entity {entity:?}.");
+            continue;
+        };
+
+        entity_commands.remove::<(
+            BvhData,
+            BvhFailed,
+            VoxelizationData,
+            VoxelizationRetries,
+            SdfQualityReport,
+        )>();
+
+        for (target_entity, _) in render_targets
+            .iter()
+            .filter(|(_, target)| target.source_entity == entity)
+        {
+            commands.entity(target_entity).despawn();
+        }
+
+        info!("Cleared derived data for entity {entity:?}.");
+    }
+}
@@ -0,0 +1,131 @@
+use crate::gpu_types::GpuTriangle;
+use bevy::prelude::*;
+
+/// Self-consistency checks of a baked distance field, computed from the base
+/// level after every bake. Large counts or deviations point at inputs the
+/// voxelizer cannot handle, like open or self-intersecting meshes.
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct SdfQualityReport {
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Voxels more than a voxel from the surface whose sign disagrees with
+    /// all six face neighbours. A valid field cannot change sign that fast.
+    pub sign_inconsistencies: usize,
+    /// Mean, RMS and maximum of `||∇d| - 1|` over the interior voxels, from
+    /// central differences. Nonzero near the medial axis even for exact
+    /// fields.
+    pub eikonal_mean: f32,
+    pub eikonal_rms: f32,
+    pub eikonal_max: f32,
+    /// Voxel edges whose end points have different signs.
+    pub zero_crossings: usize,
+    /// Total area of the source triangles.
+    pub surface_area: f32,
+    /// `zero_crossings` over the count expected for `surface_area`. Close to
+    /// one for a closed surface, far off when the sign is noisy or parts of
+    /// the mesh are missing from the field.
+    pub crossing_ratio: f32,
+}
+
+impl SdfQualityReport {
+    /// `voxels` holds the `size`³ base level distances in x-major order.
+    pub fn compute(voxels: &[f32], size: u32, voxel_size: Vec3, triangles: &[GpuTriangle]) -> Self {
+        let n = size as usize;
+        let index = |x: usize, y: usize, z: usize| x + y * n + z * n * n;
+        let far = voxel_size.max_element();
+
+        let mut sign_inconsistencies = 0;
+        let mut zero_crossings = 0;
+        let mut eikonal_sum = 0.0f64;
+        let mut eikonal_squared_sum = 0.0f64;
+        let mut eikonal_max = 0.0f32;
+        let mut interior = 0usize;
+
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let d = voxels[index(x, y, z)];
+                    let p = [x, y, z];
+
+                    // Forward edges only, so every edge is counted once
+                    for (axis, &c) in p.iter().enumerate() {
+                        if c + 1 < n {
+                            let mut q = p;
+                            q[axis] += 1;
+                            if (d < 0.0) != (voxels[index(q[0], q[1], q[2])] < 0.0) {
+                                zero_crossings += 1;
+                            }
+                        }
+                    }
+
+                    if p.iter().any(|&c| c == 0 || c + 1 == n) {
+                        continue;
+                    }
+
+                    let neighbours = |axis: usize| {
+                        let (mut lo, mut hi) = (p, p);
+                        lo[axis] -= 1;
+                        hi[axis] += 1;
+                        (
+                            voxels[index(lo[0], lo[1], lo[2])],
+                            voxels[index(hi[0], hi[1], hi[2])],
+                        )
+                    };
+                    let pairs = [neighbours(0), neighbours(1), neighbours(2)];
+
+                    if d.abs() > far
+                        && pairs
+                            .iter()
+                            .all(|&(lo, hi)| (lo < 0.0) != (d < 0.0) && (hi < 0.0) != (d < 0.0))
+                    {
+                        sign_inconsistencies += 1;
+                    }
+
+                    let gradient = Vec3::new(
+                        pairs[0].1 - pairs[0].0,
+                        pairs[1].1 - pairs[1].0,
+                        pairs[2].1 - pairs[2].0,
+                    ) / (2.0 * voxel_size);
+                    let deviation = (gradient.length() - 1.0).abs();
+                    eikonal_sum += deviation as f64;
+                    eikonal_squared_sum += (deviation as f64).powi(2);
+                    eikonal_max = eikonal_max.max(deviation);
+                    interior += 1;
+                }
+            }
+        }
+
+        let surface_area = triangles
+            .iter()
+            .map(|t| {
+                let (a, b, c) = (Vec3::from(*t.a()), Vec3::from(*t.b()), Vec3::from(*t.c()));
+                0.5 * (b - a).cross(c - a).length()
+            })
+            .sum::<f32>();
+
+        // A surface element with normal n crosses |n_x| / (h_y h_z) + ... edges
+        // per unit area. Averaged over all directions each term is 1/2.
+        let crossings_per_area = 0.5
+            * (1.0 / (voxel_size.y * voxel_size.z)
+                + 1.0 / (voxel_size.x * voxel_size.z)
+                + 1.0 / (voxel_size.x * voxel_size.y));
+        let expected_crossings = surface_area * crossings_per_area;
+
+        let interior = interior.max(1) as f64;
+        Self {
+            min_distance: voxels.iter().copied().fold(f32::INFINITY, f32::min),
+            max_distance: voxels.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+            sign_inconsistencies,
+            eikonal_mean: (eikonal_sum / interior) as f32,
+            eikonal_rms: (eikonal_squared_sum / interior).sqrt() as f32,
+            eikonal_max,
+            zero_crossings,
+            surface_area,
+            crossing_ratio: if expected_crossings > 0.0 {
+                zero_crossings as f32 / expected_crossings
+            } else {
+                0.0
+            },
+        }
+    }
+}
@@ -12,17 +12,32 @@ pub struct RaymarchMaterialExtension {
     #[uniform(102)]
     pub camera: GpuCamera,
 
+    /// Largest edge of a voxel, in mesh-local units
     #[uniform(103)]
-    pub grid_size: u32,
+    pub voxel_size: f32,
 
+    /// Mesh-local bounds of the padded grid `voxel_texture` spans
     #[uniform(104)]
-    pub mesh_bounds: GpuBox3,
+    pub volume_bounds: GpuBox3,
 
     #[uniform(105)]
     pub local_from_world: Mat4,
 
     #[uniform(106)]
     pub world_from_local: Mat4,
+
+    /// Number of mip levels in `voxel_texture`
+    #[uniform(107)]
+    pub mip_count: u32,
+
+    /// 3D colour volume sharing the grid of `voxel_texture`. Falls back to white.
+    #[texture(108, dimension = "3d")]
+    #[sampler(109)]
+    pub albedo_texture: Option<Handle<Image>>,
+
+    /// Maps sampled `voxel_texture` values to distances
+    #[uniform(110)]
+    pub distance_scale: f32,
 }
 
 impl MaterialExtension for RaymarchMaterialExtension {
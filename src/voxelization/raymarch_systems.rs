@@ -1,13 +1,12 @@
 use crate::{
     camera::marker::CameraMarkerPrimary,
-    gpu_types::{GpuBox3, GpuCamera},
+    gpu_types::GpuCamera,
     voxelization::{
         VoxelizationData, VoxelizationState, VoxelizeTargetMarker, raymarch::RaymarchRenderTarget,
-        raymarch_material::RaymarchMaterialExtension, voxelization_worker::SIZE,
+        raymarch_material::RaymarchMaterialExtension,
     },
 };
 use bevy::{
-    camera::primitives::MeshAabb,
     pbr::{ExtendedMaterial, wireframe::Wireframe},
     prelude::*,
 };
@@ -16,7 +15,15 @@ pub(super) fn spawn_raymarch_render_targets(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, RaymarchMaterialExtension>>>,
-    voxel_query: Query<(Entity, &Mesh3d, &VoxelizationData), With<VoxelizeTargetMarker>>,
+    source_materials: Res<Assets<StandardMaterial>>,
+    voxel_query: Query<
+        (
+            Entity,
+            &VoxelizationData,
+            Option<&MeshMaterial3d<StandardMaterial>>,
+        ),
+        With<VoxelizeTargetMarker>,
+    >,
     camera_params: Single<(&Transform, &Projection), With<CameraMarkerPrimary>>,
     existing_targets: Query<&RaymarchRenderTarget>,
 ) {
@@ -27,7 +34,7 @@ pub(super) fn spawn_raymarch_render_targets(
     let (camera_transform, projection) = camera_params.into_inner();
     let camera = GpuCamera::from_transform_and_projection(camera_transform, projection);
 
-    for (entity, mesh_handle, voxel_data) in voxel_query.iter() {
+    for (entity, voxel_data, source_material) in voxel_query.iter() {
         // Only spawn for meshes that have finished voxelization
         if voxel_data.state != VoxelizationState::Computed {
             continue;
@@ -38,10 +45,6 @@ pub(super) fn spawn_raymarch_render_targets(
             continue;
         }
 
-        let mesh = meshes.get(mesh_handle).unwrap();
-        let mesh_bounds = mesh.compute_aabb().map(GpuBox3::from).unwrap();
-        let grid_size = SIZE;
-
         let Some(voxel_info) = &voxel_data.data else {
             error!(
                 "VoxelizationData for entity {:?} is in Computed state but has no SignedDistanceFieldData!",
@@ -51,6 +54,18 @@ pub(super) fn spawn_raymarch_render_targets(
         };
 
         let sdf_handle = voxel_info.signed_distance_field.clone();
+        let volume = voxel_info.volume;
+        let mip_count = voxel_info.mip_count;
+
+        // A baked albedo volume already carries the source base colour
+        let base_color = if voxel_data.settings.albedo {
+            Color::WHITE
+        } else {
+            source_material
+                .and_then(|handle| source_materials.get(handle))
+                .map(|material| material.base_color)
+                .unwrap_or(Color::linear_rgba(0.0, 1.0, 0.0, 1.0))
+        };
 
         let scale_factor = 1.0f32;
         //let mat = Mat4::from_scale(Vec3::splat(scale_factor));
@@ -64,23 +79,24 @@ pub(super) fn spawn_raymarch_render_targets(
             RaymarchRenderTarget {
                 source_entity: entity,
             },
-            Mesh3d(meshes.add(Cuboid::from_corners(
-                Vec3::from(*mesh_bounds.min()),
-                Vec3::from(*mesh_bounds.max()),
-            ))),
+            // The proxy cuboid covers the padded grid, not just the mesh
+            Mesh3d(meshes.add(Cuboid::from_corners(volume.min(), volume.max()))),
             MeshMaterial3d(materials.add(ExtendedMaterial {
                 base: StandardMaterial {
-                    base_color: Color::linear_rgba(0.0, 1.0, 0.0, 1.0),
+                    base_color,
                     alpha_mode: AlphaMode::Blend,
                     ..Default::default()
                 },
                 extension: RaymarchMaterialExtension {
                     voxel_texture: sdf_handle,
                     camera,
-                    grid_size,
-                    mesh_bounds,
+                    voxel_size: volume.voxel_size().max_element(),
+                    volume_bounds: volume.bounds,
                     local_from_world: mat.inverse(),
                     world_from_local: mat,
+                    mip_count,
+                    albedo_texture: voxel_info.albedo.clone(),
+                    distance_scale: voxel_info.encoding.distance_scale,
                 },
             })),
             Transform::from_matrix(mat),
@@ -97,24 +113,36 @@ pub(super) fn update_raymarch_materials(
         &MeshMaterial3d<ExtendedMaterial<StandardMaterial, RaymarchMaterialExtension>>,
         &Transform,
     )>,
-    _voxel_sources: Query<&VoxelizationData, With<VoxelizeTargetMarker>>,
+    voxel_sources: Query<&VoxelizationData, With<VoxelizeTargetMarker>>,
     camera_params: Single<(&Transform, &Projection), With<CameraMarkerPrimary>>,
 ) {
     let (camera_transform, projection) = camera_params.into_inner();
     let camera = GpuCamera::from_transform_and_projection(camera_transform, projection);
 
-    for (_, material_handle, t) in targets.iter() {
+    for (target, material_handle, t) in targets.iter() {
         if let Some(material) = materials.get_mut(material_handle) {
             material.extension.camera = camera;
             let mat = t.to_matrix();
             material.extension.world_from_local = mat;
             material.extension.local_from_world = mat.inverse();
 
-            //if let Ok(voxel_data) = voxel_sources.get(target.source_entity) {
-            //    if let Some(sdf_data) = &voxel_data.data {
-            //        material.voxel_texture = sdf_data.signed_distance_field.clone();
-            //    }
-            //}
+            let Some(data) = voxel_sources
+                .get(target.source_entity)
+                .ok()
+                .and_then(|voxel_data| voxel_data.data.as_ref())
+            else {
+                continue;
+            };
+
+            // The albedo volume may finish baking after the render target was spawned
+            if material.extension.albedo_texture.is_none()
+                && let Some(albedo) = &data.albedo
+            {
+                material.extension.albedo_texture = Some(albedo.clone());
+            }
+
+            // Sculpting drops the mip chain
+            material.extension.mip_count = data.mip_count;
         }
     }
 }
@@ -0,0 +1,684 @@
+use crate::{
+    camera::marker::CameraMarkerPrimary,
+    voxelization::{
+        SignedDistanceFieldData, VoxelizationData, VoxelizationState,
+        raymarch::RaymarchRenderTarget,
+        sculpt_worker::{MAX_SCULPT_REGION, SculptUniforms, SculptVariables, SculptWorker},
+        storage_format::SdfEncoding,
+    },
+};
+use bevy::{
+    prelude::*,
+    render::{
+        MainWorld,
+        render_asset::RenderAssets,
+        render_resource::{
+            Extent3d, Origin3d, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect,
+        },
+        renderer::RenderQueue,
+        texture::GpuImage,
+    },
+    window::{CursorGrabMode, CursorOptions, PrimaryWindow},
+};
+use bevy_app_compute::prelude::AppComputeWorker;
+use std::collections::HashMap;
+use tracing::instrument;
+
+/// Strokes kept for undo. Older ones are forgotten.
+const MAX_UNDO_STROKES: usize = 32;
+const MAX_TRACE_STEPS: usize = 256;
+
+/// The brush applied with the left mouse button.
+#[derive(Debug, Clone, Copy, PartialEq, Resource)]
+pub struct SculptBrush {
+    pub mode: BrushMode,
+    /// In world units.
+    pub radius: f32,
+    /// Blend of the brush into the surface, `0..1`. For add and subtract this
+    /// is how far the seam is rounded, relative to the radius.
+    pub strength: f32,
+}
+
+impl Default for SculptBrush {
+    fn default() -> Self {
+        Self {
+            mode: BrushMode::Add,
+            radius: 0.15,
+            strength: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum BrushMode {
+    #[default]
+    Add,
+    Subtract,
+    Smooth,
+}
+
+/// A region of a distance field image before and after one brush dab, as
+/// stored texels without the mip chain.
+#[derive(Debug, Clone)]
+struct SculptEdit {
+    image: AssetId<Image>,
+    stroke: u64,
+    origin: UVec3,
+    size: UVec3,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+/// Bounded undo and redo stacks of brush dabs. Dabs of one mouse stroke are
+/// undone and redone together.
+#[derive(Debug, Default, Resource)]
+pub struct SculptHistory {
+    undo: Vec<SculptEdit>,
+    redo: Vec<SculptEdit>,
+    stroke: u64,
+}
+
+impl SculptHistory {
+    fn push(&mut self, edit: SculptEdit) {
+        self.redo.clear();
+        self.undo.push(edit);
+
+        let mut strokes = self.undo.iter().map(|edit| edit.stroke).collect::<Vec<_>>();
+        strokes.dedup();
+        if strokes.len() > MAX_UNDO_STROKES {
+            let oldest = self
+                .undo
+                .iter()
+                .position(|edit| edit.stroke != strokes[0])
+                .unwrap_or(self.undo.len());
+            self.undo.drain(..oldest);
+        }
+    }
+
+    /// Removes the dabs of the last stroke on a stack, in the order they were
+    /// applied.
+    fn take_stroke(from: &mut Vec<SculptEdit>) -> Vec<SculptEdit> {
+        let Some(stroke) = from.last().map(|edit| edit.stroke) else {
+            return Vec::new();
+        };
+        let start = from
+            .iter()
+            .rposition(|edit| edit.stroke != stroke)
+            .map_or(0, |i| i + 1);
+        from.split_off(start)
+    }
+}
+
+/// The dab the worker is computing.
+#[derive(Debug, Default, Resource)]
+pub(super) struct PendingDab(Option<SculptEdit>);
+
+/// Base level of a sculpted field, as stored texels and decoded distances.
+#[derive(Debug)]
+struct SculptedField {
+    encoding: SdfEncoding,
+    grid_size: u32,
+    texels: Vec<u8>,
+    voxels: Vec<f32>,
+    /// Whether the image asset misses edits, see [`sync_sculpted_images`].
+    dirty: bool,
+}
+
+impl SculptedField {
+    fn new(data: &SignedDistanceFieldData, images: &Assets<Image>) -> Option<Self> {
+        let base_len = data.volume.voxel_count() * data.encoding.format.bytes_per_voxel();
+        let texels = images
+            .get(&data.signed_distance_field)?
+            .data
+            .as_ref()?
+            .get(..base_len)?
+            .to_vec();
+        Some(Self {
+            encoding: data.encoding,
+            grid_size: data.volume.grid_size,
+            voxels: data.encoding.decode(&texels),
+            texels,
+            dirty: false,
+        })
+    }
+
+    /// Writes the stored texels of an edited region and queues their upload.
+    fn write(&mut self, edit: &SculptEdit, region: &[u8], uploads: &mut SculptUploads) {
+        let texel = self.encoding.format.bytes_per_voxel();
+        let grid = self.grid_size as usize;
+        write_region(&mut self.texels, grid, texel, edit, region);
+        write_region(
+            &mut self.voxels,
+            grid,
+            1,
+            edit,
+            &self.encoding.decode(region),
+        );
+        self.dirty = true;
+
+        uploads.0.push(RegionUpload {
+            image: edit.image,
+            origin: edit.origin,
+            size: edit.size,
+            texels: region.to_vec(),
+        });
+    }
+}
+
+/// Sculpted fields by image, so tracing does not decode every field each
+/// frame and dabs do not re-upload whole textures. Image assets catch up once
+/// a stroke ends.
+#[derive(Debug, Default, Resource)]
+pub(super) struct SculptCache(HashMap<AssetId<Image>, SculptedField>);
+
+impl SculptCache {
+    fn field(
+        &mut self,
+        data: &SignedDistanceFieldData,
+        images: &Assets<Image>,
+    ) -> Option<&mut SculptedField> {
+        let id = data.signed_distance_field.id();
+        if !self.0.contains_key(&id) {
+            self.0.insert(id, SculptedField::new(data, images)?);
+        }
+        self.0.get_mut(&id)
+    }
+}
+
+/// Texels of an edited region, written straight into the GPU texture.
+#[derive(Debug, Clone)]
+struct RegionUpload {
+    image: AssetId<Image>,
+    origin: UVec3,
+    size: UVec3,
+    texels: Vec<u8>,
+}
+
+/// Regions edited this frame, moved to the render world on extraction.
+#[derive(Debug, Default, Resource)]
+pub(super) struct SculptUploads(Vec<RegionUpload>);
+
+/// Brush controls: 1, 2 and 3 select add, subtract and smooth, [ and ] shrink
+/// and grow the brush. Ctrl+Z undoes the last stroke, Ctrl+Y or Ctrl+Shift+Z
+/// redoes it, once the dab in flight has been recorded.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn sculpt_controls(
+    input: Res<ButtonInput<KeyCode>>,
+    mut brush: ResMut<SculptBrush>,
+    mut history: ResMut<SculptHistory>,
+    pending: Res<PendingDab>,
+    mut cache: ResMut<SculptCache>,
+    mut uploads: ResMut<SculptUploads>,
+    images: Res<Assets<Image>>,
+    sources: Query<&VoxelizationData>,
+) {
+    let ctrl_held = input.pressed(KeyCode::ControlLeft) || input.pressed(KeyCode::ControlRight);
+    let shift_held = input.pressed(KeyCode::ShiftLeft) || input.pressed(KeyCode::ShiftRight);
+
+    if ctrl_held {
+        // Recording the pending dab would clear the redo stack.
+        if pending.0.is_some() {
+            return;
+        }
+        let redo =
+            input.just_pressed(KeyCode::KeyY) || (input.just_pressed(KeyCode::KeyZ) && shift_held);
+        let undo = input.just_pressed(KeyCode::KeyZ) && !shift_held;
+
+        if undo {
+            let stroke = SculptHistory::take_stroke(&mut history.undo);
+            for edit in stroke.iter().rev() {
+                if let Some(field) = edited_field(&mut cache, &images, &sources, edit) {
+                    field.write(edit, &edit.before, &mut uploads);
+                }
+            }
+            info!("Undid a stroke of {} dab(s).", stroke.len());
+            history.redo.extend(stroke);
+        } else if redo {
+            let stroke = SculptHistory::take_stroke(&mut history.redo);
+            for edit in &stroke {
+                if let Some(field) = edited_field(&mut cache, &images, &sources, edit) {
+                    field.write(edit, &edit.after, &mut uploads);
+                }
+            }
+            info!("Redid a stroke of {} dab(s).", stroke.len());
+            history.undo.extend(stroke);
+        }
+        return;
+    }
+
+    let mode = [
+        (KeyCode::Digit1, BrushMode::Add),
+        (KeyCode::Digit2, BrushMode::Subtract),
+        (KeyCode::Digit3, BrushMode::Smooth),
+    ]
+    .into_iter()
+    .find(|(key, _)| input.just_pressed(*key));
+    if let Some((_, mode)) = mode {
+        brush.mode = mode;
+        info!("Brush mode set to {mode}.");
+    }
+
+    if input.just_pressed(KeyCode::BracketLeft) {
+        brush.radius *= 0.8;
+        info!("Brush radius set to {}.", brush.radius);
+    }
+    if input.just_pressed(KeyCode::BracketRight) {
+        brush.radius *= 1.25;
+        info!("Brush radius set to {}.", brush.radius);
+    }
+}
+
+/// Traces the cursor ray against the raymarched volumes while the left mouse
+/// button is held and dispatches a brush dab at the closest hit. While the
+/// cursor is grabbed, the screen center is used.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
+pub(super) fn apply_sculpt_brush(
+    mouse: Res<ButtonInput<MouseButton>>,
+    brush: Res<SculptBrush>,
+    mut history: ResMut<SculptHistory>,
+    mut pending: ResMut<PendingDab>,
+    mut worker: ResMut<AppComputeWorker<SculptWorker>>,
+    mut cache: ResMut<SculptCache>,
+    mut images: ResMut<Assets<Image>>,
+    window: Single<(&Window, &CursorOptions), With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform), With<CameraMarkerPrimary>>,
+    targets: Query<(&RaymarchRenderTarget, &GlobalTransform)>,
+    mut sources: Query<&mut VoxelizationData>,
+) {
+    if mouse.just_pressed(MouseButton::Left) {
+        history.stroke += 1;
+    }
+    if !mouse.pressed(MouseButton::Left) || pending.0.is_some() {
+        return;
+    }
+
+    let (window, cursor_options) = window.into_inner();
+    let cursor = match cursor_options.grab_mode {
+        CursorGrabMode::None => window.cursor_position(),
+        _ => Some(window.size() * 0.5),
+    };
+    let (camera, camera_transform) = camera.into_inner();
+    let Some(ray) =
+        cursor.and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+    else {
+        return;
+    };
+
+    // Closest hit over all volumes, in world distance along the ray
+    let mut closest: Option<(f32, Entity, Vec3, f32)> = None;
+    for (target, transform) in targets.iter() {
+        let Ok(voxel_data) = sources.get(target.source_entity) else {
+            continue;
+        };
+        let Some(data) = voxel_data.data.as_ref() else {
+            continue;
+        };
+        if voxel_data.state != VoxelizationState::Computed {
+            continue;
+        }
+        let Some(field) = cache.field(data, &images) else {
+            continue;
+        };
+
+        let local_from_world = transform.affine().inverse();
+        let origin = local_from_world.transform_point3(ray.origin);
+        let direction = local_from_world.transform_vector3(*ray.direction);
+        let Some(t) = trace(data, &field.voxels, origin, direction.normalize()) else {
+            continue;
+        };
+
+        let hit_local = origin + direction.normalize() * t;
+        let distance = transform.transform_point(hit_local).distance(ray.origin);
+        if closest.is_none_or(|(d, ..)| distance < d) {
+            let scale = transform.scale().max_element();
+            closest = Some((distance, target.source_entity, hit_local, scale));
+        }
+    }
+
+    let Some((_, entity, center, scale)) = closest else {
+        return;
+    };
+    let Ok(mut voxel_data) = sources.get_mut(entity) else {
+        return;
+    };
+
+    // The conservative mip chain would go stale with the first edit. Dabs
+    // otherwise leave the component untouched, the field counts as changed
+    // once its image is synced.
+    if voxel_data
+        .data
+        .as_ref()
+        .is_some_and(|data| data.mip_count > 1)
+        && let Some(data) = voxel_data.data.as_mut()
+    {
+        strip_mips(data, &mut images);
+    }
+    let Some(data) = voxel_data.data.as_ref() else {
+        return;
+    };
+
+    let voxel_size = data.voxel_size();
+    let max_radius = (MAX_SCULPT_REGION as f32 - 4.0) * 0.25 * voxel_size.min_element();
+    let radius = (brush.radius / scale).min(max_radius);
+
+    // Everything within twice the radius, where the added or removed sphere
+    // is still close enough to matter, plus a voxel for smoothing.
+    let grid_max = IVec3::splat(data.volume.grid_size as i32 - 1);
+    let reach = Vec3::splat(2.0 * radius) + voxel_size;
+    let lo = data
+        .volume
+        .to_voxel(center - reach)
+        .floor()
+        .as_ivec3()
+        .clamp(IVec3::ZERO, grid_max);
+    let hi = data
+        .volume
+        .to_voxel(center + reach)
+        .ceil()
+        .as_ivec3()
+        .clamp(IVec3::ZERO, grid_max);
+    let origin = lo.as_uvec3();
+    let size = (hi - lo + IVec3::ONE)
+        .as_uvec3()
+        .min(UVec3::splat(MAX_SCULPT_REGION));
+
+    let Some(field) = cache.field(data, &images) else {
+        return;
+    };
+    let before = read_region(&field.texels, data, origin, size);
+
+    let mode = match brush.mode {
+        BrushMode::Add => 0,
+        BrushMode::Subtract => 1,
+        BrushMode::Smooth => 2,
+    };
+    let region_min = data.volume.voxel_center(origin);
+    worker.write_slice(
+        SculptVariables::RegionInput.as_ref(),
+        &data.encoding.decode(&before),
+    );
+    worker.write(
+        SculptVariables::SculptUniforms.as_ref(),
+        &SculptUniforms::new(
+            region_min,
+            size,
+            voxel_size,
+            center,
+            radius,
+            brush.strength,
+            mode,
+        ),
+    );
+    worker.execute();
+
+    pending.0 = Some(SculptEdit {
+        image: data.signed_distance_field.id(),
+        stroke: history.stroke,
+        origin,
+        size,
+        before,
+        after: Vec::new(),
+    });
+}
+
+/// Writes the dab computed by the worker into the distance field and records
+/// it in the history.
+#[instrument(skip_all)]
+pub(super) fn extract_sculpt_results(
+    mut pending: ResMut<PendingDab>,
+    mut history: ResMut<SculptHistory>,
+    mut cache: ResMut<SculptCache>,
+    mut uploads: ResMut<SculptUploads>,
+    images: Res<Assets<Image>>,
+    worker: ResMut<AppComputeWorker<SculptWorker>>,
+    sources: Query<&VoxelizationData>,
+) {
+    if !worker.ready() || !worker.is_changed() {
+        return;
+    }
+    let Some(mut edit) = pending.0.take() else {
+        return;
+    };
+
+    // The field may have been re-baked while the dab was computed.
+    let Some(field) = edited_field(&mut cache, &images, &sources, &edit) else {
+        return;
+    };
+
+    let voxel_count = edit.size.element_product() as usize;
+    let output = &worker.read_raw(SculptVariables::RegionOutput.as_ref())
+        [..voxel_count * std::mem::size_of::<f32>()];
+    edit.after = field.encoding.encode(bytemuck::cast_slice(output));
+
+    field.write(&edit, &edit.after, &mut uploads);
+    history.push(edit);
+}
+
+/// Copies the edits of every sculpted field into its image once no stroke is
+/// in progress, and marks the owning [`VoxelizationData`] changed so that
+/// readers of the image like CSG pick them up. This uploads the whole texture
+/// again, so it happens once per stroke instead of once per dab.
+pub(super) fn sync_sculpted_images(
+    mouse: Res<ButtonInput<MouseButton>>,
+    pending: Res<PendingDab>,
+    mut cache: ResMut<SculptCache>,
+    mut images: ResMut<Assets<Image>>,
+    mut sources: Query<&mut VoxelizationData>,
+) {
+    if mouse.pressed(MouseButton::Left) || pending.0.is_some() {
+        return;
+    }
+
+    for (id, field) in cache.0.iter_mut().filter(|(_, field)| field.dirty) {
+        field.dirty = false;
+        let Some(bytes) = images.get_mut(*id).and_then(|image| image.data.as_mut()) else {
+            continue;
+        };
+        if let Some(base_level) = bytes.get_mut(..field.texels.len()) {
+            base_level.copy_from_slice(&field.texels);
+        }
+
+        for mut voxel_data in sources.iter_mut().filter(|voxel_data| {
+            voxel_data
+                .data
+                .as_ref()
+                .is_some_and(|data| data.signed_distance_field.id() == *id)
+        }) {
+            voxel_data.set_changed();
+        }
+    }
+}
+
+/// Forgets cached fields whose image was replaced or changed by something
+/// other than sculpting.
+pub(super) fn invalidate_sculpt_cache(
+    mut events: MessageReader<AssetEvent<Image>>,
+    mut cache: ResMut<SculptCache>,
+) {
+    for event in events.read() {
+        match event {
+            // Edits not yet in the image are newer than the change.
+            AssetEvent::Modified { id } if cache.0.get(id).is_some_and(|field| !field.dirty) => {
+                cache.0.remove(id);
+            }
+            AssetEvent::Removed { id } => {
+                cache.0.remove(id);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Moves the regions edited this frame to the render world.
+pub(super) fn extract_sculpt_uploads(mut commands: Commands, mut main_world: ResMut<MainWorld>) {
+    let uploads = std::mem::take(&mut *main_world.resource_mut::<SculptUploads>());
+    commands.insert_resource(uploads);
+}
+
+/// Writes the edited regions into the GPU textures of their images.
+pub(super) fn upload_sculpt_regions(
+    uploads: Res<SculptUploads>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    render_queue: Res<RenderQueue>,
+) {
+    for upload in &uploads.0 {
+        let Some(gpu_image) = gpu_images.get(upload.image) else {
+            continue;
+        };
+        let texel = upload.texels.len() as u32 / upload.size.element_product();
+        render_queue.write_texture(
+            TexelCopyTextureInfo {
+                texture: &gpu_image.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: upload.origin.x,
+                    y: upload.origin.y,
+                    z: upload.origin.z,
+                },
+                aspect: TextureAspect::All,
+            },
+            &upload.texels,
+            TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(upload.size.x * texel),
+                rows_per_image: Some(upload.size.y),
+            },
+            Extent3d {
+                width: upload.size.x,
+                height: upload.size.y,
+                depth_or_array_layers: upload.size.z,
+            },
+        );
+    }
+}
+
+/// The cached field an edit applies to, unless its field was re-baked.
+fn edited_field<'a>(
+    cache: &'a mut SculptCache,
+    images: &Assets<Image>,
+    sources: &Query<&VoxelizationData>,
+    edit: &SculptEdit,
+) -> Option<&'a mut SculptedField> {
+    let data = sources
+        .iter()
+        .filter_map(|voxel_data| voxel_data.data.as_ref())
+        .find(|data| data.signed_distance_field.id() == edit.image)?;
+    cache.field(data, images)
+}
+
+/// Sphere traces a mesh-local ray through the distance field, returning the
+/// distance along it to the first hit inside the grid.
+fn trace(
+    data: &SignedDistanceFieldData,
+    voxels: &[f32],
+    origin: Vec3,
+    direction: Vec3,
+) -> Option<f32> {
+    let (min, max) = (data.volume.min(), data.volume.max());
+    let voxel_size = data.voxel_size();
+
+    // Slab test against the grid bounds
+    let inverse = direction.recip();
+    let (t0, t1) = ((min - origin) * inverse, (max - origin) * inverse);
+    let t_enter = t0.min(t1).max_element().max(0.0);
+    let t_exit = t0.max(t1).min_element();
+    if t_enter > t_exit {
+        return None;
+    }
+
+    let epsilon = voxel_size.min_element() * 0.1;
+    let mut t = t_enter;
+    for _ in 0..MAX_TRACE_STEPS {
+        if t > t_exit {
+            return None;
+        }
+        let distance = sample(data, voxels, origin + direction * t);
+        if distance < epsilon {
+            return Some(t);
+        }
+        t += distance.max(epsilon);
+    }
+    None
+}
+
+/// Trilinear sample of the decoded base level at a mesh-local point, clamped
+/// to the voxel centers.
+fn sample(data: &SignedDistanceFieldData, voxels: &[f32], p: Vec3) -> f32 {
+    let size = data.volume.grid_size;
+    let g = data
+        .volume
+        .to_voxel(p)
+        .clamp(Vec3::ZERO, Vec3::splat(size as f32 - 1.0));
+    let lo = g.floor().as_uvec3().min(UVec3::splat(size - 1));
+    let hi = (lo + UVec3::ONE).min(UVec3::splat(size - 1));
+    let t = g - lo.as_vec3();
+
+    let value = |x: u32, y: u32, z: u32| {
+        voxels[x as usize + y as usize * size as usize + z as usize * (size as usize).pow(2)]
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(value(lo.x, lo.y, lo.z), value(hi.x, lo.y, lo.z), t.x);
+    let x10 = lerp(value(lo.x, hi.y, lo.z), value(hi.x, hi.y, lo.z), t.x);
+    let x01 = lerp(value(lo.x, lo.y, hi.z), value(hi.x, lo.y, hi.z), t.x);
+    let x11 = lerp(value(lo.x, hi.y, hi.z), value(hi.x, hi.y, hi.z), t.x);
+    lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z)
+}
+
+/// Drops the mip chain of a field, keeping the base level.
+fn strip_mips(data: &mut SignedDistanceFieldData, images: &mut Assets<Image>) {
+    if data.mip_count <= 1 {
+        return;
+    }
+    let Some(image) = images.get_mut(&data.signed_distance_field) else {
+        return;
+    };
+
+    let base_len = data.volume.voxel_count() * data.encoding.format.bytes_per_voxel();
+    if let Some(bytes) = image.data.as_mut() {
+        bytes.truncate(base_len);
+    }
+    image.texture_descriptor.mip_level_count = 1;
+    data.mip_count = 1;
+    info!("Dropped the mip chain of a sculpted distance field.");
+}
+
+/// Copies the stored texels of a region, row by row.
+fn read_region(
+    bytes: &[u8],
+    data: &SignedDistanceFieldData,
+    origin: UVec3,
+    size: UVec3,
+) -> Vec<u8> {
+    let texel = data.encoding.format.bytes_per_voxel();
+    let grid = data.volume.grid_size as usize;
+    let row = size.x as usize * texel;
+
+    let mut region = Vec::with_capacity(size.element_product() as usize * texel);
+    for z in origin.z..origin.z + size.z {
+        for y in origin.y..origin.y + size.y {
+            let start = (origin.x as usize + y as usize * grid + z as usize * grid * grid) * texel;
+            region.extend_from_slice(&bytes[start..start + row]);
+        }
+    }
+    region
+}
+
+/// Writes a region of `texel` elements per voxel into a whole grid, row by
+/// row.
+fn write_region<T: Copy>(
+    grid_values: &mut [T],
+    grid: usize,
+    texel: usize,
+    edit: &SculptEdit,
+    region: &[T],
+) {
+    let row = edit.size.x as usize * texel;
+    let rows = (edit.origin.z..edit.origin.z + edit.size.z)
+        .flat_map(|z| (edit.origin.y..edit.origin.y + edit.size.y).map(move |y| (y, z)));
+    for ((y, z), source) in rows.zip(region.chunks_exact(row)) {
+        let start = (edit.origin.x as usize + y as usize * grid + z as usize * grid * grid) * texel;
+        grid_values[start..start + row].copy_from_slice(source);
+    }
+}
@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+use bevy_app_compute::prelude::{
+    AppComputeWorker, AppComputeWorkerBuilder, ComputeShader, ComputeWorker, ShaderRef, ShaderType,
+};
+use bytemuck::{Pod, Zeroable};
+
+/// Voxels per axis of the largest region a single brush dab can touch.
+pub const MAX_SCULPT_REGION: u32 = 48;
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(Debug, strum::EnumString, strum::Display, strum::AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum SculptVariables {
+    RegionInput,
+    RegionOutput,
+    SculptUniforms,
+}
+
+#[derive(Clone, Copy, Zeroable, Pod, ShaderType)]
+#[repr(C)]
+pub struct SculptUniforms {
+    /// Mesh-local position of the first region voxel center in `xyz`, brush
+    /// radius in `w`.
+    pub region_min: Vec4,
+    /// Voxel size in `xyz`, brush strength in `w`.
+    pub voxel_size: Vec4,
+    /// Brush center in `xyz`.
+    pub center: Vec4,
+    pub region_size: UVec3,
+    /// Index of the [`super::sculpt::BrushMode`] variant.
+    pub mode: u32,
+}
+
+impl SculptUniforms {
+    pub fn new(
+        region_min: Vec3,
+        region_size: UVec3,
+        voxel_size: Vec3,
+        center: Vec3,
+        radius: f32,
+        strength: f32,
+        mode: u32,
+    ) -> Self {
+        Self {
+            region_min: region_min.extend(radius),
+            voxel_size: voxel_size.extend(strength),
+            center: center.extend(0.0),
+            region_size,
+            mode,
+        }
+    }
+}
+
+#[derive(Default, TypePath)]
+pub struct SculptShader;
+
+impl ComputeShader for SculptShader {
+    fn shader() -> ShaderRef {
+        "shaders/sdf_sculpt.compute.wgsl".into()
+    }
+}
+
+#[derive(Resource)]
+pub struct SculptWorker;
+
+impl ComputeWorker for SculptWorker {
+    fn build(world: &mut World) -> AppComputeWorker<Self> {
+        let workgroups = [MAX_SCULPT_REGION.div_ceil(WORKGROUP_SIZE); 3];
+        let region_bytes = (MAX_SCULPT_REGION as u64).pow(3) * 4;
+
+        AppComputeWorkerBuilder::new(world)
+            .add_empty_rw_storage(SculptVariables::RegionInput.as_ref(), region_bytes)
+            .add_empty_staging(SculptVariables::RegionOutput.as_ref(), region_bytes)
+            .add_uniform(
+                SculptVariables::SculptUniforms.as_ref(),
+                &SculptUniforms::new(Vec3::ZERO, UVec3::ZERO, Vec3::ONE, Vec3::ZERO, 0.0, 0.0, 0),
+            )
+            .add_pass::<SculptShader>(
+                workgroups,
+                &[
+                    SculptVariables::RegionInput.as_ref(),
+                    SculptVariables::RegionOutput.as_ref(),
+                    SculptVariables::SculptUniforms.as_ref(),
+                ],
+            )
+            .one_shot()
+            .build()
+    }
+}
@@ -1,10 +1,10 @@
 use crate::{
     utils::input_utils::is_modifier,
-    voxelization::{VoxelizationData, VoxelizationState, voxelization_worker::SIZE},
+    voxelization::{VoxelizationData, VoxelizationState, volume_io::VolumeHeader},
 };
 use bevy::prelude::*;
 use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
-use std::path::Path;
+use std::{fs, path::Path};
 
 const TEMP_DIR: &str = "temp";
 
@@ -101,18 +101,27 @@ pub fn snapshotter(
             continue;
         };
 
-        let Some(raw_data) = &image.data else {
+        if image.data.is_none() {
             error!("Image for entity {:?} has no CPU-accessible data.", entity);
             continue;
-        };
+        }
 
-        let voxels: &[f32] = bytemuck::cast_slice(raw_data);
+        // Only the base level is visualized, decoded from the storage format.
+        let size = voxel_info.volume.grid_size;
+        let Some(voxels) = voxel_info.read_voxels(&images) else {
+            error!(
+                "Image for entity {:?} does not match its grid size or encoding.",
+                entity
+            );
+            continue;
+        };
+        let voxels = voxels.as_slice();
 
         let SliceStack(slices) = match snapshot_type.get() {
-            SnapshotType::Occupancy => occupancy_visualization(voxels),
-            SnapshotType::SignedDistance => signed_distance_visualization(voxels),
-            SnapshotType::AbsoluteDistance => absolute_distance_visualization(voxels),
-            SnapshotType::MaximumSurfaceProjection => max_surface_projection(voxels),
+            SnapshotType::Occupancy => occupancy_visualization(voxels, size),
+            SnapshotType::SignedDistance => signed_distance_visualization(voxels, size),
+            SnapshotType::AbsoluteDistance => absolute_distance_visualization(voxels, size),
+            SnapshotType::MaximumSurfaceProjection => max_surface_projection(voxels, size),
         };
 
         let temp_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(TEMP_DIR);
@@ -126,25 +135,34 @@ pub fn snapshotter(
             });
         }
 
+        // Slices are stacked along y. The header places them back in mesh space.
+        let volume = &voxel_info.volume;
+        let header = VolumeHeader::from_volume(volume, volume.sign_convention);
+        let header_path = temp_path.join(format!("{entity}_{}.json", snapshot_type.get()));
+        _ = serde_json::to_string_pretty(&header)
+            .map_err(std::io::Error::from)
+            .and_then(|json| fs::write(header_path, json))
+            .map_err(|e| error!("Failed to save snapshot header for entity {entity:?}: {e}"));
+
         info!("Snapshot(s) for entity {:?} completed.", entity);
     }
 }
 
-fn signed_distance_visualization(voxels: &[f32]) -> SliceStack {
+fn signed_distance_visualization(voxels: &[f32], size: u32) -> SliceStack {
     // Find min/max for normalization
     let min_val = voxels.iter().cloned().fold(f32::INFINITY, f32::min);
     let max_val = voxels.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
     let abs_max = min_val.abs().max(max_val.abs());
     info!(min = min_val, max = max_val, abs_max = abs_max);
 
-    let mut slices = Vec::with_capacity(SIZE as usize);
+    let mut slices = Vec::with_capacity(size as usize);
 
-    for y in 0..SIZE {
-        let mut img = RgbImage::new(SIZE, SIZE);
+    for y in 0..size {
+        let mut img = RgbImage::new(size, size);
 
-        for z in 0..SIZE {
-            for x in 0..SIZE {
-                let index = (x + y * SIZE + z * SIZE * SIZE) as usize;
+        for z in 0..size {
+            for x in 0..size {
+                let index = (x + y * size + z * size * size) as usize;
                 let value = voxels[index];
 
                 // Normalize value to [-1.0, 1.0]
@@ -160,7 +178,7 @@ fn signed_distance_visualization(voxels: &[f32]) -> SliceStack {
                 };
 
                 let img_x = z;
-                let img_y = SIZE - x - 1;
+                let img_y = size - x - 1;
                 img.put_pixel(img_x, img_y, Rgb([r, g, b]));
             }
         }
@@ -170,7 +188,7 @@ fn signed_distance_visualization(voxels: &[f32]) -> SliceStack {
     SliceStack(slices)
 }
 
-fn absolute_distance_visualization(voxels: &[f32]) -> SliceStack {
+fn absolute_distance_visualization(voxels: &[f32], size: u32) -> SliceStack {
     let unsigned_voxels = voxels.iter().map(|f| f.abs()).collect::<Vec<_>>();
 
     // Find min/max for normalization
@@ -184,14 +202,14 @@ fn absolute_distance_visualization(voxels: &[f32]) -> SliceStack {
         .fold(f32::NEG_INFINITY, f32::max);
     info!(min = min_val, max = max_val);
 
-    let mut slices = Vec::with_capacity(SIZE as usize);
+    let mut slices = Vec::with_capacity(size as usize);
 
-    for y in 0..SIZE {
-        let mut img = GrayImage::new(SIZE, SIZE);
+    for y in 0..size {
+        let mut img = GrayImage::new(size, size);
 
-        for z in 0..SIZE {
-            for x in 0..SIZE {
-                let index = (x + y * SIZE + z * SIZE * SIZE) as usize;
+        for z in 0..size {
+            for x in 0..size {
+                let index = (x + y * size + z * size * size) as usize;
                 let value = unsigned_voxels[index];
 
                 // Normalize to 0..255
@@ -199,7 +217,7 @@ fn absolute_distance_visualization(voxels: &[f32]) -> SliceStack {
                 let pixel_value = (normalized * 255.0) as u8;
 
                 let img_x = z;
-                let img_y = SIZE - x - 1;
+                let img_y = size - x - 1;
                 img.put_pixel(img_x, img_y, Luma([pixel_value]));
             }
         }
@@ -209,20 +227,20 @@ fn absolute_distance_visualization(voxels: &[f32]) -> SliceStack {
     SliceStack(slices)
 }
 
-fn occupancy_visualization(voxels: &[f32]) -> SliceStack {
+fn occupancy_visualization(voxels: &[f32], size: u32) -> SliceStack {
     // Find min/max for normalization
     let min_val = voxels.iter().cloned().fold(f32::INFINITY, f32::min);
     let max_val = voxels.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
     info!(min = min_val, max = max_val);
 
-    let mut slices = Vec::with_capacity(SIZE as usize);
+    let mut slices = Vec::with_capacity(size as usize);
 
-    for y in 0..SIZE {
-        let mut img = GrayImage::new(SIZE, SIZE);
+    for y in 0..size {
+        let mut img = GrayImage::new(size, size);
 
-        for z in 0..SIZE {
-            for x in 0..SIZE {
-                let index = (x + y * SIZE + z * SIZE * SIZE) as usize;
+        for z in 0..size {
+            for x in 0..size {
+                let index = (x + y * size + z * size * size) as usize;
                 let value = voxels[index];
 
                 // Normalize to 0..255
@@ -230,7 +248,7 @@ fn occupancy_visualization(voxels: &[f32]) -> SliceStack {
                 let pixel_value = (normalized * 255.0) as u8;
 
                 let img_x = z;
-                let img_y = SIZE - x - 1;
+                let img_y = size - x - 1;
                 img.put_pixel(img_x, img_y, Luma([pixel_value]));
             }
         }
@@ -240,15 +258,15 @@ fn occupancy_visualization(voxels: &[f32]) -> SliceStack {
     SliceStack(slices)
 }
 
-fn max_surface_projection(voxels: &[f32]) -> SliceStack {
-    let mut img = GrayImage::new(SIZE, SIZE);
+fn max_surface_projection(voxels: &[f32], size: u32) -> SliceStack {
+    let mut img = GrayImage::new(size, size);
 
-    for z in 0..SIZE {
-        for x in 0..SIZE {
+    for z in 0..size {
+        for x in 0..size {
             let mut max_density = 0.0f32;
 
-            for y in 0..SIZE {
-                let index = (x + y * SIZE + z * SIZE * SIZE) as usize;
+            for y in 0..size {
+                let index = (x + y * size + z * size * size) as usize;
                 let d = voxels[index].abs();
 
                 // surface-enhanced density
@@ -260,7 +278,7 @@ fn max_surface_projection(voxels: &[f32]) -> SliceStack {
             let pixel_value = ((max_density / (max_density + 10.0)) * 255.0) as u8;
 
             let img_x = z;
-            let img_y = SIZE - 1 - x;
+            let img_y = size - 1 - x;
 
             img.put_pixel(img_x, img_y, Luma([pixel_value]));
         }
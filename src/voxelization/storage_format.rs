@@ -0,0 +1,191 @@
+use bevy::render::{render_resource::TextureFormat, settings::WgpuFeatures};
+use half::f16;
+
+/// Texel format the baked signed distance field is stored in.
+///
+/// The normalized formats store `distance / band_width` clamped to `[-1, 1]`,
+/// so distances beyond the band saturate. Every encoding rounds towards zero
+/// and never overestimates a distance, keeping the field safe to sphere trace.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum SdfStorageFormat {
+    #[default]
+    R32Float,
+    R16Float,
+    /// Requires `TEXTURE_FORMAT_16BIT_NORM` on the render device. Bakes fall
+    /// back to `R16Float` without it.
+    R16Snorm,
+    R8Snorm,
+}
+
+impl SdfStorageFormat {
+    pub fn texture_format(&self) -> TextureFormat {
+        match self {
+            Self::R32Float => TextureFormat::R32Float,
+            Self::R16Float => TextureFormat::R16Float,
+            Self::R16Snorm => TextureFormat::R16Snorm,
+            Self::R8Snorm => TextureFormat::R8Snorm,
+        }
+    }
+
+    pub fn bytes_per_voxel(&self) -> usize {
+        match self {
+            Self::R32Float => 4,
+            Self::R16Float | Self::R16Snorm => 2,
+            Self::R8Snorm => 1,
+        }
+    }
+
+    /// Device features needed to create textures of this format.
+    pub fn required_features(&self) -> WgpuFeatures {
+        match self {
+            Self::R16Snorm => WgpuFeatures::TEXTURE_FORMAT_16BIT_NORM,
+            _ => WgpuFeatures::empty(),
+        }
+    }
+
+    pub fn is_normalized(&self) -> bool {
+        matches!(self, Self::R16Snorm | Self::R8Snorm)
+    }
+
+    /// Negates encoded texels in place. Every format is symmetric around
+    /// zero, so this is exact.
+    pub fn negate(&self, texels: &mut [u8]) {
+        match self {
+            // Flip the sign bit, stored in the last byte
+            Self::R32Float | Self::R16Float => texels
+                .chunks_exact_mut(self.bytes_per_voxel())
+                .for_each(|texel| texel[texel.len() - 1] ^= 0x80),
+            Self::R16Snorm => texels.chunks_exact_mut(2).for_each(|texel| {
+                let value = i16::from_le_bytes([texel[0], texel[1]]);
+                texel.copy_from_slice(&value.saturating_neg().to_le_bytes());
+            }),
+            Self::R8Snorm => texels
+                .iter_mut()
+                .for_each(|texel| *texel = (*texel as i8).saturating_neg() as u8),
+        }
+    }
+}
+
+/// Storage format of a baked field together with the factor that maps stored
+/// values back to distances.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SdfEncoding {
+    pub format: SdfStorageFormat,
+    /// Decoded distance = sampled value * `distance_scale`. This is the band
+    /// width for normalized formats and 1 otherwise.
+    pub distance_scale: f32,
+}
+
+impl Default for SdfEncoding {
+    fn default() -> Self {
+        Self::new(SdfStorageFormat::R32Float, 1.0)
+    }
+}
+
+/// Error introduced by encoding a field, in distance units. Voxels beyond the
+/// band of a normalized format are counted as clamped and left out of the
+/// error statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct QuantizationError {
+    pub max: f32,
+    pub rms: f32,
+    pub clamped: usize,
+}
+
+impl SdfEncoding {
+    /// `band_width` is only used by the normalized formats.
+    pub fn new(format: SdfStorageFormat, band_width: f32) -> Self {
+        let distance_scale = if format.is_normalized() {
+            band_width
+        } else {
+            1.0
+        };
+
+        Self {
+            format,
+            distance_scale,
+        }
+    }
+
+    pub fn encode(&self, voxels: &[f32]) -> Vec<u8> {
+        match self.format {
+            SdfStorageFormat::R32Float => voxels.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            SdfStorageFormat::R16Float => voxels
+                .iter()
+                .flat_map(|&v| f16_towards_zero(v).to_le_bytes())
+                .collect(),
+            SdfStorageFormat::R16Snorm => voxels
+                .iter()
+                .map(|&v| (self.normalize(v) * i16::MAX as f32).trunc() as i16)
+                .flat_map(i16::to_le_bytes)
+                .collect(),
+            SdfStorageFormat::R8Snorm => voxels
+                .iter()
+                .map(|&v| (self.normalize(v) * i8::MAX as f32).trunc() as i8 as u8)
+                .collect(),
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        match self.format {
+            SdfStorageFormat::R32Float => bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            SdfStorageFormat::R16Float => bytes
+                .chunks_exact(2)
+                .map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32())
+                .collect(),
+            SdfStorageFormat::R16Snorm => bytes
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .map(|v| (v as f32 / i16::MAX as f32).max(-1.0) * self.distance_scale)
+                .collect(),
+            SdfStorageFormat::R8Snorm => bytes
+                .iter()
+                .map(|&v| (v as i8 as f32 / i8::MAX as f32).max(-1.0) * self.distance_scale)
+                .collect(),
+        }
+    }
+
+    pub fn quantization_error(&self, voxels: &[f32], decoded: &[f32]) -> QuantizationError {
+        let band = self.format.is_normalized().then_some(self.distance_scale);
+
+        let mut error = QuantizationError::default();
+        let mut sum_squared = 0.0f64;
+        let mut counted = 0usize;
+
+        for (&original, &decoded) in voxels.iter().zip(decoded) {
+            if band.is_some_and(|band| original.abs() > band) {
+                error.clamped += 1;
+                continue;
+            }
+
+            let e = (original - decoded).abs();
+            error.max = error.max.max(e);
+            sum_squared += (e as f64).powi(2);
+            counted += 1;
+        }
+
+        if counted > 0 {
+            error.rms = (sum_squared / counted as f64).sqrt() as f32;
+        }
+        error
+    }
+
+    fn normalize(&self, value: f32) -> f32 {
+        (value / self.distance_scale).clamp(-1.0, 1.0)
+    }
+}
+
+/// Nearest half float, stepped one ulp towards zero if rounding increased the
+/// magnitude.
+fn f16_towards_zero(value: f32) -> f16 {
+    let half = f16::from_f32(value);
+    if half.to_f32().abs() > value.abs() && half.to_bits() & 0x7fff != 0 {
+        f16::from_bits(half.to_bits() - 1)
+    } else {
+        half
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{
+    gpu_types::GpuBox3,
+    utils::input_utils::is_modifier,
+    voxelization::{
+        SdfVolume, SignedDistanceFieldData, VoxelizationCompleted, VoxelizationData,
+        VoxelizationError, VoxelizationSettings, VoxelizationStarted, VoxelizationState,
+        storage_format::SdfEncoding, voxelization_systems::sdf_image,
+    },
+};
+use bevy::{platform::time::Instant, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+mod ktx2;
+mod nrrd;
+mod raw;
+mod vdb;
+
+const TEMP_DIR: &str = "temp";
+/// Narrow band half width of VDB exports without explicit settings, in voxels.
+/// Matches the OpenVDB level set default.
+pub const VDB_HALF_WIDTH: f32 = 3.0;
+
+/// Which side of the surface has negative distances.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SignConvention {
+    /// Distances are negative inside the mesh. This is what the voxelizer produces.
+    #[default]
+    NegativeInside,
+    PositiveInside,
+}
+
+/// File format of an exported volume.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    strum::EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum VolumeFormat {
+    /// Little-endian `f32` voxels with a JSON header next to them.
+    #[default]
+    Raw,
+    Nrrd,
+    /// The stored texels with their mip chain, see [`write_ktx2`].
+    Ktx2,
+    /// OpenVDB level set, see [`VolumeFile::write_vdb`].
+    Vdb,
+}
+
+impl VolumeFormat {
+    /// The format matching the extension of `path`, raw for unknown ones.
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| e.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Metadata needed to place a distance grid back into mesh-local space.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VolumeHeader {
+    /// Voxels along x, y and z. Voxel `(x, y, z)` is stored at
+    /// `x + y * dimensions[0] + z * dimensions[0] * dimensions[1]`.
+    pub dimensions: [u32; 3],
+    /// Mesh-local bounds of the padded grid. Voxel centers sit at the centers
+    /// of equal cells of this box.
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+    /// Padding that was added around the mesh bounds, relative to its extent.
+    pub padding_ratio: f32,
+    pub voxel_size: [f32; 3],
+    pub sign_convention: SignConvention,
+}
+
+impl VolumeHeader {
+    /// Header of `volume` with its values written following `sign_convention`.
+    pub fn from_volume(volume: &SdfVolume, sign_convention: SignConvention) -> Self {
+        Self {
+            dimensions: volume.dimensions().to_array(),
+            bounds_min: volume.min().to_array(),
+            bounds_max: volume.max().to_array(),
+            padding_ratio: volume.padding_ratio,
+            voxel_size: volume.voxel_size().to_array(),
+            sign_convention,
+        }
+    }
+
+    pub fn voxel_count(&self) -> usize {
+        self.dimensions.iter().map(|&d| d as usize).product()
+    }
+
+    pub fn bounds(&self) -> GpuBox3 {
+        GpuBox3::new(
+            Vec3::from_array(self.bounds_min).into(),
+            Vec3::from_array(self.bounds_max).into(),
+        )
+    }
+}
+
+/// A decoded distance grid with its metadata, as stored on disk.
+#[derive(Debug, Clone)]
+pub struct VolumeFile {
+    pub header: VolumeHeader,
+    /// Distances following `header.sign_convention`.
+    pub voxels: Vec<f32>,
+}
+
+impl VolumeFile {
+    /// Decodes the base level of a baked field. `None` if the image is not
+    /// available on the CPU.
+    pub fn from_sdf(
+        data: &SignedDistanceFieldData,
+        images: &Assets<Image>,
+        sign_convention: SignConvention,
+    ) -> Option<Self> {
+        let mut voxels = data.read_voxels(images)?;
+        if sign_convention != data.volume.sign_convention {
+            voxels.iter_mut().for_each(|v| *v = -*v);
+        }
+
+        Some(Self {
+            header: VolumeHeader::from_volume(&data.volume, sign_convention),
+            voxels,
+        })
+    }
+
+    /// Writes NRRD for `.nrrd` paths, an OpenVDB level set with a
+    /// [`VDB_HALF_WIDTH`] band for `.vdb` paths and raw + JSON header otherwise.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("nrrd") => nrrd::write(path, self),
+            Some("vdb") => self.write_vdb(path, VDB_HALF_WIDTH),
+            _ => raw::write(path, self),
+        }
+    }
+
+    /// Writes an OpenVDB file holding a single float level-set grid that keeps
+    /// the voxels within `half_width` voxels of the surface. Everything else
+    /// takes the `±half_width * voxel size` background value.
+    pub fn write_vdb(&self, path: &Path, half_width: f32) -> io::Result<()> {
+        vdb::write(path, self, half_width)
+    }
+
+    /// Reads NRRD for `.nrrd` paths and raw + JSON header otherwise.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let volume = match path.extension().and_then(|e| e.to_str()) {
+            Some("nrrd") => nrrd::read(path)?,
+            _ => raw::read(path)?,
+        };
+
+        if volume.voxels.len() != volume.header.voxel_count() {
+            return Err(invalid_data(format!(
+                "expected {} voxels, found {}",
+                volume.header.voxel_count(),
+                volume.voxels.len()
+            )));
+        }
+
+        Ok(volume)
+    }
+
+    /// Recreates the distance field as an `R32Float` image without mips.
+    pub fn into_sdf(self, images: &mut Assets<Image>) -> io::Result<SignedDistanceFieldData> {
+        let [x, y, z] = self.header.dimensions;
+        if x != y || y != z {
+            return Err(invalid_data(format!(
+                "only cubic grids are supported, found {x}x{y}x{z}"
+            )));
+        }
+
+        let mut voxels = self.voxels;
+        if self.header.sign_convention == SignConvention::PositiveInside {
+            voxels.iter_mut().for_each(|v| *v = -*v);
+        }
+
+        let encoding = SdfEncoding::default();
+        let image = sdf_image(x, 1, &encoding, encoding.encode(&voxels));
+
+        Ok(SignedDistanceFieldData {
+            signed_distance_field: images.add(image),
+            volume: SdfVolume::new(x, self.header.bounds(), self.header.padding_ratio),
+            mip_count: 1,
+            encoding,
+            closest_features: None,
+            albedo: None,
+            occupancy: None,
+        })
+    }
+}
+
+/// Writes the stored texels of a baked field, mip chain included, as a KTX2
+/// 3D texture, with distances following `sign_convention`. The bounds, voxel
+/// size, sign convention and distance scale are recorded as JSON under the
+/// `distill.volume` key.
+pub fn write_ktx2(
+    path: &Path,
+    data: &SignedDistanceFieldData,
+    images: &Assets<Image>,
+    sign_convention: SignConvention,
+) -> io::Result<()> {
+    let image = images
+        .get(&data.signed_distance_field)
+        .ok_or_else(|| invalid_data("distance field image is not loaded"))?;
+
+    let bytes = ktx2::encode(data, image, sign_convention)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, bytes)
+}
+
+/// Writes a baked field as `format`. OpenVDB level sets keep a narrow band of
+/// [`VDB_HALF_WIDTH`] voxels, KTX2 keeps the storage format and mips of the
+/// bake.
+pub fn export_volume(
+    path: &Path,
+    format: VolumeFormat,
+    sign_convention: SignConvention,
+    data: &SignedDistanceFieldData,
+    images: &Assets<Image>,
+) -> io::Result<()> {
+    if format == VolumeFormat::Ktx2 {
+        return write_ktx2(path, data, images, sign_convention);
+    }
+
+    let volume = VolumeFile::from_sdf(data, images, sign_convention)
+        .ok_or_else(|| invalid_data("distance field has no CPU-accessible data"))?;
+    match format {
+        VolumeFormat::Vdb => volume.write_vdb(path, VDB_HALF_WIDTH),
+        VolumeFormat::Nrrd => nrrd::write(path, &volume),
+        _ => raw::write(path, &volume),
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Loads the distance field of an entity from a file written by
+/// [`VolumeFile::write`] instead of baking it. If the file cannot be read the
+/// job fails with [`VoxelizationError::Load`], the entity is not baked.
+#[derive(Debug, Clone, Component)]
+pub struct LoadBakedVolume(pub PathBuf);
+
+pub(super) fn load_baked_volumes(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    query: Query<(Entity, &LoadBakedVolume), Without<VoxelizationData>>,
+    mut started: MessageWriter<VoxelizationStarted>,
+    mut completed: MessageWriter<VoxelizationCompleted>,
+) {
+    for (entity, LoadBakedVolume(path)) in query.iter() {
+        let start = Instant::now();
+        let loaded = VolumeFile::read(path).and_then(|volume| {
+            let settings = VoxelizationSettings {
+                resolution: volume.header.dimensions[0],
+                padding_ratio: volume.header.padding_ratio,
+                ..default()
+            };
+            Ok((settings, volume.into_sdf(&mut images)?))
+        });
+
+        match loaded {
+            Ok((settings, data)) => {
+                info!("Loaded baked volume {path:?} for entity {entity:?}.");
+                let mut voxel_data = VoxelizationData::in_progress(settings, Some(data));
+                voxel_data.started = start;
+                let duration = voxel_data.complete();
+                commands.entity(entity).insert(voxel_data);
+                started.write(VoxelizationStarted { entity, settings });
+                completed.write(VoxelizationCompleted {
+                    entity,
+                    settings,
+                    duration,
+                });
+            }
+            Err(e) => {
+                let mut voxel_data = VoxelizationData::failed(
+                    VoxelizationSettings::default(),
+                    VoxelizationError::Load(format!("{path:?}: {e}")),
+                );
+                voxel_data.started = start;
+                commands.entity(entity).insert(voxel_data);
+            }
+        }
+    }
+}
+
+/// Writes the distance field of an entity to the given path with
+/// [`export_volume`] once it is baked, then removes itself.
+#[derive(Debug, Clone, Component)]
+pub struct ExportBakedVolume {
+    pub path: PathBuf,
+    pub format: VolumeFormat,
+    pub sign_convention: SignConvention,
+}
+
+impl ExportBakedVolume {
+    /// Exports with negative distances inside, in the format matching the
+    /// extension of `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            format: VolumeFormat::from_path(&path),
+            path,
+            sign_convention: SignConvention::NegativeInside,
+        }
+    }
+}
+
+/// Sent when an [`ExportBakedVolume`] request was processed.
+#[derive(Debug, Clone, Message)]
+pub struct VolumeExported {
+    pub entity: Entity,
+    pub path: PathBuf,
+    /// The error message if the export failed.
+    pub error: Option<String>,
+}
+
+pub(super) fn export_requested_volumes(
+    mut commands: Commands,
+    query: Query<(Entity, &VoxelizationData, &ExportBakedVolume)>,
+    images: Res<Assets<Image>>,
+    mut exported: MessageWriter<VolumeExported>,
+) {
+    for (entity, voxel_data, export) in query.iter() {
+        let path = &export.path;
+        if voxel_data.state != VoxelizationState::Computed {
+            continue;
+        }
+
+        let result = voxel_data
+            .data
+            .as_ref()
+            .ok_or_else(|| invalid_data("no distance field was baked"))
+            .and_then(|data| {
+                export_volume(path, export.format, export.sign_convention, data, &images)
+            });
+
+        match &result {
+            Ok(()) => info!("Exported volume of entity {entity:?} to {path:?}."),
+            Err(e) => error!("Failed to export volume of entity {entity:?} to {path:?}: {e}"),
+        }
+
+        exported.write(VolumeExported {
+            entity,
+            path: path.clone(),
+            error: result.err().map(|e| e.to_string()),
+        });
+        commands.entity(entity).remove::<ExportBakedVolume>();
+    }
+}
+
+/// E writes every computed volume as raw + JSON, NRRD, KTX2 and OpenVDB.
+pub fn exporter(
+    input: Res<ButtonInput<KeyCode>>,
+    voxel_query: Query<(Entity, &VoxelizationData)>,
+    images: Res<Assets<Image>>,
+) {
+    if !input.just_pressed(KeyCode::KeyE) || input.get_pressed().any(|key| is_modifier(*key)) {
+        return;
+    }
+
+    let temp_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(TEMP_DIR);
+
+    for (entity, voxel_data) in voxel_query.iter() {
+        if voxel_data.state != VoxelizationState::Computed {
+            warn!("Voxelization for entity {entity:?} is not yet computed. Skipping export.");
+            continue;
+        }
+
+        let Some(data) = &voxel_data.data else {
+            continue;
+        };
+
+        for format in [
+            VolumeFormat::Raw,
+            VolumeFormat::Nrrd,
+            VolumeFormat::Ktx2,
+            VolumeFormat::Vdb,
+        ] {
+            let path = temp_path.join(format!("{entity}.{format}"));
+            match export_volume(&path, format, SignConvention::NegativeInside, data, &images) {
+                Ok(()) => info!("Exported volume of entity {entity:?} to {path:?}."),
+                Err(e) => error!("Failed to export volume of entity {entity:?} to {path:?}: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use super::{SignConvention, VolumeHeader, invalid_data};
+use crate::voxelization::{
+    SignedDistanceFieldData, storage_format::SdfStorageFormat, voxelization_worker::mip_level_size,
+};
+use bevy::prelude::*;
+use serde::Serialize;
+use std::{borrow::Cow, io};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_LENGTH: usize = 12 + 9 * 4 + 4 * 4 + 2 * 8;
+const LEVEL_INDEX_ENTRY_LENGTH: usize = 3 * 8;
+/// Level data alignment, `lcm(texel block size, 4)` for every supported format.
+const LEVEL_ALIGNMENT: usize = 4;
+
+const KHR_DF_MODEL_RGBSDA: u8 = 1;
+const KHR_DF_PRIMARIES_BT709: u8 = 1;
+const KHR_DF_TRANSFER_LINEAR: u8 = 1;
+const KHR_DF_SAMPLE_DATATYPE_SIGNED: u8 = 0x40;
+const KHR_DF_SAMPLE_DATATYPE_FLOAT: u8 = 0x80;
+
+/// Metadata stored under the `distill.volume` key, as JSON.
+#[derive(Serialize)]
+struct Ktx2Metadata {
+    #[serde(flatten)]
+    volume: VolumeHeader,
+    storage_format: String,
+    /// Decoded distance = sampled value * `distance_scale`.
+    distance_scale: f32,
+}
+
+/// Encodes the stored texels of a baked field, mip chain included, as an
+/// uncompressed KTX2 3D texture, negated if `sign_convention` differs from
+/// the one of the field.
+pub(super) fn encode(
+    data: &SignedDistanceFieldData,
+    image: &Image,
+    sign_convention: SignConvention,
+) -> io::Result<Vec<u8>> {
+    let texels = image
+        .data
+        .as_deref()
+        .ok_or_else(|| invalid_data("distance field has no CPU-accessible data"))?;
+    let mut texels = Cow::Borrowed(texels);
+    if sign_convention != data.volume.sign_convention {
+        data.encoding.format.negate(texels.to_mut());
+    }
+
+    let format = data.encoding.format;
+    let size = data.volume.grid_size;
+    let level_lengths = (0..data.mip_count)
+        .map(|level| (mip_level_size(size, level) as usize).pow(3) * format.bytes_per_voxel())
+        .collect::<Vec<_>>();
+    if level_lengths.iter().sum::<usize>() != texels.len() {
+        return Err(invalid_data(
+            "distance field data does not match its mip chain",
+        ));
+    }
+
+    let dfd = data_format_descriptor(format);
+    let metadata = Ktx2Metadata {
+        volume: VolumeHeader::from_volume(&data.volume, sign_convention),
+        storage_format: format.to_string(),
+        distance_scale: data.encoding.distance_scale,
+    };
+    // Keys are sorted by code point.
+    let kvd = key_value_data(&[
+        ("KTXwriter", "distill"),
+        ("distill.volume", &serde_json::to_string(&metadata)?),
+    ]);
+
+    let dfd_offset = HEADER_LENGTH + level_lengths.len() * LEVEL_INDEX_ENTRY_LENGTH;
+    let kvd_offset = dfd_offset + dfd.len();
+    let mut level_offset = align(kvd_offset + kvd.len(), LEVEL_ALIGNMENT);
+
+    // Level data is stored from the smallest mip to the base level.
+    let mut level_offsets = vec![0; level_lengths.len()];
+    for (level, &length) in level_lengths.iter().enumerate().rev() {
+        level_offsets[level] = level_offset;
+        level_offset = align(level_offset + length, LEVEL_ALIGNMENT);
+    }
+
+    let mut out = Vec::with_capacity(level_offset);
+    out.extend_from_slice(&IDENTIFIER);
+    for value in [
+        vk_format(format),
+        format.bytes_per_voxel() as u32,
+        size,
+        size,
+        size,
+        0, // layerCount
+        1, // faceCount
+        data.mip_count,
+        0, // supercompressionScheme
+        dfd_offset as u32,
+        dfd.len() as u32,
+        kvd_offset as u32,
+        kvd.len() as u32,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    // No supercompression global data.
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+
+    for (&offset, &length) in level_offsets.iter().zip(&level_lengths) {
+        for value in [offset, length, length] {
+            out.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+    }
+
+    out.extend_from_slice(&dfd);
+    out.extend_from_slice(&kvd);
+
+    let level_starts = level_lengths
+        .iter()
+        .scan(0, |start, &length| {
+            let level_start = *start;
+            *start += length;
+            Some(level_start)
+        })
+        .collect::<Vec<_>>();
+    for level in (0..level_lengths.len()).rev() {
+        let start = level_starts[level];
+        out.resize(level_offsets[level], 0);
+        out.extend_from_slice(&texels[start..start + level_lengths[level]]);
+    }
+
+    Ok(out)
+}
+
+fn vk_format(format: SdfStorageFormat) -> u32 {
+    match format {
+        SdfStorageFormat::R32Float => 100, // VK_FORMAT_R32_SFLOAT
+        SdfStorageFormat::R16Float => 76,  // VK_FORMAT_R16_SFLOAT
+        SdfStorageFormat::R16Snorm => 71,  // VK_FORMAT_R16_SNORM
+        SdfStorageFormat::R8Snorm => 10,   // VK_FORMAT_R8_SNORM
+    }
+}
+
+/// Basic data format descriptor of a single-channel format.
+fn data_format_descriptor(format: SdfStorageFormat) -> Vec<u8> {
+    const BLOCK_LENGTH: u16 = 24 + 16;
+
+    let bytes = format.bytes_per_voxel();
+    let (channel_type, lower, upper) = match format {
+        SdfStorageFormat::R32Float | SdfStorageFormat::R16Float => (
+            KHR_DF_SAMPLE_DATATYPE_FLOAT | KHR_DF_SAMPLE_DATATYPE_SIGNED,
+            (-1.0f32).to_bits(),
+            1.0f32.to_bits(),
+        ),
+        SdfStorageFormat::R16Snorm => (
+            KHR_DF_SAMPLE_DATATYPE_SIGNED,
+            -(i16::MAX as i32) as u32,
+            i16::MAX as u32,
+        ),
+        SdfStorageFormat::R8Snorm => (
+            KHR_DF_SAMPLE_DATATYPE_SIGNED,
+            -(i8::MAX as i32) as u32,
+            i8::MAX as u32,
+        ),
+    };
+
+    let mut dfd = Vec::with_capacity(4 + BLOCK_LENGTH as usize);
+    dfd.extend_from_slice(&(4 + BLOCK_LENGTH as u32).to_le_bytes());
+    // Khronos vendor, basic descriptor type
+    dfd.extend_from_slice(&0u32.to_le_bytes());
+    dfd.extend_from_slice(&2u16.to_le_bytes());
+    dfd.extend_from_slice(&BLOCK_LENGTH.to_le_bytes());
+    dfd.extend_from_slice(&[
+        KHR_DF_MODEL_RGBSDA,
+        KHR_DF_PRIMARIES_BT709,
+        KHR_DF_TRANSFER_LINEAR,
+        0, // flags
+    ]);
+    // 1x1x1 texel blocks
+    dfd.extend_from_slice(&[0; 4]);
+    dfd.extend_from_slice(&[bytes as u8, 0, 0, 0, 0, 0, 0, 0]);
+
+    // Single red sample
+    dfd.extend_from_slice(&0u16.to_le_bytes());
+    dfd.push((bytes * 8 - 1) as u8);
+    dfd.push(channel_type);
+    dfd.extend_from_slice(&[0; 4]);
+    dfd.extend_from_slice(&lower.to_le_bytes());
+    dfd.extend_from_slice(&upper.to_le_bytes());
+
+    dfd
+}
+
+fn key_value_data(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut kvd = Vec::new();
+    for (key, value) in entries {
+        let length = key.len() + value.len() + 2;
+        kvd.extend_from_slice(&(length as u32).to_le_bytes());
+        kvd.extend_from_slice(key.as_bytes());
+        kvd.push(0);
+        kvd.extend_from_slice(value.as_bytes());
+        kvd.push(0);
+        kvd.resize(align(kvd.len(), 4), 0);
+    }
+    kvd
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.next_multiple_of(alignment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gpu_types::GpuBox3,
+        voxelization::{
+            SdfVolume,
+            storage_format::SdfEncoding,
+            voxelization_systems::sdf_image,
+            voxelization_worker::{mip_chain_voxel_count, mip_level_count},
+        },
+    };
+    use bevy::{
+        asset::RenderAssetUsages,
+        image::{CompressedImageFormats, ImageSampler, ImageType},
+    };
+
+    const SIZE: u32 = 8;
+
+    /// Loads `bytes` back through Bevy's KTX2 loader and checks that it
+    /// yields the same texture as `expected`.
+    fn verify_round_trip(bytes: &[u8], expected: &Image) -> io::Result<()> {
+        let loaded = Image::from_buffer(
+            bytes,
+            ImageType::Extension("ktx2"),
+            CompressedImageFormats::NONE,
+            false,
+            ImageSampler::Default,
+            RenderAssetUsages::MAIN_WORLD,
+        )
+        .map_err(|e| invalid_data(format!("Bevy failed to load the KTX2 file: {e}")))?;
+
+        let mismatch = |what: &str| Err(invalid_data(format!("round-tripped {what} differs")));
+        let (loaded_desc, expected_desc) =
+            (&loaded.texture_descriptor, &expected.texture_descriptor);
+
+        if loaded_desc.format != expected_desc.format {
+            return mismatch("format");
+        }
+        if loaded_desc.size != expected_desc.size
+            || loaded_desc.dimension != expected_desc.dimension
+        {
+            return mismatch("extent");
+        }
+        if loaded_desc.mip_level_count != expected_desc.mip_level_count {
+            return mismatch("mip count");
+        }
+        if loaded.data != expected.data {
+            return mismatch("texel data");
+        }
+
+        Ok(())
+    }
+
+    /// A field of `SIZE`³ voxels of both signs, some beyond the snorm band.
+    fn field(format: SdfStorageFormat, mip_count: u32) -> (SignedDistanceFieldData, Image) {
+        let voxel_count = (SIZE as usize).pow(3) + mip_chain_voxel_count(SIZE, mip_count);
+        let voxels = (0..voxel_count)
+            .map(|i| (i as f32 * 0.37).sin() * 6.0)
+            .collect::<Vec<_>>();
+
+        let encoding = SdfEncoding::new(format, 4.0);
+        let image = sdf_image(SIZE, mip_count, &encoding, encoding.encode(&voxels));
+        let data = SignedDistanceFieldData {
+            signed_distance_field: Handle::default(),
+            volume: SdfVolume::new(
+                SIZE,
+                GpuBox3::new(Vec3::NEG_ONE.into(), Vec3::ONE.into()),
+                0.05,
+            ),
+            mip_count,
+            encoding,
+            closest_features: None,
+            albedo: None,
+            occupancy: None,
+        };
+        (data, image)
+    }
+
+    #[test]
+    fn round_trips_through_bevy_loader() {
+        let formats = [
+            SdfStorageFormat::R32Float,
+            SdfStorageFormat::R16Float,
+            SdfStorageFormat::R16Snorm,
+            SdfStorageFormat::R8Snorm,
+        ];
+        for format in formats {
+            for mip_count in [1, mip_level_count(SIZE)] {
+                let (data, image) = field(format, mip_count);
+                let bytes = encode(&data, &image, data.volume.sign_convention).unwrap();
+                if let Err(e) = verify_round_trip(&bytes, &image) {
+                    panic!("{format} with {mip_count} mip level(s): {e}");
+                }
+            }
+        }
+    }
+}
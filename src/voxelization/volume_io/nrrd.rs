@@ -0,0 +1,285 @@
+use super::{SignConvention, VolumeFile, VolumeHeader, invalid_data};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+/// Prefix of the key/value pairs holding the fields NRRD has no field for.
+const KEY_PREFIX: &str = "distill_";
+
+/// Writes a single-file NRRD with an attached raw `float` payload. The space
+/// origin is the center of the first voxel, as NRRD samples are cell
+/// centered.
+pub(super) fn write(path: &Path, volume: &VolumeFile) -> io::Result<()> {
+    let VolumeHeader {
+        dimensions: [nx, ny, nz],
+        bounds_min,
+        bounds_max,
+        padding_ratio,
+        voxel_size: [vx, vy, vz],
+        sign_convention,
+    } = volume.header;
+    let origin = [
+        bounds_min[0] + vx * 0.5,
+        bounds_min[1] + vy * 0.5,
+        bounds_min[2] + vz * 0.5,
+    ];
+
+    let mut header = String::from("NRRD0004\n");
+    // Writing to a String never fails.
+    _ = writeln!(header, "# Signed distance field baked by distill");
+    _ = writeln!(header, "type: float");
+    _ = writeln!(header, "dimension: 3");
+    _ = writeln!(header, "space dimension: 3");
+    _ = writeln!(header, "sizes: {nx} {ny} {nz}");
+    _ = writeln!(header, "space directions: ({vx},0,0) (0,{vy},0) (0,0,{vz})");
+    _ = writeln!(
+        header,
+        "space origin: ({},{},{})",
+        origin[0], origin[1], origin[2]
+    );
+    _ = writeln!(header, "kinds: domain domain domain");
+    _ = writeln!(header, "endian: little");
+    _ = writeln!(header, "encoding: raw");
+    _ = writeln!(
+        header,
+        "{KEY_PREFIX}bounds_min:={} {} {}",
+        bounds_min[0], bounds_min[1], bounds_min[2]
+    );
+    _ = writeln!(
+        header,
+        "{KEY_PREFIX}bounds_max:={} {} {}",
+        bounds_max[0], bounds_max[1], bounds_max[2]
+    );
+    _ = writeln!(header, "{KEY_PREFIX}padding_ratio:={padding_ratio}");
+    _ = writeln!(header, "{KEY_PREFIX}sign_convention:={sign_convention}");
+    header.push('\n');
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut bytes = header.into_bytes();
+    bytes.extend(volume.voxels.iter().flat_map(|v| v.to_le_bytes()));
+    fs::write(path, bytes)
+}
+
+/// Reads NRRDs as written by [`write`]. Files from other tools are accepted
+/// if they hold a raw little-endian `float` 3D grid; missing bounds are then
+/// derived from the space directions and the space origin, which defaults to
+/// the origin.
+pub(super) fn read(path: &Path) -> io::Result<VolumeFile> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+
+    let mut magic = String::new();
+    reader.read_line(&mut magic)?;
+    if !magic.starts_with("NRRD") {
+        return Err(invalid_data("missing NRRD magic"));
+    }
+
+    let mut fields = HashMap::new();
+    let mut key_values = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("unexpected end of NRRD header"));
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once(":=") {
+            key_values.insert(key.to_string(), value.to_string());
+        } else if let Some((field, value)) = line.split_once(": ") {
+            fields.insert(field.to_string(), value.trim().to_string());
+        }
+    }
+
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| invalid_data(format!("missing NRRD field \"{name}\"")))
+    };
+
+    if !matches!(field("type")?, "float" | "float32") {
+        return Err(invalid_data(format!(
+            "unsupported NRRD type {}",
+            field("type")?
+        )));
+    }
+    if field("encoding")? != "raw" {
+        return Err(invalid_data(format!(
+            "unsupported NRRD encoding {}",
+            field("encoding")?
+        )));
+    }
+    if fields
+        .get("endian")
+        .is_some_and(|endian| endian != "little")
+    {
+        return Err(invalid_data("only little-endian NRRDs are supported"));
+    }
+
+    let sizes = parse_floats(field("sizes")?)?;
+    let [nx, ny, nz] = as_vec3(&sizes)?.map(|s| s as u32);
+
+    let directions = field("space directions")?
+        .split_whitespace()
+        .map(parse_tuple)
+        .collect::<io::Result<Vec<_>>>()?;
+    let [dx, dy, dz] = directions
+        .try_into()
+        .map_err(|_| invalid_data("expected three space directions"))?;
+    let voxel_size = [dx[0], dy[1], dz[2]];
+    if [dx[1], dx[2], dy[0], dy[2], dz[0], dz[1]]
+        .iter()
+        .any(|v| *v != 0.0)
+    {
+        return Err(invalid_data("only axis-aligned NRRD grids are supported"));
+    }
+
+    let origin = fields
+        .get("space origin")
+        .map(String::as_str)
+        .map(parse_tuple)
+        .transpose()?
+        .unwrap_or_default();
+    let dimensions = [nx, ny, nz];
+    let derived_min: [f32; 3] = std::array::from_fn(|i| origin[i] - voxel_size[i] * 0.5);
+    let derived_max: [f32; 3] =
+        std::array::from_fn(|i| derived_min[i] + voxel_size[i] * dimensions[i] as f32);
+
+    let key_value = |key: &str| key_values.get(&format!("{KEY_PREFIX}{key}"));
+    let bounds_min = key_value("bounds_min")
+        .map(|v| parse_floats(v).and_then(|v| as_vec3(&v)))
+        .transpose()?
+        .unwrap_or(derived_min);
+    let bounds_max = key_value("bounds_max")
+        .map(|v| parse_floats(v).and_then(|v| as_vec3(&v)))
+        .transpose()?
+        .unwrap_or(derived_max);
+    let padding_ratio = key_value("padding_ratio")
+        .map(|v| v.parse::<f32>().map_err(invalid_data_from))
+        .transpose()?
+        .unwrap_or(0.0);
+    let sign_convention = key_value("sign_convention")
+        .map(|v| v.parse::<SignConvention>().map_err(invalid_data_from))
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let voxels = bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+
+    Ok(VolumeFile {
+        header: VolumeHeader {
+            dimensions,
+            bounds_min,
+            bounds_max,
+            padding_ratio,
+            voxel_size,
+            sign_convention,
+        },
+        voxels,
+    })
+}
+
+fn parse_floats(value: &str) -> io::Result<Vec<f32>> {
+    value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<f32>().map_err(invalid_data_from))
+        .collect()
+}
+
+/// Parses a `(x,y,z)` vector.
+fn parse_tuple(value: &str) -> io::Result<[f32; 3]> {
+    let inner = value
+        .trim()
+        .strip_prefix('(')
+        .and_then(|v| v.strip_suffix(')'))
+        .ok_or_else(|| invalid_data(format!("malformed NRRD vector {value}")))?;
+    as_vec3(&parse_floats(inner)?)
+}
+
+fn as_vec3(values: &[f32]) -> io::Result<[f32; 3]> {
+    values
+        .try_into()
+        .map_err(|_| invalid_data(format!("expected 3 components, found {}", values.len())))
+}
+
+fn invalid_data_from(error: impl std::fmt::Display) -> io::Error {
+    invalid_data(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x3x2 grid of both signs with bounds off the origin.
+    fn volume(sign_convention: SignConvention) -> VolumeFile {
+        VolumeFile {
+            header: VolumeHeader {
+                dimensions: [4, 3, 2],
+                bounds_min: [-1.0, 0.5, 2.0],
+                bounds_max: [1.0, 2.0, 3.0],
+                padding_ratio: 0.1,
+                voxel_size: [0.5, 0.5, 0.5],
+                sign_convention,
+            },
+            voxels: (0..24).map(|i| i as f32 * 0.25 - 2.0).collect(),
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("distill-nrrd-{}-{name}.nrrd", std::process::id()))
+    }
+
+    #[test]
+    fn round_trips_header_and_voxels() {
+        for sign_convention in [
+            SignConvention::NegativeInside,
+            SignConvention::PositiveInside,
+        ] {
+            let path = temp_path(&sign_convention.to_string());
+            let written = volume(sign_convention);
+            write(&path, &written).unwrap();
+            let read = read(&path).unwrap();
+            _ = fs::remove_file(&path);
+
+            assert_eq!(read.header, written.header);
+            assert_eq!(read.voxels, written.voxels);
+        }
+    }
+
+    #[test]
+    fn derives_bounds_of_foreign_files() {
+        let header = "NRRD0004\ntype: float\ndimension: 3\nsizes: 2 2 2\n\
+                      space directions: (0.5,0,0) (0,0.5,0) (0,0,0.5)\nencoding: raw\n\n";
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend((0..8).flat_map(|i| (i as f32).to_le_bytes()));
+        let path = temp_path("foreign");
+        fs::write(&path, bytes).unwrap();
+        let read = read(&path).unwrap();
+        _ = fs::remove_file(&path);
+
+        // Without a space origin the first voxel is centered on the origin.
+        assert_eq!(read.header.bounds_min, [-0.25; 3]);
+        assert_eq!(read.header.bounds_max, [0.75; 3]);
+        assert_eq!(read.header.voxel_size, [0.5; 3]);
+        assert_eq!(read.header.sign_convention, SignConvention::NegativeInside);
+        assert_eq!(read.voxels, (0..8).map(|i| i as f32).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,110 @@
+use super::{VolumeFile, VolumeHeader, invalid_data};
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+/// JSON sidecar of a raw volume. The voxels live in `data_file`, next to the
+/// header, as little-endian `f32` with x varying fastest.
+#[derive(Debug, Serialize, Deserialize)]
+struct RawHeader {
+    #[serde(flatten)]
+    volume: VolumeHeader,
+    data_file: String,
+    value_type: String,
+    endianness: String,
+}
+
+/// Writes `<stem>.raw` and its `<stem>.json` header, whichever of the two
+/// `path` names.
+pub(super) fn write(path: &Path, volume: &VolumeFile) -> io::Result<()> {
+    let raw_path = path.with_extension("raw");
+    let header = RawHeader {
+        volume: volume.header.clone(),
+        data_file: file_name(&raw_path)?,
+        value_type: "float32".to_string(),
+        endianness: "little".to_string(),
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let bytes = volume
+        .voxels
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect::<Vec<_>>();
+    fs::write(&raw_path, bytes)?;
+    fs::write(
+        path.with_extension("json"),
+        serde_json::to_string_pretty(&header)?,
+    )
+}
+
+/// Reads a volume from its `.json` header or its `.raw` data file.
+pub(super) fn read(path: &Path) -> io::Result<VolumeFile> {
+    let header_path = path.with_extension("json");
+    let header = serde_json::from_str::<RawHeader>(&fs::read_to_string(&header_path)?)?;
+
+    if header.value_type != "float32" || header.endianness != "little" {
+        return Err(invalid_data(format!(
+            "unsupported voxel encoding {} {}",
+            header.endianness, header.value_type
+        )));
+    }
+
+    let data_path = header_path.with_file_name(&header.data_file);
+    let voxels = fs::read(data_path)?
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+
+    Ok(VolumeFile {
+        header: header.volume,
+        voxels,
+    })
+}
+
+fn file_name(path: &Path) -> io::Result<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| invalid_data(format!("{path:?} has no file name")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxelization::volume_io::SignConvention;
+
+    #[test]
+    fn round_trips_header_and_voxels() {
+        for sign_convention in [
+            SignConvention::NegativeInside,
+            SignConvention::PositiveInside,
+        ] {
+            let written = VolumeFile {
+                header: VolumeHeader {
+                    dimensions: [4, 3, 2],
+                    bounds_min: [-1.0, 0.5, 2.0],
+                    bounds_max: [1.0, 2.0, 3.0],
+                    padding_ratio: 0.1,
+                    voxel_size: [0.5, 0.5, 0.5],
+                    sign_convention,
+                },
+                voxels: (0..24).map(|i| i as f32 * 0.25 - 2.0).collect(),
+            };
+            let path = std::env::temp_dir().join(format!(
+                "distill-raw-{}-{sign_convention}.json",
+                std::process::id()
+            ));
+            write(&path, &written).unwrap();
+            // Either file of the pair names the volume.
+            let read = read(&path.with_extension("raw")).unwrap();
+            _ = fs::remove_file(path.with_extension("raw"));
+            _ = fs::remove_file(&path);
+
+            assert_eq!(read.header, written.header);
+            assert_eq!(read.voxels, written.voxels);
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use super::{SignConvention, VolumeFile};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MAGIC: i64 = 0x5644_4220;
+const FILE_VERSION: u32 = 224;
+const LIBRARY_VERSION: [u32; 2] = [10, 0];
+const GRID_NAME: &str = "surface";
+const GRID_TYPE: &str = "Tree_float_5_4_3";
+/// Node values are written uncompressed, without an active-mask encoding.
+const NO_MASK_AND_ALL_VALS: u8 = 6;
+
+const LEAF_LOG2: u32 = 3;
+const INTERNAL1_LOG2: u32 = 4;
+const INTERNAL2_LOG2: u32 = 5;
+/// log2 of the voxels spanned by a leaf and by each internal node level.
+const LEAF_TOTAL: u32 = LEAF_LOG2;
+const INTERNAL1_TOTAL: u32 = LEAF_TOTAL + INTERNAL1_LOG2;
+const INTERNAL2_TOTAL: u32 = INTERNAL1_TOTAL + INTERNAL2_LOG2;
+
+type Coord = [i32; 3];
+
+struct Leaf {
+    value_mask: Vec<u64>,
+    values: Vec<f32>,
+}
+
+#[derive(Default)]
+struct Internal1 {
+    leaves: BTreeMap<usize, Leaf>,
+}
+
+#[derive(Default)]
+struct Internal2 {
+    children: BTreeMap<usize, Internal1>,
+}
+
+/// The distance grid viewed as a narrow-band level set in VDB index space.
+/// Voxel `(x, y, z)` of the grid is VDB coordinate `(x, y, z)`.
+struct LevelSet<'a> {
+    dimensions: [usize; 3],
+    /// Distances, negative inside.
+    voxels: &'a [f32],
+    background: f32,
+}
+
+impl LevelSet<'_> {
+    /// Distance at the grid voxel closest to `coord`. Coordinates outside the
+    /// grid read as the exterior background.
+    fn value(&self, coord: Coord) -> f32 {
+        if coord.iter().any(|&c| c < 0)
+            || coord
+                .iter()
+                .zip(self.dimensions)
+                .any(|(&c, d)| c as usize >= d)
+        {
+            return self.background;
+        }
+
+        let [nx, ny, _] = self.dimensions;
+        let [x, y, z] = coord.map(|c| c as usize);
+        self.voxels[x + y * nx + z * nx * ny]
+    }
+
+    /// `±background` of an inactive region, signed by the voxel closest to
+    /// its center.
+    fn tile_value(&self, origin: Coord, log2_span: u32) -> f32 {
+        let half = 1 << log2_span >> 1;
+        let center = origin.map(|c| c + half);
+        let clamped =
+            std::array::from_fn(|i| center[i].clamp(0, self.dimensions[i].max(1) as i32 - 1));
+        self.background.copysign(self.value(clamped))
+    }
+
+    fn build(&self) -> BTreeMap<Coord, Internal2> {
+        let mut root = BTreeMap::<Coord, Internal2>::new();
+        let leaf_dim = 1 << LEAF_LOG2;
+
+        let [nx, ny, nz] = self.dimensions.map(|d| d.div_ceil(leaf_dim) as i32);
+        for lz in 0..nz {
+            for ly in 0..ny {
+                for lx in 0..nx {
+                    let origin = [lx, ly, lz].map(|l| l * leaf_dim as i32);
+                    let Some(leaf) = self.leaf(origin) else {
+                        continue;
+                    };
+
+                    let root_key = origin.map(|c| c >> INTERNAL2_TOTAL << INTERNAL2_TOTAL);
+                    root.entry(root_key)
+                        .or_default()
+                        .children
+                        .entry(child_offset(origin, INTERNAL1_TOTAL, INTERNAL2_LOG2))
+                        .or_default()
+                        .leaves
+                        .insert(child_offset(origin, LEAF_TOTAL, INTERNAL1_LOG2), leaf);
+                }
+            }
+        }
+
+        root
+    }
+
+    /// The leaf at `origin`, if any of its voxels lies inside the band.
+    fn leaf(&self, origin: Coord) -> Option<Leaf> {
+        let dim = 1 << LEAF_LOG2;
+        let mut leaf = Leaf {
+            value_mask: vec![0; node_size(LEAF_LOG2) / 64],
+            values: vec![0.0; node_size(LEAF_LOG2)],
+        };
+
+        for x in 0..dim {
+            for y in 0..dim {
+                for z in 0..dim {
+                    let offset = ((x << (2 * LEAF_LOG2)) | (y << LEAF_LOG2) | z) as usize;
+                    let value = self.value([origin[0] + x, origin[1] + y, origin[2] + z]);
+                    if value.abs() < self.background {
+                        leaf.value_mask[offset / 64] |= 1 << (offset % 64);
+                        leaf.values[offset] = value;
+                    } else {
+                        leaf.values[offset] = self.background.copysign(value);
+                    }
+                }
+            }
+        }
+
+        leaf.value_mask
+            .iter()
+            .any(|&word| word != 0)
+            .then_some(leaf)
+    }
+
+    fn active_bounds(&self) -> Option<(Coord, Coord)> {
+        let [nx, ny, _] = self.dimensions;
+        let mut bounds: Option<(Coord, Coord)> = None;
+
+        for (i, value) in self.voxels.iter().enumerate() {
+            if value.abs() >= self.background {
+                continue;
+            }
+            let coord = [i % nx, (i / nx) % ny, i / (nx * ny)].map(|c| c as i32);
+            let (min, max) = bounds.get_or_insert((coord, coord));
+            *min = std::array::from_fn(|a| min[a].min(coord[a]));
+            *max = std::array::from_fn(|a| max[a].max(coord[a]));
+        }
+
+        bounds
+    }
+}
+
+fn node_size(log2_dim: u32) -> usize {
+    1 << (3 * log2_dim)
+}
+
+/// Offset of the child containing `coord` in a node with `2^log2_dim` children
+/// per axis, each spanning `2^child_total` voxels.
+fn child_offset(coord: Coord, child_total: u32, log2_dim: u32) -> usize {
+    let [x, y, z] = coord.map(|c| ((c >> child_total) & ((1 << log2_dim) - 1)) as usize);
+    (x << (2 * log2_dim)) | (y << log2_dim) | z
+}
+
+fn child_origin(parent_origin: Coord, offset: usize, child_total: u32, log2_dim: u32) -> Coord {
+    let mask = (1 << log2_dim) - 1;
+    let local = [
+        offset >> (2 * log2_dim),
+        (offset >> log2_dim) & mask,
+        offset & mask,
+    ];
+    std::array::from_fn(|i| parent_origin[i] + ((local[i] as i32) << child_total))
+}
+
+/// Little-endian writer for the OpenVDB stream primitives.
+#[derive(Default)]
+struct VdbWriter {
+    bytes: Vec<u8>,
+}
+
+impl VdbWriter {
+    fn put(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.put(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.put(&value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.put(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.put(&value.to_le_bytes());
+    }
+
+    fn vec3d(&mut self, value: [f64; 3]) {
+        value.iter().for_each(|v| self.put(&v.to_le_bytes()));
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.put(value.as_bytes());
+    }
+
+    fn mask(&mut self, words: &[u64]) {
+        words.iter().for_each(|word| self.put(&word.to_le_bytes()));
+    }
+
+    fn values(&mut self, values: &[f32]) {
+        self.put(&[NO_MASK_AND_ALL_VALS]);
+        values.iter().for_each(|&v| self.f32(v));
+    }
+
+    /// A typed metadata entry: name, type name, byte size and value.
+    fn metadata(&mut self, name: &str, type_name: &str, value: &[u8]) {
+        self.string(name);
+        self.string(type_name);
+        self.u32(value.len() as u32);
+        self.put(value);
+    }
+}
+
+/// Writes the grid as a single float level-set grid. Voxels closer to the
+/// surface than `half_width` voxels are active, every other value is clamped
+/// to the `±half_width * voxel size` background.
+pub(super) fn write(path: &Path, volume: &VolumeFile, half_width: f32) -> io::Result<()> {
+    let header = &volume.header;
+    let voxel_size = header.voxel_size;
+    let background = half_width * voxel_size.iter().copied().fold(0.0, f32::max);
+
+    let voxels = match header.sign_convention {
+        SignConvention::NegativeInside => volume.voxels.clone(),
+        SignConvention::PositiveInside => volume.voxels.iter().map(|v| -v).collect(),
+    };
+    let level_set = LevelSet {
+        dimensions: header.dimensions.map(|d| d as usize),
+        voxels: &voxels,
+        background,
+    };
+    let root = level_set.build();
+
+    let mut out = VdbWriter::default();
+    out.i64(MAGIC);
+    out.u32(FILE_VERSION);
+    LIBRARY_VERSION.iter().for_each(|&v| out.u32(v));
+    // Grid offsets are present
+    out.put(&[1]);
+    out.put(uuid().as_bytes());
+
+    // File metadata
+    out.u32(1);
+    out.metadata("creator", "string", b"distill");
+
+    out.i32(1);
+    out.string(GRID_NAME);
+    out.string(GRID_TYPE);
+    // Not an instance of another grid
+    out.string("");
+
+    let stream_pos = out.bytes.len();
+    // Grid, block and end positions, patched below
+    (0..3).for_each(|_| out.i64(0));
+    let grid_pos = out.bytes.len();
+
+    // No compression
+    out.u32(0);
+
+    let (bbox_min, bbox_max) = level_set.active_bounds().unwrap_or_default();
+    let active_count = voxels.iter().filter(|v| v.abs() < background).count();
+    out.u32(5);
+    out.metadata("class", "string", b"level set");
+    out.metadata("file_bbox_max", "vec3i", &coord_bytes(bbox_max));
+    out.metadata("file_bbox_min", "vec3i", &coord_bytes(bbox_min));
+    out.metadata(
+        "file_voxel_count",
+        "int64",
+        &(active_count as i64).to_le_bytes(),
+    );
+    out.metadata("name", "string", GRID_NAME.as_bytes());
+
+    // Index (0, 0, 0) is the center of the first voxel.
+    let voxel_size = voxel_size.map(f64::from);
+    let translation =
+        std::array::from_fn(|i| f64::from(header.bounds_min[i]) + voxel_size[i] * 0.5);
+    let uniform = voxel_size.iter().all(|&v| v == voxel_size[0]);
+    out.string(if uniform {
+        "UniformScaleTranslateMap"
+    } else {
+        "ScaleTranslateMap"
+    });
+    out.vec3d(translation);
+    out.vec3d(voxel_size);
+    out.vec3d(voxel_size);
+    out.vec3d(voxel_size.map(|v| 1.0 / v));
+    out.vec3d(voxel_size.map(|v| 1.0 / (v * v)));
+    out.vec3d(voxel_size.map(|v| 0.5 / v));
+
+    // Topology: one buffer per leaf, then the root node.
+    out.i32(1);
+    out.f32(background);
+    out.u32(0);
+    out.u32(root.len() as u32);
+    for (&origin, internal2) in &root {
+        origin.iter().for_each(|&c| out.i32(c));
+        write_internal2_topology(&mut out, &level_set, origin, internal2);
+    }
+
+    let block_pos = out.bytes.len();
+    for leaf in root
+        .values()
+        .flat_map(|internal2| internal2.children.values())
+        .flat_map(|internal1| internal1.leaves.values())
+    {
+        out.mask(&leaf.value_mask);
+        out.values(&leaf.values);
+    }
+    let end_pos = out.bytes.len();
+
+    for (i, pos) in [grid_pos, block_pos, end_pos].into_iter().enumerate() {
+        let at = stream_pos + i * 8;
+        out.bytes[at..at + 8].copy_from_slice(&(pos as i64).to_le_bytes());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, out.bytes)
+}
+
+fn write_internal2_topology(
+    out: &mut VdbWriter,
+    level_set: &LevelSet,
+    origin: Coord,
+    node: &Internal2,
+) {
+    let offsets = node.children.keys().copied();
+    write_internal_header(
+        out,
+        level_set,
+        origin,
+        offsets,
+        INTERNAL2_LOG2,
+        INTERNAL1_TOTAL,
+    );
+
+    for (&offset, child) in &node.children {
+        let child_origin = child_origin(origin, offset, INTERNAL1_TOTAL, INTERNAL2_LOG2);
+        let offsets = child.leaves.keys().copied();
+        write_internal_header(
+            out,
+            level_set,
+            child_origin,
+            offsets,
+            INTERNAL1_LOG2,
+            LEAF_TOTAL,
+        );
+
+        // Leaf topology is its value mask alone.
+        for leaf in child.leaves.values() {
+            out.mask(&leaf.value_mask);
+        }
+    }
+}
+
+/// Child mask, an empty value mask and the tile values of an internal node.
+fn write_internal_header(
+    out: &mut VdbWriter,
+    level_set: &LevelSet,
+    origin: Coord,
+    child_offsets: impl Iterator<Item = usize>,
+    log2_dim: u32,
+    child_total: u32,
+) {
+    let size = node_size(log2_dim);
+    let mut child_mask = vec![0u64; size / 64];
+    for offset in child_offsets {
+        child_mask[offset / 64] |= 1 << (offset % 64);
+    }
+
+    let values = (0..size)
+        .map(|offset| {
+            if child_mask[offset / 64] & (1 << (offset % 64)) != 0 {
+                0.0
+            } else {
+                let tile_origin = child_origin(origin, offset, child_total, log2_dim);
+                level_set.tile_value(tile_origin, child_total)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    out.mask(&child_mask);
+    out.mask(&vec![0; size / 64]);
+    out.values(&values);
+}
+
+fn coord_bytes(coord: Coord) -> Vec<u8> {
+    coord.iter().flat_map(|c| c.to_le_bytes()).collect()
+}
+
+/// A version 4 style UUID string. Only used to tell files apart, so the clock
+/// is random enough.
+fn uuid() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let bits = nanos ^ (nanos << 64) ^ 0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c834;
+    format!(
+        "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+        (bits >> 96) as u32,
+        (bits >> 80) as u16,
+        (bits >> 64) as u16 & 0x0fff,
+        ((bits >> 48) as u16 & 0x3fff) | 0x8000,
+        bits as u64 & 0xffff_ffff_ffff,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxelization::volume_io::VolumeHeader;
+    use bevy::math::{UVec3, Vec3};
+
+    const SIZE: u32 = 24;
+    const RADIUS: f32 = 5.0;
+
+    /// Sequential little-endian reader over a written file.
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, len: usize) -> &'a [u8] {
+            self.pos += len;
+            &self.bytes[self.pos - len..self.pos]
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn i64(&mut self) -> i64 {
+            i64::from_le_bytes(self.take(8).try_into().unwrap())
+        }
+
+        fn string(&mut self) -> String {
+            let len = self.u32() as usize;
+            String::from_utf8(self.take(len).to_vec()).unwrap()
+        }
+
+        /// Name, type name and value of a metadata entry.
+        fn metadata(&mut self) -> (String, String, Vec<u8>) {
+            let (name, type_name) = (self.string(), self.string());
+            let len = self.u32() as usize;
+            (name, type_name, self.take(len).to_vec())
+        }
+    }
+
+    /// Sphere of `RADIUS` voxels centered in a `SIZE`³ grid of unit voxels.
+    fn sphere() -> VolumeFile {
+        let center = Vec3::splat(SIZE as f32 * 0.5);
+        let voxels = (0..SIZE.pow(3))
+            .map(|i| UVec3::new(i % SIZE, (i / SIZE) % SIZE, i / (SIZE * SIZE)))
+            .map(|voxel| (voxel.as_vec3() + 0.5).distance(center) - RADIUS)
+            .collect();
+        VolumeFile {
+            header: VolumeHeader {
+                dimensions: [SIZE; 3],
+                bounds_min: [0.0; 3],
+                bounds_max: [SIZE as f32; 3],
+                padding_ratio: 0.0,
+                voxel_size: [1.0; 3],
+                sign_convention: SignConvention::NegativeInside,
+            },
+            voxels,
+        }
+    }
+
+    fn vec3i(bytes: &[u8]) -> Coord {
+        std::array::from_fn(|i| i32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()))
+    }
+
+    #[test]
+    fn writes_header_grid_descriptor_and_active_bounds() {
+        let volume = sphere();
+        let path = std::env::temp_dir().join(format!("distill-vdb-{}.vdb", std::process::id()));
+        write(&path, &volume, 3.0).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        _ = fs::remove_file(&path);
+        let mut reader = Reader {
+            bytes: &bytes,
+            pos: 0,
+        };
+
+        // File header
+        assert_eq!(reader.i64(), MAGIC);
+        assert_eq!(reader.u32(), FILE_VERSION);
+        assert_eq!([reader.u32(), reader.u32()], LIBRARY_VERSION);
+        assert_eq!(reader.take(1), [1]);
+        let uuid = String::from_utf8(reader.take(36).to_vec()).unwrap();
+        assert_eq!(
+            uuid.split('-').map(str::len).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+        assert_eq!(reader.u32(), 1);
+        assert_eq!(
+            reader.metadata(),
+            ("creator".into(), "string".into(), b"distill".to_vec())
+        );
+
+        // Grid descriptor
+        assert_eq!(reader.u32(), 1);
+        assert_eq!(reader.string(), GRID_NAME);
+        assert_eq!(reader.string(), GRID_TYPE);
+        assert_eq!(reader.string(), "");
+        let [grid_pos, block_pos, end_pos] = [reader.i64(), reader.i64(), reader.i64()];
+        assert_eq!(grid_pos as usize, reader.pos);
+        assert!(grid_pos < block_pos && block_pos < end_pos);
+        assert_eq!(end_pos as usize, bytes.len());
+
+        // Grid metadata
+        assert_eq!(reader.u32(), 0, "compression");
+        assert_eq!(reader.u32(), 5);
+        let metadata = (0..5)
+            .map(|_| {
+                let (name, _, value) = reader.metadata();
+                (name, value)
+            })
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(metadata["class"], b"level set");
+        assert_eq!(metadata["name"], GRID_NAME.as_bytes());
+
+        // Voxels within 3 of the surface are active.
+        let active = volume.voxels.iter().filter(|d| d.abs() < 3.0).count();
+        let voxel_count = i64::from_le_bytes(metadata["file_voxel_count"][..].try_into().unwrap());
+        assert_eq!(voxel_count as usize, active);
+        assert_eq!(vec3i(&metadata["file_bbox_min"]), [4; 3]);
+        assert_eq!(vec3i(&metadata["file_bbox_max"]), [19; 3]);
+    }
+}
@@ -1,38 +1,147 @@
 use bevy::{
     asset::RenderAssetUsages,
-    image::{ImageFilterMode, ImageSampler, ImageSamplerDescriptor},
+    image::{ImageFilterMode, ImageSampler, ImageSamplerDescriptor, TextureFormatPixelInfo},
     prelude::*,
-    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        renderer::RenderDevice,
+    },
 };
-use bevy_app_compute::prelude::AppComputeWorker;
+use bevy_app_compute::prelude::{AppComputeWorker, ComputeWorker};
 use tracing::instrument;
 
 use crate::{
     bvh::BvhData,
     voxelization::{
-        SignedDistanceFieldData, VoxelizationData, VoxelizationState, VoxelizeTargetMarker,
-        voxelization_worker::{SIZE, VoxelVariables, VoxelizationWorker},
+        ClosestFeatureData, SdfVolume, SignMode, SignedDistanceFieldData, VoxelizationCompleted,
+        VoxelizationData, VoxelizationError, VoxelizationSettings, VoxelizationStarted,
+        VoxelizationState, VoxelizeTargetMarker,
+        occupancy::{OccupancyFormat, solid_fractions},
+        quality::SdfQualityReport,
+        storage_format::{SdfEncoding, SdfStorageFormat},
+        volume_io::LoadBakedVolume,
+        voxelization_worker::{
+            ClosestFeatureWorker, MAX_BVH_NODES, MAX_TRIANGLES, SIZE, SlabDepth, VoxelUniforms,
+            VoxelVariables, VoxelizationWorker, mip_chain_voxel_count, mip_level_count,
+        },
     },
 };
 
-#[allow(clippy::type_complexity)]
+/// Z-slabs of the bake the workers are running.
+#[derive(Debug, Default, Resource)]
+pub(super) struct BakeSlabs {
+    /// Target the workers run for. Runs whose target was re-baked or failed
+    /// meanwhile are read back and dropped.
+    entity: Option<Entity>,
+    /// Z range of the slab dispatched last.
+    start: u32,
+    end: u32,
+    /// Whether the slab still has to be read back from the voxelization and
+    /// the feature worker.
+    pending_voxels: bool,
+    pending_features: bool,
+    /// Base level of the slabs read back so far.
+    voxels: Vec<u8>,
+    /// Mip chain, read back with the last slab.
+    mips: Vec<u8>,
+    /// Closest-feature volumes of the slabs read back so far, if the bake
+    /// requests them.
+    features: Option<FeatureBytes>,
+}
+
+impl BakeSlabs {
+    /// Moves on to the slab after the one read back last.
+    fn advance(&mut self, depth: u32, grid_size: u32) {
+        self.start = self.end;
+        self.end = (self.start + depth).min(grid_size);
+        self.pending_voxels = true;
+        self.pending_features = self.features.is_some();
+    }
+
+    /// Whether a worker run has not been read back yet. The workers hold a
+    /// single run, so no bake may start before.
+    fn busy(&self) -> bool {
+        self.pending_voxels || self.pending_features
+    }
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 #[instrument(skip_all)]
 pub(super) fn queue_voxelization(
     mut commands: Commands,
-    mesh_data: Query<(Entity, &BvhData), (With<VoxelizeTargetMarker>, Without<VoxelizationData>)>,
+    mesh_data: Query<
+        (Entity, &BvhData, Option<&VoxelizationSettings>),
+        (
+            With<VoxelizeTargetMarker>,
+            Without<VoxelizationData>,
+            Without<LoadBakedVolume>,
+        ),
+    >,
+    voxelizations: Query<&VoxelizationData>,
     mut worker: ResMut<AppComputeWorker<VoxelizationWorker>>,
+    mut feature_worker: ResMut<AppComputeWorker<ClosestFeatureWorker>>,
+    mut slabs: ResMut<BakeSlabs>,
+    slab_depth: Res<SlabDepth>,
+    render_device: Res<RenderDevice>,
+    mut started: MessageWriter<VoxelizationStarted>,
 ) {
     if mesh_data.is_empty() {
         trace!("No meshes to voxelize.");
         return;
     }
 
+    // The worker holds a single mesh at a time.
+    if slabs.busy()
+        || voxelizations
+            .iter()
+            .any(|voxel_data| voxel_data.state == VoxelizationState::InProgress)
+    {
+        trace!("Voxelization already in progress.");
+        return;
+    }
+
     info!(
         mesh_count = mesh_data.iter().count(),
         "Starting voxelization queue."
     );
 
-    if let Some((entity, bvh_data)) = mesh_data.iter().next() {
+    for (entity, bvh_data, settings) in mesh_data.iter() {
+        let mut settings = settings.copied().unwrap_or_default();
+        if settings.resolution > SIZE {
+            warn!(
+                "Requested resolution {} for entity {entity:?} exceeds the worker capacity of {SIZE}. Clamping.",
+                settings.resolution
+            );
+        }
+        settings.resolution = settings.resolution.clamp(1, SIZE);
+        let features = render_device.features();
+        if !features.contains(settings.storage_format.required_features()) {
+            warn!(
+                "The render device does not support {} textures. Storing the field of entity {entity:?} as {} instead.",
+                settings.storage_format,
+                SdfStorageFormat::R16Float
+            );
+            settings.storage_format = SdfStorageFormat::R16Float;
+        }
+        if let Some(format) = settings.occupancy
+            && !features.contains(format.required_features())
+        {
+            warn!(
+                "The render device does not support {format} textures. Storing the occupancy of entity {entity:?} as {} instead.",
+                OccupancyFormat::R8Unorm
+            );
+            settings.occupancy = Some(OccupancyFormat::R8Unorm);
+        }
+        // The colour transfer needs the closest triangle of every voxel
+        settings.closest_features |= settings.albedo;
+
+        if let Some(error) = capacity_error(bvh_data) {
+            commands
+                .entity(entity)
+                .insert(VoxelizationData::failed(settings, error));
+            continue;
+        }
+
         info!(
             n_triangles = bvh_data.triangles.len(),
             n_bvh_nodes = bvh_data.nodes.len(),
@@ -41,71 +150,397 @@ pub(super) fn queue_voxelization(
 
         worker.write_slice(VoxelVariables::Triangles.as_ref(), &bvh_data.triangles);
         worker.write_slice(VoxelVariables::BvhNodes.as_ref(), &bvh_data.nodes);
-        info!("Starting voxelization for entity {entity:?}.");
 
-        commands.entity(entity).insert(VoxelizationData {
-            state: VoxelizationState::InProgress,
-            data: None,
-        });
+        let SlabDepth(depth) = *slab_depth;
+        let end = depth.min(settings.resolution);
+        *slabs = BakeSlabs {
+            entity: Some(entity),
+            start: 0,
+            end,
+            pending_voxels: true,
+            pending_features: settings.closest_features,
+            voxels: Vec::with_capacity((settings.resolution as usize).pow(3) * 4),
+            mips: Vec::new(),
+            features: settings
+                .closest_features
+                .then(|| FEATURE_BUFFERS.map(|(_, format)| (Vec::new(), format))),
+        };
+        let uniforms = VoxelUniforms::from(&settings).with_slab(0, end);
+        worker.write(VoxelVariables::VoxelUniforms.as_ref(), &uniforms);
+        if settings.closest_features {
+            feature_worker.write_slice(VoxelVariables::Triangles.as_ref(), &bvh_data.triangles);
+            feature_worker.write_slice(VoxelVariables::BvhNodes.as_ref(), &bvh_data.nodes);
+            feature_worker.write(VoxelVariables::VoxelUniforms.as_ref(), &uniforms);
+            feature_worker.execute();
+        }
+        info!(
+            resolution = settings.resolution,
+            padding_ratio = settings.padding_ratio,
+            slabs = settings.resolution.div_ceil(depth),
+            "Starting voxelization for entity {entity:?}."
+        );
+
+        commands
+            .entity(entity)
+            .insert(VoxelizationData::in_progress(settings, None));
+        started.write(VoxelizationStarted { entity, settings });
 
         worker.execute();
+        return;
     }
 }
 
+/// Why the mesh does not fit the worker buffers, if it does not.
+fn capacity_error(bvh_data: &BvhData) -> Option<VoxelizationError> {
+    [
+        (
+            VoxelVariables::Triangles,
+            bvh_data.triangles.len(),
+            MAX_TRIANGLES,
+        ),
+        (
+            VoxelVariables::BvhNodes,
+            bvh_data.nodes.len(),
+            MAX_BVH_NODES,
+        ),
+    ]
+    .into_iter()
+    .find(|&(_, required, capacity)| required > capacity)
+    .map(
+        |(buffer, required, capacity)| VoxelizationError::BufferOverflow {
+            buffer: buffer.to_string(),
+            required,
+            capacity,
+        },
+    )
+}
+
+/// Closest-feature buffers in [`ClosestFeatureData`] field order.
+const FEATURE_BUFFERS: [(VoxelVariables, TextureFormat); 4] = [
+    (VoxelVariables::ClosestPoints, TextureFormat::Rgba32Float),
+    (VoxelVariables::ClosestNormals, TextureFormat::Rgba32Float),
+    (VoxelVariables::ClosestTriangles, TextureFormat::R32Uint),
+    (
+        VoxelVariables::ClosestBarycentrics,
+        TextureFormat::Rgba32Float,
+    ),
+];
+
+type FeatureBytes = [(Vec<u8>, TextureFormat); 4];
+
+/// Appends the slab the voxelization worker voxelized last to `slabs`, and
+/// the mip chain after the last slab.
+fn read_voxel_slab(
+    worker: &AppComputeWorker<VoxelizationWorker>,
+    slabs: &mut BakeSlabs,
+    grid_size: u32,
+) -> Result<(), VoxelizationError> {
+    let float_size = std::mem::size_of::<f32>();
+    let voxel_count = (slabs.end - slabs.start) as usize * (grid_size as usize).pow(2);
+    let sdf = read_back(
+        worker,
+        VoxelVariables::SlabVoxels.as_ref(),
+        voxel_count * float_size,
+    )?;
+    slabs.voxels.extend_from_slice(&sdf);
+
+    if slabs.end >= grid_size {
+        let mip_voxel_count = mip_chain_voxel_count(grid_size, mip_level_count(grid_size));
+        slabs.mips = read_back(
+            worker,
+            VoxelVariables::VoxelMips.as_ref(),
+            mip_voxel_count * float_size,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Appends the closest features of the slab the feature worker voxelized
+/// last to `slabs`.
+fn read_feature_slab(
+    feature_worker: &AppComputeWorker<ClosestFeatureWorker>,
+    slabs: &mut BakeSlabs,
+    grid_size: u32,
+) -> Result<(), VoxelizationError> {
+    let voxel_count = (slabs.end - slabs.start) as usize * (grid_size as usize).pow(2);
+    let Some(features) = &mut slabs.features else {
+        return Ok(());
+    };
+    for ((variable, _), (bytes, format)) in FEATURE_BUFFERS.iter().zip(features) {
+        let texel_size = format.pixel_size().unwrap_or(4);
+        let slab = read_back(feature_worker, variable.as_ref(), voxel_count * texel_size)?;
+        bytes.extend_from_slice(&slab);
+    }
+
+    Ok(())
+}
+
+/// The first `len` bytes of a read-back buffer of `worker`.
+pub(super) fn read_back<W: ComputeWorker>(
+    worker: &AppComputeWorker<W>,
+    buffer: &str,
+    len: usize,
+) -> Result<Vec<u8>, VoxelizationError> {
+    let bytes = worker.read_raw(buffer);
+    bytes
+        .get(..len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| VoxelizationError::ReadbackSize {
+            buffer: buffer.to_owned(),
+            expected: len,
+            actual: bytes.len(),
+        })
+}
+
+#[allow(clippy::too_many_arguments)]
 #[instrument(skip_all)]
 pub(super) fn extract_voxelization_data(
+    mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
-    worker: ResMut<AppComputeWorker<VoxelizationWorker>>,
-    mut query: Query<(Entity, &mut VoxelizationData), With<VoxelizeTargetMarker>>,
+    mut worker: ResMut<AppComputeWorker<VoxelizationWorker>>,
+    mut feature_worker: ResMut<AppComputeWorker<ClosestFeatureWorker>>,
+    mut slabs: ResMut<BakeSlabs>,
+    slab_depth: Res<SlabDepth>,
+    mut query: Query<(&mut VoxelizationData, &BvhData), With<VoxelizeTargetMarker>>,
+    mut completed: MessageWriter<VoxelizationCompleted>,
 ) {
-    if !worker.ready() {
-        trace!("Worker is not ready!");
+    // Each worker's results are read the frame it finishes, the two may
+    // finish in different frames.
+    let voxels_done = worker.ready() && worker.is_changed();
+    let features_done = feature_worker.ready() && feature_worker.is_changed();
+    if !voxels_done && !features_done {
+        trace!("Workers are not ready!");
         return;
     }
+    let Some(entity) = slabs.entity else {
+        return;
+    };
+    let job = query
+        .get_mut(entity)
+        .ok()
+        .filter(|(voxel_data, _)| voxel_data.state == VoxelizationState::InProgress);
+    let Some((mut voxel_data, bvh_data)) = job else {
+        // The target was re-baked or failed while the workers ran.
+        debug!("Dropping voxelization results of stale job for entity {entity:?}.");
+        slabs.pending_voxels &= !voxels_done;
+        slabs.pending_features &= !features_done;
+        if !slabs.busy() {
+            *slabs = BakeSlabs::default();
+        }
+        return;
+    };
 
-    if !worker.is_changed() {
-        info!("Worker has not changed. Skipping read.");
+    let grid_size = voxel_data.settings.resolution;
+    if voxels_done && slabs.pending_voxels {
+        slabs.pending_voxels = false;
+        if let Err(error) = read_voxel_slab(&worker, &mut slabs, grid_size) {
+            voxel_data.fail(error);
+            return;
+        }
+    }
+    if features_done && slabs.pending_features {
+        slabs.pending_features = false;
+        if let Err(error) = read_feature_slab(&feature_worker, &mut slabs, grid_size) {
+            voxel_data.fail(error);
+            return;
+        }
+    }
+    if slabs.busy() {
         return;
     }
-    for (entity, mut voxel_data) in query.iter_mut() {
-        if voxel_data.state != VoxelizationState::InProgress {
-            continue;
+
+    if slabs.end < grid_size {
+        voxel_data.progress = slabs.end as f32 / grid_size as f32;
+        slabs.advance(slab_depth.0, grid_size);
+        debug!(
+            progress = voxel_data.progress,
+            "Voxelizing slab {}..{} of entity {entity:?}.", slabs.start, slabs.end
+        );
+        let uniforms = VoxelUniforms::from(&voxel_data.settings).with_slab(slabs.start, slabs.end);
+        worker.write(VoxelVariables::VoxelUniforms.as_ref(), &uniforms);
+        worker.execute();
+        if slabs.pending_features {
+            feature_worker.write(VoxelVariables::VoxelUniforms.as_ref(), &uniforms);
+            feature_worker.execute();
         }
+        return;
+    }
 
-        info!("Reading voxelization results for entity {entity:?}.");
+    info!("Reading voxelization results for entity {entity:?}.");
 
-        let sdf_buffer = worker
-            .read_raw(VoxelVariables::VoxelTexture.as_ref())
-            .to_vec();
+    let voxel_count = (grid_size as usize).pow(3);
 
-        let grid_size = SIZE;
-        let extent = Extent3d {
-            width: grid_size,
-            height: grid_size,
-            depth_or_array_layers: grid_size,
-        };
+    let mip_count = mip_level_count(grid_size);
+    let sdf_buffer = std::mem::take(&mut slabs.voxels);
+    let mip_buffer = std::mem::take(&mut slabs.mips);
+    let feature_bytes = slabs.features.take();
 
-        // Convert to GPU 3D texture
-        let mut image = Image::new(
-            extent,
-            TextureDimension::D3,
-            bytemuck::cast_slice(&sdf_buffer).to_vec(),
-            TextureFormat::R32Float, // one channel, 32-bit float
-            RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
-        );
+    let extent = Extent3d {
+        width: grid_size,
+        height: grid_size,
+        depth_or_array_layers: grid_size,
+    };
 
-        image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
-            mag_filter: ImageFilterMode::Linear,
-            min_filter: ImageFilterMode::Linear,
-            mipmap_filter: ImageFilterMode::Linear,
-            ..default()
-        });
+    // Base level followed by the conservative mip chain
+    let voxels: Vec<f32> = bytemuck::cast_slice(&sdf_buffer)
+        .iter()
+        .chain(bytemuck::cast_slice(&mip_buffer))
+        .copied()
+        .collect();
 
-        let handle = images.add(image);
+    let settings = &voxel_data.settings;
+    let volume = SdfVolume::padded(grid_size, bvh_data.nodes[0].aabb(), settings.padding_ratio);
+    let voxel_size = volume.voxel_size().max_element();
+    let encoding = SdfEncoding::new(settings.storage_format, settings.band_width * voxel_size);
+    let encoded = encoding.encode(&voxels);
 
-        voxel_data.state = VoxelizationState::Computed;
-        voxel_data.data = Some(SignedDistanceFieldData {
-            signed_distance_field: handle,
-        });
+    let quality = SdfQualityReport::compute(
+        &voxels[..voxel_count],
+        grid_size,
+        volume.voxel_size(),
+        &bvh_data.triangles,
+    );
+    info!(
+        min_distance = quality.min_distance,
+        max_distance = quality.max_distance,
+        sign_inconsistencies = quality.sign_inconsistencies,
+        eikonal_mean = quality.eikonal_mean,
+        eikonal_rms = quality.eikonal_rms,
+        eikonal_max = quality.eikonal_max,
+        zero_crossings = quality.zero_crossings,
+        surface_area = quality.surface_area,
+        crossing_ratio = quality.crossing_ratio,
+        "Quality of the SDF of entity {entity:?}."
+    );
+    if quality.sign_inconsistencies > 0 {
+        warn!(
+            "{} voxels of entity {entity:?} disagree in sign with all their neighbours. \
+             The mesh is likely open or self-intersecting.",
+            quality.sign_inconsistencies
+        );
     }
+    commands.entity(entity).insert(quality);
+
+    if settings.storage_format != SdfStorageFormat::R32Float {
+        let base_level = &voxels[..voxel_count];
+        let decoded = encoding.decode(&encoded[..voxel_count * encoding.format.bytes_per_voxel()]);
+        let error = encoding.quantization_error(base_level, &decoded);
+        info!(
+            format = %encoding.format,
+            band_width = encoding.distance_scale,
+            max_error = error.max,
+            rms_error = error.rms,
+            clamped_voxels = error.clamped,
+            "Quantised SDF of entity {entity:?}."
+        );
+    }
+
+    let occupancy = match settings.occupancy {
+        Some(_) if settings.sign_mode == SignMode::Unsigned => {
+            warn!("Unsigned field of entity {entity:?} has no inside. Skipping occupancy.");
+            None
+        }
+        Some(format) => {
+            let fractions = solid_fractions(&voxels[..voxel_count], grid_size, volume.voxel_size());
+            let solid = fractions.iter().map(|&f| f as f64).sum::<f64>();
+            info!(
+                format = %format,
+                solid_volume = solid * volume.voxel_size().element_product() as f64,
+                "Estimated occupancy of entity {entity:?}."
+            );
+            let mut image = Image::new(
+                extent,
+                TextureDimension::D3,
+                format.encode(&fractions),
+                format.texture_format(),
+                RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+            );
+            image.sampler = ImageSampler::linear();
+            Some(images.add(image))
+        }
+        None => None,
+    };
+
+    // Convert to GPU 3D texture
+    let image = sdf_image(grid_size, mip_count, &encoding, encoded);
+
+    let handle = images.add(image);
+
+    let closest_features = feature_bytes.map(|feature_bytes| {
+        let [points, normals, triangles, barycentrics] =
+            feature_bytes.map(|(bytes, format)| images.add(feature_image(extent, bytes, format)));
+
+        ClosestFeatureData {
+            closest_point_offset: points,
+            closest_normal: normals,
+            closest_triangle: triangles,
+            closest_barycentric: barycentrics,
+        }
+    });
+
+    voxel_data.data = Some(SignedDistanceFieldData {
+        signed_distance_field: handle,
+        volume,
+        mip_count,
+        encoding,
+        closest_features,
+        albedo: None,
+        occupancy,
+    });
+
+    let duration = voxel_data.complete();
+    info!(?duration, "Voxelization of entity {entity:?} completed.");
+    completed.write(VoxelizationCompleted {
+        entity,
+        settings: voxel_data.settings,
+        duration,
+    });
+}
+
+/// Creates the 3D texture holding an encoded distance field and its mip chain.
+pub(super) fn sdf_image(
+    grid_size: u32,
+    mip_count: u32,
+    encoding: &SdfEncoding,
+    encoded: Vec<u8>,
+) -> Image {
+    let extent = Extent3d {
+        width: grid_size,
+        height: grid_size,
+        depth_or_array_layers: grid_size,
+    };
+
+    let mut image = Image::new_uninit(
+        extent,
+        TextureDimension::D3,
+        encoding.format.texture_format(),
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    );
+    image.data = Some(encoded);
+    image.texture_descriptor.mip_level_count = mip_count;
+
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        mag_filter: ImageFilterMode::Linear,
+        min_filter: ImageFilterMode::Linear,
+        mipmap_filter: ImageFilterMode::Linear,
+        ..default()
+    });
+
+    image
+}
+
+/// Wraps one read-back closest-feature buffer in a 3D texture. Integer and
+/// 32-bit float formats are not filterable everywhere, so these use nearest
+/// sampling.
+fn feature_image(extent: Extent3d, data: Vec<u8>, format: TextureFormat) -> Image {
+    let mut image = Image::new(
+        extent,
+        TextureDimension::D3,
+        data,
+        format,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    );
+    image.sampler = ImageSampler::nearest();
+    image
 }
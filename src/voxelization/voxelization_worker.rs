@@ -5,15 +5,48 @@ use bevy_app_compute::prelude::{
 };
 use bytemuck::{Pod, Zeroable};
 
-use crate::gpu_types::{GpuBvhNode, GpuTriangle};
+use crate::{
+    gpu_types::{GpuBvhNode, GpuTriangle, GpuVec4},
+    voxelization::{SignMode, VoxelizationPolicy, VoxelizationSettings},
+};
 
 pub const SIZE: u32 = 128;
+/// Largest triangle and BVH node counts of a mesh the worker can hold.
+pub const MAX_TRIANGLES: usize = 8192;
+pub const MAX_BVH_NODES: usize = 8192;
+/// Number of levels (including the base level) in the SDF mip pyramid.
+pub const MAX_MIP_LEVELS: u32 = 5;
 const WORKGROUP_SIZE: u32 = 8;
 
+/// Number of mip levels generated for a grid of `size` voxels per axis.
+pub fn mip_level_count(size: u32) -> u32 {
+    (u32::BITS - size.leading_zeros()).clamp(1, MAX_MIP_LEVELS)
+}
+
+/// Voxels per axis of mip `level`, following the wgpu convention.
+pub fn mip_level_size(size: u32, level: u32) -> u32 {
+    (size >> level).max(1)
+}
+
+/// Total voxel count of mip levels `1..mip_count`, as packed in the mip buffer.
+pub fn mip_chain_voxel_count(size: u32, mip_count: u32) -> usize {
+    (1..mip_count)
+        .map(|level| (mip_level_size(size, level) as usize).pow(3))
+        .sum()
+}
+
 #[derive(Debug, strum::EnumString, strum::Display, strum::AsRefStr)]
 #[strum(serialize_all = "snake_case")]
 pub enum VoxelVariables {
     VoxelTexture,
+    /// Base level voxels of the slab dispatched last, read back after every
+    /// slab.
+    SlabVoxels,
+    VoxelMips,
+    ClosestPoints,
+    ClosestNormals,
+    ClosestTriangles,
+    ClosestBarycentrics,
     Triangles,
     BvhNodes,
     VoxelUniforms,
@@ -23,6 +56,87 @@ pub enum VoxelVariables {
 #[repr(C)]
 pub struct VoxelUniforms {
     size: u32,
+    padding_ratio: f32,
+    mip_count: u32,
+    /// Z range of the voxels written by a dispatch. The mip chain is built
+    /// by the dispatch of the last slab.
+    slab_start: u32,
+    slab_end: u32,
+    /// 0 for parity signs, 1 for unsigned and 2 for a thin shell
+    sign_mode: u32,
+    shell_thickness: f32,
+}
+
+impl VoxelUniforms {
+    pub fn with_slab(self, slab_start: u32, slab_end: u32) -> Self {
+        Self {
+            slab_start,
+            slab_end,
+            ..self
+        }
+    }
+}
+
+impl From<&VoxelizationSettings> for VoxelUniforms {
+    fn from(settings: &VoxelizationSettings) -> Self {
+        Self {
+            size: settings.resolution,
+            padding_ratio: settings.padding_ratio,
+            mip_count: mip_level_count(settings.resolution),
+            slab_start: 0,
+            slab_end: settings.resolution,
+            sign_mode: match settings.sign_mode {
+                SignMode::Parity => 0,
+                SignMode::Unsigned => 1,
+                SignMode::ThinShell { .. } => 2,
+            },
+            shell_thickness: match settings.sign_mode {
+                SignMode::ThinShell { thickness } => thickness,
+                _ => 0.0,
+            },
+        }
+    }
+}
+
+/// Depth of the Z-slabs a grid of `size` voxels per axis is voxelized in, so
+/// that one slab takes at most `workgroup_budget` workgroups. At least one
+/// layer of workgroups.
+pub fn slab_depth(size: u32, workgroup_budget: u32) -> u32 {
+    let layer_workgroups = size.div_ceil(WORKGROUP_SIZE).pow(2);
+    let layers = (workgroup_budget / layer_workgroups).max(1);
+    (layers * WORKGROUP_SIZE).min(size)
+}
+
+/// Depth of the Z-slab one run of the [`VoxelizationWorker`] and the
+/// [`ClosestFeatureWorker`] voxelizes, from
+/// [`VoxelizationPolicy::workgroups_per_frame`] when the workers are built.
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct SlabDepth(pub u32);
+
+impl SlabDepth {
+    fn from_world(world: &mut World) -> Self {
+        let policy = world
+            .get_resource::<VoxelizationPolicy>()
+            .cloned()
+            .unwrap_or_default();
+        let depth = Self(slab_depth(SIZE, policy.workgroups_per_frame));
+        world.insert_resource(depth);
+        depth
+    }
+
+    /// Workgroups of one slab of the largest grid.
+    fn workgroups(self) -> [u32; 3] {
+        let layer_workgroups = SIZE.div_ceil(WORKGROUP_SIZE);
+        [
+            layer_workgroups,
+            layer_workgroups,
+            self.0.div_ceil(WORKGROUP_SIZE),
+        ]
+    }
+
+    fn voxel_count(self) -> u64 {
+        (SIZE as u64).pow(2) * self.0 as u64
+    }
 }
 
 #[derive(Default, TypePath)]
@@ -34,28 +148,55 @@ impl ComputeShader for VoxelizationShader {
     }
 }
 
+#[derive(Default, TypePath)]
+pub struct ClosestFeatureShader;
+
+impl ComputeShader for ClosestFeatureShader {
+    fn shader() -> ShaderRef {
+        "shaders/closest_features.compute.wgsl".into()
+    }
+}
+
+#[derive(Default, TypePath)]
+pub struct SdfMipShader;
+
+impl ComputeShader for SdfMipShader {
+    fn shader() -> ShaderRef {
+        "shaders/sdf_mips.compute.wgsl".into()
+    }
+}
+
 #[derive(Resource)]
 pub struct VoxelizationWorker;
 
 impl ComputeWorker for VoxelizationWorker {
     fn build(world: &mut World) -> AppComputeWorker<Self> {
-        let workgroups = [SIZE.div_ceil(WORKGROUP_SIZE); 3];
-        info!(workgroups = ?workgroups);
+        // One slab per run, offset along Z by the shader.
+        let slab = SlabDepth::from_world(world);
+        let workgroups = slab.workgroups();
+        // One invocation per texel of the first mip level.
+        let mip_workgroups = [mip_level_size(SIZE, 1).div_ceil(WORKGROUP_SIZE); 3];
+        info!(workgroups = ?workgroups, mip_workgroups = ?mip_workgroups);
 
-        let voxel_uniforms = VoxelUniforms { size: SIZE };
+        let voxel_uniforms = VoxelUniforms::from(&VoxelizationSettings::default());
+        let voxel_count = (SIZE as u64).pow(3);
 
         AppComputeWorkerBuilder::new(world)
+            // The full base level stays on the GPU for the mip pass, only the
+            // slab just voxelized is read back.
+            .add_empty_rw_storage(VoxelVariables::VoxelTexture.as_ref(), voxel_count * 4)
+            .add_empty_staging(VoxelVariables::SlabVoxels.as_ref(), slab.voxel_count() * 4)
             .add_empty_staging(
-                VoxelVariables::VoxelTexture.as_ref(),
-                (SIZE as u64).pow(3) * 4,
+                VoxelVariables::VoxelMips.as_ref(),
+                mip_chain_voxel_count(SIZE, MAX_MIP_LEVELS).max(1) as u64 * 4,
             )
             .add_empty_rw_storage(
                 VoxelVariables::Triangles.as_ref(),
-                8192 * std::mem::size_of::<GpuTriangle>() as u64,
+                (MAX_TRIANGLES * std::mem::size_of::<GpuTriangle>()) as u64,
             )
             .add_empty_rw_storage(
                 VoxelVariables::BvhNodes.as_ref(),
-                8192 * std::mem::size_of::<GpuBvhNode>() as u64,
+                (MAX_BVH_NODES * std::mem::size_of::<GpuBvhNode>()) as u64,
             )
             .add_uniform(VoxelVariables::VoxelUniforms.as_ref(), &voxel_uniforms)
             .add_pass::<VoxelizationShader>(
@@ -65,6 +206,62 @@ impl ComputeWorker for VoxelizationWorker {
                     VoxelVariables::Triangles.as_ref(),
                     VoxelVariables::BvhNodes.as_ref(),
                     VoxelVariables::VoxelUniforms.as_ref(),
+                    VoxelVariables::SlabVoxels.as_ref(),
+                ],
+            )
+            .add_pass::<SdfMipShader>(
+                mip_workgroups,
+                &[
+                    VoxelVariables::VoxelTexture.as_ref(),
+                    VoxelVariables::VoxelMips.as_ref(),
+                    VoxelVariables::BvhNodes.as_ref(),
+                    VoxelVariables::VoxelUniforms.as_ref(),
+                ],
+            )
+            .one_shot()
+            .build()
+    }
+}
+
+/// Voxelizes the closest-feature volumes of the same slabs as the
+/// [`VoxelizationWorker`]. Only run for bakes that request them, so other
+/// bakes never copy the feature buffers back.
+#[derive(Resource)]
+pub struct ClosestFeatureWorker;
+
+impl ComputeWorker for ClosestFeatureWorker {
+    fn build(world: &mut World) -> AppComputeWorker<Self> {
+        let slab = SlabDepth::from_world(world);
+        let voxel_uniforms = VoxelUniforms::from(&VoxelizationSettings::default());
+        let feature_size = slab.voxel_count() * std::mem::size_of::<GpuVec4>() as u64;
+
+        AppComputeWorkerBuilder::new(world)
+            .add_uniform(VoxelVariables::VoxelUniforms.as_ref(), &voxel_uniforms)
+            .add_empty_rw_storage(
+                VoxelVariables::Triangles.as_ref(),
+                (MAX_TRIANGLES * std::mem::size_of::<GpuTriangle>()) as u64,
+            )
+            .add_empty_rw_storage(
+                VoxelVariables::BvhNodes.as_ref(),
+                (MAX_BVH_NODES * std::mem::size_of::<GpuBvhNode>()) as u64,
+            )
+            .add_empty_staging(VoxelVariables::ClosestPoints.as_ref(), feature_size)
+            .add_empty_staging(VoxelVariables::ClosestNormals.as_ref(), feature_size)
+            .add_empty_staging(
+                VoxelVariables::ClosestTriangles.as_ref(),
+                slab.voxel_count() * 4,
+            )
+            .add_empty_staging(VoxelVariables::ClosestBarycentrics.as_ref(), feature_size)
+            .add_pass::<ClosestFeatureShader>(
+                slab.workgroups(),
+                &[
+                    VoxelVariables::VoxelUniforms.as_ref(),
+                    VoxelVariables::Triangles.as_ref(),
+                    VoxelVariables::BvhNodes.as_ref(),
+                    VoxelVariables::ClosestPoints.as_ref(),
+                    VoxelVariables::ClosestNormals.as_ref(),
+                    VoxelVariables::ClosestTriangles.as_ref(),
+                    VoxelVariables::ClosestBarycentrics.as_ref(),
                 ],
             )
             .one_shot()
@@ -0,0 +1,48 @@
+//! Runs `distill-bake` on a real mesh from start to exit. Needs a GPU, so it
+//! only runs with `cargo test -- --ignored`.
+use distill::voxelization::volume_io::VolumeFile;
+use std::{
+    path::Path,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn bakes_cow_end_to_end() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = std::env::temp_dir().join(format!("distill-smoke-{}.nrrd", std::process::id()));
+
+    let mut bake = Command::new(env!("CARGO_BIN_EXE_distill-bake"))
+        .current_dir(root)
+        .arg(root.join("assets/models/cow.obj"))
+        .arg(&output)
+        .args(["--resolution", "32"])
+        .spawn()
+        .expect("failed to start distill-bake");
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = bake.try_wait().expect("failed to wait for distill-bake") {
+            break status;
+        }
+        if started.elapsed() > TIMEOUT {
+            _ = bake.kill();
+            panic!("distill-bake did not exit within {TIMEOUT:?}");
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert!(status.success(), "distill-bake exited with {status}");
+
+    let volume = VolumeFile::read(&output).expect("failed to read the baked volume");
+    _ = std::fs::remove_file(&output);
+    assert_eq!(volume.header.dimensions, [32; 3]);
+    assert!(volume.voxels.iter().any(|&d| d < 0.0), "no voxel is inside");
+    assert!(
+        volume.voxels.iter().any(|&d| d > 0.0),
+        "no voxel is outside"
+    );
+}
@@ -0,0 +1,86 @@
+//! Bakes the validation primitives and checks them against their analytic
+//! distances through `distill validate`. Needs a GPU, so it only runs with
+//! `cargo test -- --ignored`.
+use std::{
+    path::Path,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(600);
+const RESOLUTIONS: [u32; 2] = [32, 64];
+const PRIMITIVES: usize = 6;
+/// Largest RMS and max error in voxels a bake may show.
+const MAX_RMS_VOXELS: f32 = 0.5;
+const MAX_ERROR_VOXELS: f32 = 2.0;
+
+#[test]
+#[ignore = "needs a GPU adapter"]
+fn primitives_bake_within_error_thresholds() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let report =
+        std::env::temp_dir().join(format!("distill-validation-{}.csv", std::process::id()));
+
+    let resolutions = RESOLUTIONS.map(|r| r.to_string()).join(",");
+    let mut validate = Command::new(env!("CARGO_BIN_EXE_distill"))
+        .current_dir(root)
+        .arg("validate")
+        .args(["--resolutions", &resolutions])
+        .args(["--max-rms", &MAX_RMS_VOXELS.to_string()])
+        .arg("--report")
+        .arg(&report)
+        .spawn()
+        .expect("failed to start distill validate");
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = validate.try_wait().expect("failed to wait for distill") {
+            break status;
+        }
+        if started.elapsed() > TIMEOUT {
+            _ = validate.kill();
+            panic!("distill validate did not exit within {TIMEOUT:?}");
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert!(status.success(), "distill validate exited with {status}");
+
+    let csv = std::fs::read_to_string(&report).expect("failed to read the validation report");
+    _ = std::fs::remove_file(&report);
+    let mut lines = csv.lines();
+    let header = lines
+        .next()
+        .expect("report is empty")
+        .split(',')
+        .collect::<Vec<_>>();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|&column| column == name)
+            .unwrap_or_else(|| panic!("report has no {name} column"))
+    };
+    let (primitive, resolution) = (column("primitive"), column("resolution"));
+    let (max_error, rms_error) = (column("max_error_voxels"), column("rms_error_voxels"));
+    let sign_mismatches = column("sign_mismatches");
+
+    let rows = lines
+        .map(|line| line.split(',').collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(rows.len(), PRIMITIVES * RESOLUTIONS.len());
+    for row in rows {
+        let name = format!("{} at {}³", row[primitive], row[resolution]);
+        let value = |index: usize| row[index].parse::<f32>().unwrap();
+        assert!(
+            value(rms_error) <= MAX_RMS_VOXELS,
+            "{name}: rms error {}",
+            row[rms_error]
+        );
+        assert!(
+            value(max_error) <= MAX_ERROR_VOXELS,
+            "{name}: max error {}",
+            row[max_error]
+        );
+        assert_eq!(row[sign_mismatches], "0", "{name}: sign mismatches");
+    }
+}